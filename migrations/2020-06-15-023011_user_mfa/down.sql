@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE mfa_recovery_codes;
+DROP TABLE user_totp;
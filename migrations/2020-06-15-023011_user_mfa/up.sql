@@ -0,0 +1,23 @@
+-- Your SQL goes here
+CREATE TABLE user_totp (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- base32 TOTP secret, shared with the user's authenticator app
+    secret TEXT NOT NULL,
+    -- false until the user verifies their first code
+    enabled BOOLEAN NOT NULL DEFAULT false,
+    -- last accepted time step, so a code can't be used twice
+    last_used_step BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    enabled_at TIMESTAMP
+);
+
+CREATE TABLE mfa_recovery_codes (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- sha256 of the normalized recovery code
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX mfa_recovery_codes_user_id_idx ON mfa_recovery_codes(user_id);
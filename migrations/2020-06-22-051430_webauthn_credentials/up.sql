@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE webauthn_credentials (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- base64url credential ID chosen by the authenticator
+    credential_id TEXT NOT NULL UNIQUE,
+    -- COSE_Key encoded public key
+    public_key BYTEA NOT NULL,
+    -- signature counter, to detect cloned authenticators
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    -- user's label for the passkey, e.g. "iPhone"
+    name TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    last_used_at TIMESTAMP
+);
+
+CREATE INDEX webauthn_credentials_user_id_idx ON webauthn_credentials(user_id);
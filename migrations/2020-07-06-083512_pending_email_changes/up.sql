@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE pending_email_changes (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    old_email TEXT NOT NULL,
+    new_email TEXT NOT NULL,
+    -- sha256 of the token in the confirmation link sent to new_email
+    confirm_token_hash TEXT NOT NULL UNIQUE,
+    -- sha256 of the token in the revert link sent to old_email
+    revert_token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    -- the confirmation link stops working after this
+    expires_at TIMESTAMP NOT NULL,
+    confirmed_at TIMESTAMP,
+    reverted_at TIMESTAMP
+);
+
+CREATE INDEX pending_email_changes_user_id_idx ON pending_email_changes(user_id);
@@ -0,0 +1,34 @@
+-- Your SQL goes here
+-- Emails are identified case-insensitively, see models/email_address.rs.
+-- Accounts whose emails only differ by case or surrounding whitespace have to
+-- be merged or renamed by hand first, see scripts/email_collisions.sql.
+DO $$
+DECLARE
+    collision RECORD;
+    collisions INTEGER := 0;
+BEGIN
+    FOR collision IN
+        SELECT lower(trim(email)) AS normalized_email,
+               array_agg(id ORDER BY created_at) AS user_ids,
+               array_agg(email ORDER BY created_at) AS emails
+        FROM users
+        GROUP BY lower(trim(email))
+        HAVING count(*) > 1
+    LOOP
+        collisions := collisions + 1;
+        RAISE WARNING 'email collision: % users: % emails: %',
+            collision.normalized_email, collision.user_ids, collision.emails;
+    END LOOP;
+
+    IF collisions > 0 THEN
+        RAISE EXCEPTION '% emails are used by more than one account when ignoring case', collisions;
+    END IF;
+END $$;
+
+-- Trim, and lowercase the domain. The local part keeps its case.
+UPDATE users
+SET email = substring(trim(email) from '^(.*)@') || '@' || lower(substring(trim(email) from '@([^@]*)$'))
+WHERE trim(email) LIKE '%@%'
+AND email <> substring(trim(email) from '^(.*)@') || '@' || lower(substring(trim(email) from '@([^@]*)$'));
+
+CREATE UNIQUE INDEX users_email_lower_idx ON users (lower(email));
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- Audit trail of admins impersonating users.
+-- No foreign keys, so records outlive the accounts involved.
+CREATE TABLE impersonations (
+    id TEXT PRIMARY KEY,
+    -- the admin, named in the token's `act` claim
+    admin_id TEXT NOT NULL,
+    -- the impersonated user
+    user_id TEXT NOT NULL,
+    reason TEXT,
+    -- JWT ID of the issued token, to revoke it
+    jti TEXT NOT NULL UNIQUE,
+    ip TEXT,
+    user_agent TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX impersonations_admin_id_idx ON impersonations(admin_id);
+CREATE INDEX impersonations_user_id_idx ON impersonations(user_id);
@@ -0,0 +1,33 @@
+-- Your SQL goes here
+-- Security audit log, see src/bin/user/audit
+CREATE TABLE audit_events (
+    id TEXT PRIMARY KEY,
+    -- who did it: the user, an admin, or NULL if not logged in (e.g. failed logins)
+    actor_id TEXT,
+    -- the account it was done to
+    user_id TEXT,
+    action TEXT NOT NULL,
+    -- SUCCESS or FAILURE
+    outcome TEXT NOT NULL,
+    ip TEXT,
+    user_agent TEXT,
+    payload JSONB NOT NULL DEFAULT '{}',
+    -- clock_timestamp() so events in one transaction keep their order
+    created_at TIMESTAMP NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX audit_events_created_at_idx ON audit_events(created_at);
+CREATE INDEX audit_events_user_id_idx ON audit_events(user_id, created_at);
+CREATE INDEX audit_events_actor_id_idx ON audit_events(actor_id, created_at);
+CREATE INDEX audit_events_action_idx ON audit_events(action, created_at);
+
+-- Append only
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE PROCEDURE audit_events_append_only();
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- Suspension history, users.is_suspended is true while one is active
+CREATE TABLE suspensions (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- SuspensionReason code, shown to the user
+    reason TEXT NOT NULL,
+    -- for admins only
+    note TEXT,
+    admin_id TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- NULL until an admin lifts it
+    until TIMESTAMP,
+    lifted_at TIMESTAMP,
+    -- NULL if lifted automatically
+    lifted_by TEXT
+);
+
+CREATE INDEX suspensions_user_id_idx ON suspensions(user_id, created_at);
+CREATE INDEX suspensions_active_until_idx ON suspensions(until) WHERE lifted_at IS NULL;
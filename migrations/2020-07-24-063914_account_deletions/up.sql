@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Soft deleted accounts, restorable until erase_after, then erased.
+-- Rows are kept after erasure as the record of it.
+CREATE TABLE account_deletions (
+    id TEXT PRIMARY KEY,
+    -- no foreign key, so the record outlives the erased user
+    user_id TEXT NOT NULL,
+    -- restored on restore, cleared on erasure
+    original_email TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    erase_after TIMESTAMP NOT NULL,
+    restored_at TIMESTAMP,
+    -- the user, or the admin who restored it
+    restored_by TEXT,
+    erased_at TIMESTAMP
+);
+
+CREATE INDEX account_deletions_user_id_idx ON account_deletions(user_id, created_at);
+CREATE INDEX account_deletions_erase_after_idx ON account_deletions(erase_after)
+    WHERE restored_at IS NULL AND erased_at IS NULL;
+CREATE INDEX account_deletions_original_email_idx ON account_deletions(lower(original_email))
+    WHERE restored_at IS NULL AND erased_at IS NULL;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX account_deletions_original_email_identity_idx;
+ALTER TABLE account_deletions DROP COLUMN original_email_identity;
+CREATE INDEX account_deletions_original_email_idx ON account_deletions(lower(original_email))
+    WHERE restored_at IS NULL AND erased_at IS NULL;
+
+CREATE UNIQUE INDEX users_email_lower_idx ON users (lower(email));
+ALTER TABLE users DROP COLUMN email_identity;
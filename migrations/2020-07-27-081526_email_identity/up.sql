@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Emails are identified by models::email_identity, which Postgres can't compute:
+-- unicode domains have to be converted to punycode, and lower() doesn't always
+-- agree with Rust's to_lowercase() for non-ASCII letters.
+-- They're filled in by running `dt_user backfill-email-identities` once, which
+-- changes nothing if two accounts share an identity (list them with
+-- `dt_user email-collisions`), see db::users_raw::backfill_email_identities.
+ALTER TABLE users ADD COLUMN email_identity TEXT;
+-- NULL for deleted and erased accounts, which have placeholder emails
+CREATE UNIQUE INDEX users_email_identity_idx ON users (email_identity);
+DROP INDEX users_email_lower_idx;
+
+ALTER TABLE account_deletions ADD COLUMN original_email_identity TEXT;
+DROP INDEX account_deletions_original_email_idx;
+CREATE INDEX account_deletions_original_email_identity_idx ON account_deletions(original_email_identity)
+    WHERE restored_at IS NULL AND erased_at IS NULL;
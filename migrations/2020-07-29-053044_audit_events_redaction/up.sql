@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Audit events stay append only, except that erasing an account may redact
+-- the personal data in its events: the IP, user agent and payload emails.
+-- What happened, to whom and when can't be changed, and rows can't be deleted.
+CREATE OR REPLACE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE'
+        AND NEW.id = OLD.id
+        AND NEW.actor_id IS NOT DISTINCT FROM OLD.actor_id
+        AND NEW.user_id IS NOT DISTINCT FROM OLD.user_id
+        AND NEW.action = OLD.action
+        AND NEW.outcome = OLD.outcome
+        AND NEW.created_at = OLD.created_at
+        AND NEW.ip IS NULL
+        AND NEW.user_agent IS NULL
+        AND NEW.payload = OLD.payload - 'email' - 'oldEmail' - 'newEmail'
+    THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'audit_events is append only';
+END;
+$$ LANGUAGE plpgsql;
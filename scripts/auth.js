@@ -9,7 +9,7 @@ let res = await fetch("https://0.0.0.0:8082/login", {
   credentials: "same-origin",
   body: JSON.stringify({
       "email": "sirius@hogwarts.com",
-      "password": ""
+      "password": "turn to page three hundred and ninety-four"
   })
 });
 
@@ -0,0 +1,31 @@
+#### builds the breached password blocklist for PASSWORD_BLOCKLIST_FILE
+#### input is either plain passwords, one per line, or Pwned Passwords
+#### SHA-1 hashes ("HASH:COUNT" lines, as downloaded from haveibeenpwned.com)
+####     python3 scripts/build_password_blocklist.py pwned-passwords-sha1.txt blocklist.bin
+#### output is the sorted, unique first 8 bytes of each SHA-1, big-endian
+import hashlib
+import sys
+
+def sha1_prefixes(path, min_count):
+    with open(path, encoding="utf-8", errors="ignore") as f:
+        for line in f:
+            line = line.rstrip("\r\n")
+            head, _, count = line.partition(":")
+            is_sha1 = len(head) == 40 and all(c in "0123456789abcdefABCDEF" for c in head)
+            if is_sha1:
+                if count and int(count) < min_count:
+                    continue
+                yield bytes.fromhex(head)[:8]
+            elif line:
+                yield hashlib.sha1(line.encode("utf-8")).digest()[:8]
+
+if __name__ == "__main__":
+    if len(sys.argv) < 3:
+        print("usage: build_password_blocklist.py <input> <output> [min breach count]")
+        sys.exit(1)
+    min_count = int(sys.argv[3]) if len(sys.argv) > 3 else 1
+    prefixes = sorted(set(sha1_prefixes(sys.argv[1], min_count)))
+    with open(sys.argv[2], "wb") as out:
+        for prefix in prefixes:
+            out.write(prefix)
+    print("wrote {} passwords to {}".format(len(prefixes), sys.argv[2]))
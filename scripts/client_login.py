@@ -24,7 +24,7 @@ json.loads(res2.content)
 ### 3. Test Login
 res3 = requests.post(
     '{url}/login'.format(url=url),
-     json={"email": "severus@hogwarts.com", "password": ""}
+     json={"email": "severus@hogwarts.com", "password": "turn to page three hundred and ninety-four"}
 )
 json.loads(res3.content)
 res3.cookies.get('dt-auth')
@@ -68,7 +68,7 @@ json.loads(res6.content)
 res7 = requests.post('{url}/user/create'.format(url=url),
     json={
         "email": "severus@hogwarts.com",
-        "password": "",
+        "password": "turn to page three hundred and ninety-four",
         "username": "Halfblood Prince",
         "first_name": "Severus",
         "last_name": "Snape",
@@ -81,7 +81,7 @@ json.loads(res7.content)
 res8 = requests.post('{url}/user/create'.format(url=url),
     json={
         "email": "severus@hogwarts.com",
-        "password": "",
+        "password": "turn to page three hundred and ninety-four",
         "username": "severus",
         "first_name": "Severus",
         "last_name": "Snape",
@@ -99,7 +99,7 @@ json.loads(res9.content)
 
 res11 = requests.get(
     'http://0.0.0.0:8082/login',
-    json={"email": "severus@hogwarts.com", "password": ""}
+    json={"email": "severus@hogwarts.com", "password": "turn to page three hundred and ninety-four"}
 )
 res11.content
 
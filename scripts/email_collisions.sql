@@ -0,0 +1,15 @@
+-- Lists accounts whose emails only differ by case or surrounding whitespace.
+-- These have to be resolved before the users_email_lower_unique migration.
+-- It doesn't catch everything email_identity does (e.g. punycode domains),
+-- so for the email_identity migration use `dt_user email-collisions` instead.
+--     psql $DATABASE_URL -f scripts/email_collisions.sql
+SELECT lower(trim(email)) AS normalized_email,
+       count(*) AS accounts,
+       array_agg(id ORDER BY created_at) AS user_ids,
+       array_agg(email ORDER BY created_at) AS emails,
+       array_agg(email_verified ORDER BY created_at) AS emails_verified,
+       array_agg(created_at ORDER BY created_at) AS created_at
+FROM users
+GROUP BY lower(trim(email))
+HAVING count(*) > 1
+ORDER BY accounts DESC, normalized_email;
@@ -0,0 +1,134 @@
+//// External Imports
+use actix::{Addr, Handler, Message, SyncContext};
+use actix_web::HttpRequest;
+
+//// Internal Imports
+use crate::auth::{AuthInfo, SessionClient};
+use crate::db::{DatabaseActor, insertAuditEvent};
+use crate::models::{
+    AuditAction,
+    AuditOutcome,
+    NewAuditEvent,
+};
+use crate::AppState;
+
+/////////////////////////////////////////////
+/// Security Audit Log
+/////////////////////////////////////////////
+/// Security relevant actions (logins, password and email changes, admin
+/// actions...) are written to the `audit_events` table, which is append
+/// only: a trigger rejects updates and deletes.
+///
+/// Each event records who did it (the actor), who it was done to (the user),
+/// the action, whether it succeeded, the client's IP and user agent,
+/// and a json payload with anything else worth keeping:
+///
+///     audit(&req, AuditAction::Suspend)
+///         .by(&authInfo)
+///         .user(&user.id)
+///         .payload(json!({ "reason": reason }))
+///         .record();
+///
+/// Events are written in the background by the DatabaseActor, so a failure
+/// to write one is logged but doesn't fail the request.
+///
+/// Admins with "audit:read" can search all events at GET /auth/admin/audit,
+/// and users can see their own at GET /auth/audit.
+
+pub fn audit(req: &HttpRequest, action: AuditAction) -> AuditRecorder {
+    let client = SessionClient::from_request(req);
+    AuditRecorder {
+        database_actor: AppState::databaseActor(req).clone(),
+        event: NewAuditEvent {
+            id: format!("aud_{}", uuid::Uuid::new_v4()),
+            actor_id: None,
+            user_id: None,
+            action: action.as_str().to_string(),
+            outcome: AuditOutcome::Success.as_str().to_string(),
+            ip: client.ip,
+            user_agent: client.user_agent,
+            payload: json!({}),
+        },
+    }
+}
+
+pub struct AuditRecorder {
+    database_actor: Addr<DatabaseActor>,
+    event: NewAuditEvent,
+}
+
+impl AuditRecorder {
+    /// The logged in user did it to their own account.
+    /// When impersonating, the admin is the actor.
+    pub fn by(mut self, authInfo: &AuthInfo) -> Self {
+        self.event.actor_id = Some(
+            authInfo.impersonator_id.clone().unwrap_or(authInfo.user_id.clone())
+        );
+        self.event.user_id = Some(authInfo.user_id.clone());
+        self
+    }
+
+    /// Who it was done to, if not the actor's own account
+    pub fn user(mut self, user_id: &str) -> Self {
+        self.event.user_id = Some(user_id.to_string());
+        self
+    }
+
+    /// The actor, when they aren't logged in with a JWT,
+    /// e.g. a user logging in or resetting their password
+    pub fn actor(mut self, actor_id: &str) -> Self {
+        self.event.actor_id = Some(actor_id.to_string());
+        self
+    }
+
+    pub fn outcome(mut self, outcome: AuditOutcome) -> Self {
+        self.event.outcome = outcome.as_str().to_string();
+        self
+    }
+
+    pub fn failed(self) -> Self {
+        self.outcome(AuditOutcome::Failure)
+    }
+
+    pub fn payload(mut self, payload: serde_json::Value) -> Self {
+        self.event.payload = payload;
+        self
+    }
+
+    pub fn record(self) {
+        debug!("audit: {} {} user: {:?} actor: {:?}",
+            self.event.action, self.event.outcome,
+            self.event.user_id, self.event.actor_id);
+        self.database_actor.do_send(RecordAuditEvent(self.event));
+    }
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+#[derive(Clone, Debug)]
+pub struct RecordAuditEvent(pub NewAuditEvent);
+
+impl Message for RecordAuditEvent {
+    type Result = ();
+}
+
+impl Handler<RecordAuditEvent> for DatabaseActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: RecordAuditEvent,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let event = msg.0;
+        match self.pool.get() {
+            Err(e) => warn!("audit event {:?} not recorded: {}", event, e),
+            Ok(conn) => {
+                if let Err(e) = insertAuditEvent(&conn, event.clone()) {
+                    warn!("audit event {:?} not recorded: {}", event, e);
+                }
+            }
+        }
+    }
+}
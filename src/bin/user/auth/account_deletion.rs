@@ -0,0 +1,133 @@
+//// External Imports
+use actix::{Addr, Handler, Message, SyncContext};
+
+//// Internal Imports
+use crate::auth::RevokeSessions;
+use crate::db::{
+    DatabaseActor,
+    getAccountDeletionsDue,
+    eraseUser,
+    insertAuditEvent,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    AuditAction,
+    AuditOutcome,
+    NewAuditEvent,
+};
+
+/////////////////////////////////////////////
+/// Account Erasure
+/////////////////////////////////////////////
+/// Deleted accounts can be restored for ACCOUNT_DELETION_GRACE_DAYS
+/// (default 30), see `models::account_deletion`. After that they are erased
+/// by a background task, which checks every ACCOUNT_ERASE_INTERVAL_SECONDS
+/// (default 3600), see `eraseUser` for what is erased.
+///
+/// Each erasure sets `account_deletions.erased_at`, and is recorded
+/// in the audit log as "account_erase".
+/// Licenses aren't linked to users in this service, so aren't erased here.
+///
+/// Kept after erasure, under the anonymized user id:
+/// - audit events, with their IPs, user agents and emails redacted,
+///   as the security record of the account
+/// - suspensions, so abuse can still be shown if it's disputed or comes back.
+///   They have the reason and the admin's note, but no IP or email
+/// - impersonations, which record what admins did. Their IP and user agent
+///   are the admin's, not the user's
+
+const DEFAULT_ACCOUNT_ERASE_INTERVAL_SECONDS: u64 = 3600;
+
+fn account_erase_interval() -> std::time::Duration {
+    let seconds = std::env::var("ACCOUNT_ERASE_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(DEFAULT_ACCOUNT_ERASE_INTERVAL_SECONDS);
+    std::time::Duration::from_secs(seconds)
+}
+
+/// Runs for as long as the server, call from main
+pub fn spawn_account_eraser(database_actor: Addr<DatabaseActor>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(account_erase_interval());
+        loop {
+            interval.tick().await;
+            match database_actor.send(EraseDeletedAccounts).await {
+                Ok(Ok(0)) => {},
+                Ok(Ok(erased)) => info!("erased {} deleted accounts", erased),
+                Ok(Err(e)) => warn!("error erasing deleted accounts: {}", e),
+                Err(e) => warn!("error erasing deleted accounts: {}", e),
+            }
+        }
+    });
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+/// Erases every deleted account past its grace period, returns how many
+/// were erased. Ones which fail are logged and retried next run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EraseDeletedAccounts;
+
+impl Message for EraseDeletedAccounts {
+    type Result = Result<usize, LoginError>;
+}
+
+impl Handler<EraseDeletedAccounts> for DatabaseActor {
+    type Result = Result<usize, LoginError>;
+
+    fn handle(
+        &mut self,
+        _msg: EraseDeletedAccounts,
+        ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let conn = self.pool.get()
+            .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+        let due = getAccountDeletionsDue(&conn)?;
+        let mut erased = 0;
+        for deletion in &due {
+            match eraseUser(&conn, deletion) {
+                Ok(true) => {},
+                // Restored since it was loaded
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!("deleted account {} not erased: {}", deletion.user_id, e);
+                    continue
+                }
+            }
+            erased += 1;
+            debug!("erased deleted account: {}", deletion.user_id);
+
+            // Sessions were ended on deletion, this catches any left over
+            let revoke_sessions = RevokeSessions {
+                user_id: deletion.user_id.clone(),
+                keep: None,
+            };
+            if let Err(e) = Handler::<RevokeSessions>::handle(self, revoke_sessions, ctx) {
+                warn!("sessions not revoked for erased user {}: {}", deletion.user_id, e);
+            }
+            if let Err(e) = self.revoke_user_tokens(&deletion.user_id) {
+                warn!("tokens not revoked for erased user {}: {}", deletion.user_id, e);
+            }
+
+            let event = NewAuditEvent {
+                id: format!("aud_{}", uuid::Uuid::new_v4()),
+                actor_id: None,
+                user_id: Some(deletion.user_id.clone()),
+                action: AuditAction::AccountErase.as_str().to_string(),
+                outcome: AuditOutcome::Success.as_str().to_string(),
+                ip: None,
+                user_agent: None,
+                payload: json!({ "deletionId": deletion.id, "deletedAt": deletion.created_at }),
+            };
+            if let Err(e) = insertAuditEvent(&conn, event) {
+                warn!("audit event not recorded: {}", e);
+            }
+        }
+        Ok(erased)
+    }
+}
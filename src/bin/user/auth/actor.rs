@@ -18,36 +18,128 @@ use crate::db::DatabaseActor;
 /// to execute Redis Commands from other actors
 
 use actix_identity::{Identity};
+use chrono::Local;
+use crate::auth::{Claims, refresh_token_ttl, family_key};
 
+/// Redis keys for revoked tokens:
+///     revoked_jti:{jti}          => "REVOKED", expires with the token
+///     revoked_before:{user_id}   => unix timestamp in milliseconds. Every token
+///                                   for this user issued before it is revoked.
+/// Tokens are also revoked when their session ends, see `auth::session`.
+///
+/// The watermark is in milliseconds so tokens issued earlier in the same second
+/// as a revocation are revoked, while sessions started just after it (e.g. the
+/// new session after a password change) are not.
+pub fn revoked_jti_key(jti: &str) -> String {
+    format!("revoked_jti:{}", jti)
+}
+
+pub fn revoked_before_key(user_id: &str) -> String {
+    format!("revoked_before:{}", user_id)
+}
+
+/// Values written before timestamps were in milliseconds are in seconds,
+/// anything below this is taken as seconds (it's in 1973 as milliseconds).
+const MIN_UNIX_MILLIS: i64 = 100_000_000_000;
+
+/// Parses a stored watermark or session start time as unix milliseconds
+pub fn parse_unix_millis(t: &str) -> Option<i64> {
+    t.parse::<i64>().ok()
+        .map(|t| if t < MIN_UNIX_MILLIS { t * 1000 } else { t })
+}
+
+/// Whether something issued or started at `issued_ms` is revoked by the watermark
+pub fn revoked_by_watermark(issued_ms: i64, watermark_ms: Option<i64>) -> bool {
+    watermark_ms.map(|w| issued_ms < w).unwrap_or(false)
+}
+
+/// Checks a decoded JWT against the revocation lists
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CheckJwt(pub String);
+pub struct CheckJwt(pub Claims);
 
 impl Message for CheckJwt {
-    type Result = Result<String, CheckJwtError>;
+    type Result = Result<Claims, CheckJwtError>;
 }
 
 impl Handler<CheckJwt> for DatabaseActor {
-    type Result = Result<String, CheckJwtError>;
+    type Result = Result<Claims, CheckJwtError>;
 
     fn handle(
         &mut self,
         msg: CheckJwt,
         _ctx: &mut SyncContext<Self>
-    ) -> Result<String, CheckJwtError> {
+    ) -> Result<Claims, CheckJwtError> {
 
-        // Check if JWT exists in redis, if so
-        // Return a Jwt revoked error
+        // Check if the JWT ID is revoked, its session has ended, or the token
+        // was issued before the user's revocation watermark.
+        // If so return a Jwt revoked error
         let mut conn = self.redis_sync_client.clone().get_connection()?;
-        let res: RedisResult<String> = redis::cmd("GET").arg(msg.0.clone()).query(&mut conn);
-        match res {
-            // Not in blacklist => Ok
-            Err(_) => Ok(msg.0),
-            // In blacklist => Err
-            Ok(_) => Err(CheckJwtError::Revoked),
+        let (revoked, revoked_before, session): (Option<String>, Option<String>, Option<String>) = redis::cmd("MGET")
+            .arg(revoked_jti_key(&msg.0.jti))
+            .arg(revoked_before_key(&msg.0.sub))
+            .arg(msg.0.sid.as_ref().map(|sid| family_key(sid)).unwrap_or_default())
+            .query(&mut conn)?;
+
+        if revoked.is_some() {
+            return Err(CheckJwtError::Revoked)
+        }
+
+        // Tokens issued before sessions were recorded have no sid
+        if msg.0.sid.is_some() && session.is_none() {
+            return Err(CheckJwtError::Revoked)
+        }
+
+        // Tokens with a session are revoked with it. Others only have a
+        // whole second `iat`, so are revoked if issued in the watermark's second
+        let issued_ms = match session.as_deref().and_then(parse_unix_millis) {
+            Some(session_started) => session_started,
+            None => msg.0.iat * 1000,
+        };
+        let watermark = revoked_before.as_deref().and_then(parse_unix_millis);
+
+        if revoked_by_watermark(issued_ms, watermark) {
+            Err(CheckJwtError::Revoked)
+        } else {
+            Ok(msg.0)
         }
     }
 }
 
+/// Revokes every token issued to a user up to now, in one write.
+/// Used when a user is suspended, or their password changes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevokeUserTokens(pub String);
+
+impl Message for RevokeUserTokens {
+    type Result = Result<(), CheckJwtError>;
+}
+
+impl Handler<RevokeUserTokens> for DatabaseActor {
+    type Result = Result<(), CheckJwtError>;
+
+    fn handle(
+        &mut self,
+        msg: RevokeUserTokens,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<(), CheckJwtError> {
+        self.revoke_user_tokens(&msg.0)
+            .map_err(CheckJwtError::from)
+    }
+}
+
+impl DatabaseActor {
+    pub fn revoke_user_tokens(&self, user_id: &str) -> redis::RedisResult<()> {
+        // Keep the watermark for as long as the longest-lived token (refresh tokens)
+        let _: String = self.setex_to_cache(
+            revoked_before_key(user_id),
+            refresh_token_ttl().num_seconds() as i32,
+            Local::now().timestamp_millis().to_string(),
+        )?;
+        debug!("revoked all tokens issued before now for user: {}", user_id);
+        Ok(())
+    }
+}
+
 #[derive(Debug, Fail)]
 pub enum CheckJwtError {
     #[fail(display = "{{\"status\":\"JWT revoked, login again\"}}")]
@@ -83,4 +175,24 @@ impl ResponseError for CheckJwtError {
             },
        }
     }
-}
\ No newline at end of file
+}
+
+
+#[test]
+fn revokes_tokens_issued_in_the_same_second_as_the_watermark() {
+    let watermark = parse_unix_millis("1594000000500");
+    // session started 300ms before the watermark, in the same second
+    assert!(revoked_by_watermark(1594000000200, watermark));
+    // session started just after it, e.g. after a password change
+    assert!(!revoked_by_watermark(1594000000600, watermark));
+    // token without a session, with a whole second iat
+    assert!(revoked_by_watermark(1594000000 * 1000, watermark));
+    assert!(!revoked_by_watermark(1594000000200, None));
+}
+
+#[test]
+fn parses_watermarks_stored_in_seconds() {
+    assert_eq!(parse_unix_millis("1594000000"), Some(1594000000000));
+    assert_eq!(parse_unix_millis("1594000000500"), Some(1594000000500));
+    assert_eq!(parse_unix_millis("soon"), None);
+}
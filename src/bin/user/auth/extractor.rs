@@ -0,0 +1,74 @@
+use actix_web::{
+    dev::Payload,
+    http::header,
+    Error,
+    FromRequest,
+    HttpMessage,
+    HttpRequest,
+};
+use actix_identity::RequestIdentity;
+use futures::future::LocalBoxFuture;
+
+use crate::auth::{
+    AuthInfo,
+    verify_jwt,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+};
+
+/////////////////////////////////////////////
+/// AuthInfo Request Extractor
+/////////////////////////////////////////////
+/// Handlers take `AuthInfo` as an argument to require a logged in user:
+///     pub async fn handler(req: HttpRequest, auth_info: AuthInfo) -> ...
+///
+/// The JWT is read from `Authorization: Bearer <jwt>` (mobile clients and
+/// server-to-server calls), or else the "degen-auth" identity cookie.
+/// It is then fully verified with `verify_jwt`: signature, expiry, revocation,
+/// and that the user is not suspended or deleted.
+///
+/// Use `Option<AuthInfo>` for routes where logging in is optional.
+///
+/// If a `RequirePermission` middleware has already verified the request,
+/// its AuthInfo is reused from the request extensions.
+
+impl FromRequest for AuthInfo {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if let Some(auth_info) = req.extensions().get::<AuthInfo>() {
+            let auth_info = auth_info.clone();
+            return Box::pin(async move { Ok(auth_info) })
+        }
+        let req = req.clone();
+        Box::pin(async move {
+            let jwt = request_jwt(&req)
+                .ok_or(Error::from(noJwtError!()))?;
+            let (claims, _user) = verify_jwt(&req, &jwt).await?;
+            Ok(AuthInfo::from(claims))
+        })
+    }
+}
+
+/// Reads the JWT from the Bearer Authorization header, or identity cookie
+pub fn request_jwt<T: HttpMessage>(req: &T) -> Option<String> {
+    bearer_token(req).or(req.get_identity())
+}
+
+pub fn bearer_token<T: HttpMessage>(req: &T) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| {
+            if h.starts_with("Bearer ") {
+                Some(h.trim_start_matches("Bearer ").trim().to_string())
+            } else {
+                None
+            }
+        })
+        .filter(|jwt| !jwt.is_empty())
+}
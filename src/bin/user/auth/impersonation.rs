@@ -0,0 +1,60 @@
+use chrono::Duration;
+
+use crate::auth::{
+    Actor,
+    AuthInfo,
+    Claims,
+    SESSION_SCOPE,
+};
+use crate::models::{
+    User,
+    LoginError,
+    ErrJson,
+};
+
+/////////////////////////////////////////////
+/// Impersonation
+/////////////////////////////////////////////
+/// Admins with the "users:impersonate" permission can get a short-lived
+/// access JWT for a user at /auth/impersonate, to see what they see.
+/// The token's `act` claim names the admin (RFC 8693 token exchange):
+///     { "sub": "<user id>", "act": { "sub": "<admin id>" }, ... }
+///
+/// Impersonation tokens have no refresh token or session, and are never set
+/// as the identity cookie, so the admin's own login is untouched.
+/// They can't be used to change the password, email or profile, delete the
+/// account, manage 2FA, passkeys or sessions, export data, or impersonate
+/// someone else. Every impersonation is recorded in the `impersonations` table.
+
+const IMPERSONATION_TTL_MINUTES: i64 = 15;
+
+/// Claims of an impersonation token for `user`, acting as `admin_id`
+pub fn impersonation_claims(user: &User, admin_id: &str) -> Claims {
+    Claims {
+        act: Some(Actor { sub: admin_id.to_string() }),
+        ..Claims::with_scope(
+            user.email.clone(),
+            user.id.clone(),
+            user.user_role.clone(),
+            SESSION_SCOPE,
+            Duration::minutes(IMPERSONATION_TTL_MINUTES),
+        )
+    }
+}
+
+impl AuthInfo {
+    pub fn is_impersonation(&self) -> bool {
+        self.impersonator_id.is_some()
+    }
+
+    /// Errors with 403 Forbidden for impersonation tokens,
+    /// for account changes only the real user can make
+    pub fn forbid_impersonation(&self) -> Result<(), LoginError> {
+        if self.is_impersonation() {
+            Err(LoginError::Forbidden(
+                errJson!("Not allowed while impersonating a user")))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -2,6 +2,7 @@ use chrono::{Duration, Local};
 use jsonwebtoken::{
     encode,
     decode,
+    decode_header,
     Header,
     Algorithm,
     Validation,
@@ -11,28 +12,76 @@ use jsonwebtoken::{
 
 use crate::models::auth::{LoginEmail, QueryUserId, LoginForm, UserRole};
 use crate::models::{ User, LoginError, ErrJson };
+use crate::auth::keys::JWT_KEYS;
 
+const DEFAULT_ACCESS_TTL_MINUTES: i64 = 15;
+/// Scope of access tokens issued for a logged in session
+pub const SESSION_SCOPE: &str = "session";
+/// Scope of the token issued after a correct password, when the user
+/// still has to enter a 2FA code. It can only be used at /login/mfa.
+pub const MFA_PENDING_SCOPE: &str = "mfa_pending";
+
+/// Lifetime of access JWTs. Sessions are extended with refresh tokens,
+/// see `auth::refresh`.
+pub fn access_token_ttl() -> Duration {
+    dotenv::dotenv().ok();
+    let minutes = std::env::var("JWT_ACCESS_TTL_MINUTES").ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_ACCESS_TTL_MINUTES);
+    Duration::minutes(minutes)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     // issuer
-    iss: String,
+    pub iss: String,
     // audience: USER, ANON, DEALER, PLATFORM_ADMIN
-    aud: UserRole,
+    pub aud: UserRole,
     // subject: User ID
-    sub: String,
+    pub sub: String,
     //issued at
-    iat: i64,
+    pub iat: i64,
     // expiry
-    exp: i64,
+    pub exp: i64,
+    // JWT ID: unique per token, used to revoke a single token
+    pub jti: String,
+    // space separated scopes this token grants
+    pub scope: String,
     // user email
-    email: String,
+    pub email: String,
+    // session ID: the refresh token family this token was issued for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    // actor: the admin impersonating the subject (RFC 8693)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// The party acting on behalf of the token's subject
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Actor {
+    // User ID of the admin
+    pub sub: String,
 }
 impl Claims {
-    fn with_email(
+    fn with_session(
+        email: String,
+        user_id: String,
+        user_role: Option<UserRole>,
+        session_id: String,
+    ) -> Self {
+        Claims {
+            sid: Some(session_id),
+            ..Claims::with_scope(email, user_id, user_role, SESSION_SCOPE, access_token_ttl())
+        }
+    }
+
+    pub fn with_scope(
         email: String,
         user_id: String,
         user_role: Option<UserRole>,
+        scope: &str,
+        ttl: Duration,
     ) -> Self {
         dotenv::dotenv().ok();
         Claims {
@@ -40,10 +89,23 @@ impl Claims {
             sub: user_id,
             aud: user_role.unwrap_or(UserRole::USER),
             iat: Local::now().timestamp(),
-            exp: (Local::now() + Duration::hours(24*30)).timestamp(),
+            exp: (Local::now() + ttl).timestamp(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            scope: String::from(scope),
             email: email,
+            sid: None,
+            act: None,
         }
     }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope.split_whitespace().any(|s| s == scope)
+    }
+
+    /// Seconds until this token expires, used as the TTL when revoking it
+    pub fn remaining_lifetime(&self) -> i64 {
+        std::cmp::max(0, self.exp - Local::now().timestamp())
+    }
 }
 
 impl From<Claims> for LoginEmail {
@@ -69,6 +131,12 @@ pub struct AuthInfo {
     pub user_id: String,
     pub email: String,
     pub user_role: UserRole,
+    /// Session of the access token, see `auth::session`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// User ID of the admin, if this is an impersonation token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<String>,
 }
 
 // impl AuthInfo {
@@ -84,6 +152,8 @@ impl From<User> for AuthInfo {
             user_id: user.id,
             email: user.email,
             user_role: user.user_role.unwrap_or(UserRole::USER),
+            session_id: None,
+            impersonator_id: None,
         }
     }
 }
@@ -94,6 +164,8 @@ impl From<Claims> for AuthInfo {
             user_id: claims.sub,
             email: claims.email,
             user_role: claims.aud,
+            session_id: claims.sid,
+            impersonator_id: claims.act.map(|act| act.sub),
         }
     }
 }
@@ -102,27 +174,53 @@ pub fn create_token(
     email: String,
     user_id: String,
     user_role: Option<UserRole>,
+    session_id: String,
 ) -> Result<String, LoginError> {
 
-    let claims = Claims::with_email(email, user_id, user_role);
+    let claims = Claims::with_session(email, user_id, user_role, session_id);
+    encode_claims(&claims)
+}
+
+/// Signs claims with the active signing key, and sets its `kid` header
+pub fn encode_claims<C: serde::Serialize>(claims: &C) -> Result<String, LoginError> {
+
+    let signing_key = &JWT_KEYS.signing;
+    let mut header = Header::new(signing_key.alg);
+    header.kid = Some(signing_key.kid.clone());
 
     encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(get_secret().as_ref()),
+        &header,
+        claims,
+        &signing_key.key,
     ).map_err(|e| LoginError::DecodeError(errJson!(e)))
 }
 
 pub fn decode_token<T>(token: &str) -> Result<T, LoginError>
     where T: From<Claims>
 {
-    decode::<Claims>(
+    decode_claims::<Claims>(token)
+        .map(T::from)
+}
+
+/// Verifies the signature with the key named in the `kid` header
+pub fn decode_claims<C>(token: &str) -> Result<C, LoginError>
+    where C: serde::de::DeserializeOwned
+{
+    let header = decode_header(token)
+        .map_err(|e| LoginError::Unauthorized(errJson!(e)))?;
+
+    let verifying_key = JWT_KEYS.verifying_key(header.kid.as_deref())
+        .ok_or(LoginError::Unauthorized(errJson!("Unknown JWT signing key")))?;
+
+    // Only accept the algorithm registered for this key,
+    // never the algorithm claimed in the token header.
+    decode::<C>(
         token,
-        &DecodingKey::from_secret(get_secret().as_ref()),
-        &Validation::default()
+        &verifying_key.key,
+        &Validation::new(verifying_key.alg)
     )
-    .map(|data| Ok(data.claims.into()))
-    .map_err(|e| LoginError::Unauthorized(errJson!(e)))?
+    .map(|data| data.claims)
+    .map_err(|e| LoginError::Unauthorized(errJson!(e)))
 }
 
 pub fn get_secret() -> String {
@@ -0,0 +1,327 @@
+use jsonwebtoken::{
+    Algorithm,
+    EncodingKey,
+    DecodingKey,
+};
+use openssl::bn::BigNumContext;
+use openssl::ec::EcKey;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Id, Private, Public};
+use std::collections::HashMap;
+use std::path::Path;
+
+//////////////////////////////////////////////
+/// JWT signing and verification keys
+//////////////////////////////////////////////
+/// Tokens are signed with RS256 or ES256 (P-256) keys. Each key has an ID
+/// (`kid`) which is written into the JWT header, so verifiers know which
+/// key to check the signature with.
+///
+/// Keys are read from the directory in JWT_KEYS_DIR:
+///     <kid>.pem       PKCS8 private key: can sign and verify
+///     <kid>.pub.pem   public key: verify only (e.g. a retired signing key)
+///
+/// JWT_SIGNING_KID picks which private key signs new tokens, and defaults
+/// to the last private key by name. To rotate keys: add the new private key,
+/// then switch JWT_SIGNING_KID. Keep the old key (or just its public key)
+/// until every token signed with it has expired.
+///
+/// If JWT_KEYS_DIR is not set, tokens are signed with HS256 and the
+/// shared JWT_SECRET (local development only). Set JWT_ACCEPT_HS256=true to
+/// keep accepting HS256 tokens while migrating to asymmetric keys.
+
+lazy_static! {
+    pub static ref JWT_KEYS: JwtKeys = JwtKeys::from_env()
+        .unwrap_or_else(|e| panic!("Error loading JWT keys: {}", e));
+}
+
+const HS256_KID: &str = "hs256";
+
+pub struct SigningKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub key: EncodingKey,
+}
+
+pub struct VerifyingKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub key: DecodingKey<'static>,
+    /// Public JWK, published at /.well-known/jwks.json. None for HS256.
+    pub jwk: Option<Jwk>,
+}
+
+pub struct JwtKeys {
+    pub signing: SigningKey,
+    pub verifying: HashMap<String, VerifyingKey>,
+}
+
+/// JSON Web Key (RFC 7517), public parameters only
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwtKeys {
+
+    pub fn from_env() -> Result<Self, failure::Error> {
+        dotenv::dotenv().ok();
+        match std::env::var("JWT_KEYS_DIR").ok() {
+            None => {
+                warn!("JWT_KEYS_DIR not set, signing JWTs with HS256 JWT_SECRET");
+                Ok(JwtKeys::hs256(&crate::auth::get_secret()))
+            },
+            Some(dir) => {
+                let mut keys = JwtKeys::from_dir(
+                    Path::new(&dir),
+                    std::env::var("JWT_SIGNING_KID").ok(),
+                )?;
+                if accept_hs256() {
+                    let hs256 = JwtKeys::hs256(&crate::auth::get_secret());
+                    keys.verifying.extend(hs256.verifying);
+                }
+                Ok(keys)
+            }
+        }
+    }
+
+    pub fn hs256(secret: &str) -> Self {
+        let mut verifying = HashMap::new();
+        verifying.insert(HS256_KID.to_string(), VerifyingKey {
+            kid: HS256_KID.to_string(),
+            alg: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret.as_bytes()).into_static(),
+            jwk: None,
+        });
+        JwtKeys {
+            signing: SigningKey {
+                kid: HS256_KID.to_string(),
+                alg: Algorithm::HS256,
+                key: EncodingKey::from_secret(secret.as_bytes()),
+            },
+            verifying: verifying,
+        }
+    }
+
+    pub fn from_dir(
+        dir: &Path,
+        signing_kid: Option<String>,
+    ) -> Result<Self, failure::Error> {
+
+        let mut signing_keys: HashMap<String, SigningKey> = HashMap::new();
+        let mut verifying: HashMap<String, VerifyingKey> = HashMap::new();
+
+        let mut paths = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let file_name = match path.file_name().and_then(|f| f.to_str()) {
+                Some(f) => f.to_string(),
+                None => continue,
+            };
+            let pem = std::fs::read(&path)?;
+
+            if file_name.ends_with(".pub.pem") {
+                let kid = file_name.trim_end_matches(".pub.pem").to_string();
+                verifying.insert(kid.clone(), load_public_key(&kid, &pem)?);
+            } else if file_name.ends_with(".pem") {
+                let kid = file_name.trim_end_matches(".pem").to_string();
+                let (signing_key, verifying_key) = load_private_key(&kid, &pem)?;
+                signing_keys.insert(kid.clone(), signing_key);
+                verifying.insert(kid, verifying_key);
+            }
+        }
+
+        let signing_kid = match signing_kid {
+            Some(kid) => kid,
+            None => {
+                let mut kids = signing_keys.keys().cloned().collect::<Vec<String>>();
+                kids.sort();
+                kids.pop().ok_or(format_err!("No private keys found in {:?}", dir))?
+            }
+        };
+
+        let signing = signing_keys.remove(&signing_kid)
+            .ok_or(format_err!("Private key for JWT_SIGNING_KID {} not found", signing_kid))?;
+
+        info!("Signing JWTs with kid: {}, verifying kids: {:?}",
+            signing.kid, verifying.keys().collect::<Vec<_>>());
+
+        Ok(JwtKeys {
+            signing: signing,
+            verifying: verifying,
+        })
+    }
+
+    pub fn verifying_key(&self, kid: Option<&str>) -> Option<&VerifyingKey> {
+        // Tokens issued before key IDs were added have no kid header
+        self.verifying.get(kid.unwrap_or(HS256_KID))
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys = self.verifying.values()
+            .filter_map(|k| k.jwk.clone())
+            .collect::<Vec<Jwk>>();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        JwkSet { keys: keys }
+    }
+}
+
+fn accept_hs256() -> bool {
+    match std::env::var("JWT_ACCEPT_HS256").ok() {
+        Some(s) => s.to_lowercase() == "true" || s.to_lowercase() == "yes",
+        None => false,
+    }
+}
+
+fn b64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn load_private_key(
+    kid: &str,
+    pem: &[u8],
+) -> Result<(SigningKey, VerifyingKey), failure::Error> {
+
+    let pkey: PKey<Private> = PKey::private_key_from_pem(pem)?;
+    let public_pem = pkey.public_key_to_pem()?;
+    let public_key: PKey<Public> = PKey::public_key_from_pem(&public_pem)?;
+    let verifying_key = verifying_key_from(kid, &public_key, &public_pem)?;
+
+    let signing_key = match verifying_key.alg {
+        Algorithm::RS256 => EncodingKey::from_rsa_pem(pem)?,
+        Algorithm::ES256 => EncodingKey::from_ec_pem(pem)?,
+        alg => return Err(format_err!("Unsupported JWT algorithm: {:?}", alg)),
+    };
+
+    Ok((
+        SigningKey {
+            kid: kid.to_string(),
+            alg: verifying_key.alg,
+            key: signing_key,
+        },
+        verifying_key,
+    ))
+}
+
+pub fn load_public_key(
+    kid: &str,
+    pem: &[u8],
+) -> Result<VerifyingKey, failure::Error> {
+    let public_key: PKey<Public> = PKey::public_key_from_pem(pem)?;
+    let public_pem = public_key.public_key_to_pem()?;
+    verifying_key_from(kid, &public_key, &public_pem)
+}
+
+fn verifying_key_from(
+    kid: &str,
+    public_key: &PKey<Public>,
+    public_pem: &[u8],
+) -> Result<VerifyingKey, failure::Error> {
+
+    match public_key.id() {
+        Id::RSA => {
+            let rsa = public_key.rsa()?;
+            Ok(VerifyingKey {
+                kid: kid.to_string(),
+                alg: Algorithm::RS256,
+                key: DecodingKey::from_rsa_pem(public_pem)?.into_static(),
+                jwk: Some(Jwk {
+                    kty: String::from("RSA"),
+                    kid: kid.to_string(),
+                    alg: String::from("RS256"),
+                    key_use: String::from("sig"),
+                    n: Some(b64url(&rsa.n().to_vec())),
+                    e: Some(b64url(&rsa.e().to_vec())),
+                    crv: None,
+                    x: None,
+                    y: None,
+                }),
+            })
+        },
+        Id::EC => {
+            let ec: EcKey<Public> = public_key.ec_key()?;
+            if ec.group().curve_name() != Some(Nid::X9_62_PRIME256V1) {
+                return Err(format_err!("EC key {} must use the P-256 curve for ES256", kid))
+            }
+            let mut ctx = BigNumContext::new()?;
+            let mut x = openssl::bn::BigNum::new()?;
+            let mut y = openssl::bn::BigNum::new()?;
+            ec.public_key().affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)?;
+            Ok(VerifyingKey {
+                kid: kid.to_string(),
+                alg: Algorithm::ES256,
+                key: DecodingKey::from_ec_pem(public_pem)?.into_static(),
+                jwk: Some(Jwk {
+                    kty: String::from("EC"),
+                    kid: kid.to_string(),
+                    alg: String::from("ES256"),
+                    key_use: String::from("sig"),
+                    n: None,
+                    e: None,
+                    crv: Some(String::from("P-256")),
+                    // coordinates are fixed width (32 bytes) for P-256
+                    x: Some(b64url(&x.to_vec_padded(32)?)),
+                    y: Some(b64url(&y.to_vec_padded(32)?)),
+                }),
+            })
+        },
+        _ => Err(format_err!("Unsupported key type for {}, use RSA or EC P-256", kid)),
+    }
+}
+
+
+
+#[test]
+fn loads_rsa_key_and_publishes_jwk() {
+    let rsa = openssl::rsa::Rsa::generate(2048).expect("rsa key");
+    let pem = PKey::from_rsa(rsa).expect("pkey")
+        .private_key_to_pem_pkcs8().expect("pkcs8 pem");
+
+    let (signing, verifying) = load_private_key("rsa-2020-01", &pem)
+        .expect("loaded rsa key");
+    assert_eq!(signing.alg, Algorithm::RS256);
+
+    let jwk = verifying.jwk.expect("rsa jwk");
+    assert_eq!(jwk.kty, "RSA");
+    assert_eq!(jwk.kid, "rsa-2020-01");
+    assert_eq!(jwk.e, Some(String::from("AQAB")));
+}
+
+#[test]
+fn loads_ec_key_and_publishes_jwk() {
+    let group = openssl::ec::EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).expect("p256");
+    let ec = EcKey::generate(&group).expect("ec key");
+    let pem = PKey::from_ec_key(ec).expect("pkey")
+        .private_key_to_pem_pkcs8().expect("pkcs8 pem");
+
+    let (signing, verifying) = load_private_key("ec-2020-01", &pem)
+        .expect("loaded ec key");
+    assert_eq!(signing.alg, Algorithm::ES256);
+
+    let jwk = verifying.jwk.expect("ec jwk");
+    assert_eq!(jwk.crv, Some(String::from("P-256")));
+    // 32 bytes => 43 base64 chars without padding
+    assert_eq!(jwk.x.map(|x| x.len()), Some(43));
+}
@@ -0,0 +1,298 @@
+//// External Imports
+use actix_web::{
+    http::{HeaderMap, StatusCode},
+    error::ResponseError,
+    HttpRequest,
+    HttpResponse,
+    Error,
+};
+use chrono::Local;
+use std::net::{IpAddr, SocketAddr};
+
+//// Internal Imports
+use crate::models::email_identity;
+use crate::redis_client::{RedisCommand, Setex, Incr};
+use crate::AppState;
+
+/////////////////////////////////////////////
+/// Login brute-force protection
+/////////////////////////////////////////////
+/// Wrong passwords and 2FA codes are counted per account (email) and per
+/// client IP.
+/// After a few failures each further attempt is delayed with exponential
+/// backoff, and after too many the account or IP is locked out for a while.
+/// Blocked requests get a 429 with Retry-After, before the password is checked.
+///
+/// Counters expire FAILURE_WINDOW_SECONDS after the last failure.
+/// A completed login (after the 2FA code, if enabled) clears the account
+/// counter, as does a password reset.
+/// Admins can clear a lockout at /auth/lockout/clear.
+///
+/// Redis keys:
+///     login_failures:account:{email} => number of recent failures
+///     login_failures:ip:{ip}         => number of recent failures
+///     login_blocked:account:{email}  => unix time the account is blocked until
+///     login_blocked:ip:{ip}          => unix time the IP is blocked until
+
+const FAILURE_WINDOW_SECONDS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockoutPolicy {
+    /// failures before backoff starts
+    pub backoff_after: i64,
+    /// failures before lockout
+    pub lockout_after: i64,
+    pub lockout_seconds: i64,
+}
+
+pub const ACCOUNT_LOCKOUT: LockoutPolicy = LockoutPolicy {
+    backoff_after: 3,
+    lockout_after: 10,
+    lockout_seconds: 900, // 15min
+};
+
+/// Looser than per account, as many users can share an IP (NAT, offices)
+pub const IP_LOCKOUT: LockoutPolicy = LockoutPolicy {
+    backoff_after: 20,
+    lockout_after: 100,
+    lockout_seconds: 900,
+};
+
+impl LockoutPolicy {
+    /// How long to block further attempts after `failures` failures.
+    /// Doubles with each failure from 1 second, up to the lockout.
+    pub fn block_seconds(&self, failures: i64) -> Option<i64> {
+        if failures >= self.lockout_after {
+            Some(self.lockout_seconds)
+        } else if failures >= self.backoff_after {
+            let exponent = std::cmp::min(failures - self.backoff_after, 30) as u32;
+            Some(std::cmp::min(2i64.pow(exponent), self.lockout_seconds))
+        } else {
+            None
+        }
+    }
+}
+
+lazy_static! {
+    /// Proxies (e.g. the gateway) trusted to set X-Forwarded-For,
+    /// a comma separated list of IPs in TRUSTED_PROXIES
+    static ref TRUSTED_PROXIES: Vec<IpAddr> = std::env::var("TRUSTED_PROXIES")
+        .map(|s| s.split(',').filter_map(parse_ip).collect())
+        .unwrap_or_default();
+}
+
+/// The client's IP, see `connection_ip`
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    connection_ip(req.peer_addr(), req.headers())
+}
+
+/// The client's IP. X-Forwarded-For is only read when the connection comes
+/// from a trusted proxy, otherwise clients could dodge per-IP limits by
+/// sending a different header with each request.
+pub fn connection_ip(peer_addr: Option<SocketAddr>, headers: &HeaderMap) -> Option<String> {
+    let forwarded_for = headers.get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok());
+    peer_addr.map(|peer| {
+        forwarded_client_ip(peer.ip(), forwarded_for, &TRUSTED_PROXIES).to_string()
+    })
+}
+
+/// Walks X-Forwarded-For back from the nearest hop, past trusted proxies.
+/// The first untrusted address is the client, anything before it
+/// was sent by the client and could be forged.
+fn forwarded_client_ip(
+    peer: IpAddr,
+    forwarded_for: Option<&str>,
+    trusted: &[IpAddr],
+) -> IpAddr {
+
+    let mut client = peer;
+    if !trusted.contains(&client) {
+        return client
+    }
+    for hop in forwarded_for.unwrap_or("").rsplit(',') {
+        match parse_ip(hop) {
+            Some(ip) => client = ip,
+            None => break,
+        }
+        if !trusted.contains(&client) {
+            break
+        }
+    }
+    client
+}
+
+/// Proxies may include the port
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim();
+    addr.parse::<IpAddr>().ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|socket| socket.ip()))
+}
+
+fn account_id(email: &str) -> String {
+    email_identity(email)
+}
+
+fn failures_key(scope: &str, id: &str) -> String {
+    format!("login_failures:{}:{}", scope, id)
+}
+
+fn blocked_key(scope: &str, id: &str) -> String {
+    format!("login_blocked:{}:{}", scope, id)
+}
+
+fn scopes(email: &str, ip: Option<&str>) -> Vec<(&'static str, String, LockoutPolicy)> {
+    let mut scopes = vec![("account", account_id(email), ACCOUNT_LOCKOUT)];
+    if let Some(ip) = ip {
+        scopes.push(("ip", String::from(ip), IP_LOCKOUT));
+    }
+    scopes
+}
+
+/// Errors with 429 if the account or IP is currently blocked
+pub async fn check_login_lockout(
+    req: &HttpRequest,
+    email: &str,
+) -> Result<(), Error> {
+
+    let ip = client_ip(req);
+    let now = Local::now().timestamp();
+    let mut retry_after = 0;
+
+    for (scope, id, _policy) in scopes(email, ip.as_deref()) {
+        // Missing keys are an error from GET, i.e. not blocked
+        let blocked_until = AppState::redisActor(req)
+            .send(RedisCommand::Get(blocked_key(scope, &id)))
+            .await?
+            .ok()
+            .and_then(|t| t.parse::<i64>().ok());
+
+        if let Some(until) = blocked_until {
+            retry_after = std::cmp::max(retry_after, until - now);
+        }
+    }
+
+    if retry_after > 0 {
+        return Err(Error::from(LockoutError::Locked(retry_after)))
+    }
+    Ok(())
+}
+
+/// Counts a wrong password or 2FA code, and blocks the account or IP if needed
+pub async fn record_login_failure(
+    req: &HttpRequest,
+    email: &str,
+) -> Result<(), Error> {
+
+    let ip = client_ip(req);
+    let now = Local::now().timestamp();
+
+    for (scope, id, policy) in scopes(email, ip.as_deref()) {
+        let failures = AppState::redisActor(req)
+            .send(RedisCommand::Incr(Incr {
+                key: failures_key(scope, &id),
+                ttl: FAILURE_WINDOW_SECONDS as i32,
+            }))
+            .await??
+            .parse::<i64>()
+            .unwrap_or(0);
+
+        if let Some(seconds) = policy.block_seconds(failures) {
+            if seconds >= policy.lockout_seconds {
+                warn!("login locked out for {} {} after {} failures", scope, id, failures);
+            }
+            AppState::redisActor(req)
+                .send(RedisCommand::Setex(Setex {
+                    key: blocked_key(scope, &id),
+                    ttl: seconds as i32,
+                    value: (now + seconds).to_string(),
+                }))
+                .await??;
+        }
+    }
+    Ok(())
+}
+
+/// Clears the failure count and any lockout for an account
+pub fn clear_account_lockout(req: &HttpRequest, email: &str) {
+    let id = account_id(email);
+    AppState::redisActor(req)
+        .do_send(RedisCommand::Del(failures_key("account", &id)));
+    AppState::redisActor(req)
+        .do_send(RedisCommand::Del(blocked_key("account", &id)));
+}
+
+/// Clears the failure count and any lockout for an IP
+pub fn clear_ip_lockout(req: &HttpRequest, ip: &str) {
+    AppState::redisActor(req)
+        .do_send(RedisCommand::Del(failures_key("ip", ip)));
+    AppState::redisActor(req)
+        .do_send(RedisCommand::Del(blocked_key("ip", ip)));
+}
+
+#[derive(Debug, Fail)]
+pub enum LockoutError {
+    #[fail(display = "{{\"status\":\"Too many login attempts, retry after {}s\"}}", _0)]
+    Locked(i64),
+}
+
+impl ResponseError for LockoutError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            LockoutError::Locked(retry_after) => {
+                HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                .header("Retry-After", retry_after.to_string())
+                .json(json!({
+                    "status": "TOO_MANY_ATTEMPTS",
+                    "message": "Too many failed login attempts, try again later.",
+                    "retryAfter": retry_after,
+                }))
+            },
+       }
+    }
+}
+
+
+#[test]
+fn lockout_backs_off_exponentially_then_locks() {
+    let policy = ACCOUNT_LOCKOUT;
+    assert_eq!(policy.block_seconds(1), None);
+    assert_eq!(policy.block_seconds(2), None);
+    assert_eq!(policy.block_seconds(3), Some(1));
+    assert_eq!(policy.block_seconds(4), Some(2));
+    assert_eq!(policy.block_seconds(9), Some(64));
+    assert_eq!(policy.block_seconds(10), Some(900));
+    assert_eq!(policy.block_seconds(500), Some(900));
+}
+
+#[test]
+fn ip_backoff_is_capped_at_the_lockout() {
+    let policy = IP_LOCKOUT;
+    assert_eq!(policy.block_seconds(19), None);
+    assert_eq!(policy.block_seconds(20), Some(1));
+    assert_eq!(policy.block_seconds(99), Some(900));
+    assert_eq!(policy.block_seconds(100), Some(900));
+}
+
+#[test]
+fn client_ips_do_not_include_the_port() {
+    assert_eq!(parse_ip("10.0.0.1:4000"), "10.0.0.1".parse().ok());
+    assert_eq!(parse_ip("[::1]:4000"), "::1".parse().ok());
+    assert_eq!(parse_ip(" 203.0.113.7"), "203.0.113.7".parse().ok());
+    assert_eq!(parse_ip("unknown"), None);
+}
+
+#[test]
+fn forwarded_for_is_only_trusted_from_trusted_proxies() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let gateway = ip("10.0.0.2");
+    let trusted = vec![gateway];
+
+    // Direct clients can't pick their IP
+    assert_eq!(forwarded_client_ip(ip("203.0.113.7"), Some("1.2.3.4"), &trusted), ip("203.0.113.7"));
+    // Behind the gateway, the address it saw is the client
+    assert_eq!(forwarded_client_ip(gateway, Some("203.0.113.7"), &trusted), ip("203.0.113.7"));
+    // and addresses the client prepended are ignored
+    assert_eq!(forwarded_client_ip(gateway, Some("1.2.3.4, 203.0.113.7"), &trusted), ip("203.0.113.7"));
+    assert_eq!(forwarded_client_ip(gateway, None, &trusted), gateway);
+}
@@ -0,0 +1,388 @@
+//// External Imports
+use actix::{Handler, SyncContext, Message};
+use actix_web::{
+    http::StatusCode,
+    error::ResponseError,
+    HttpResponse,
+};
+use ring::constant_time::verify_slices_are_equal;
+use ring::rand::{SecureRandom, SystemRandom};
+
+//// Internal Imports
+use crate::db::DatabaseActor;
+use crate::auth::hash_token;
+use crate::models::{email_identity, User};
+
+/////////////////////////////////////////////
+/// Passwordless login (magic links and email codes)
+/////////////////////////////////////////////
+/// /login/magic/send emails the user a login link and a 6 digit code.
+/// Either one can be exchanged once at /login/magic/redeem for the usual
+/// JWT and refresh token, and both stop working when the other is used.
+///
+/// /login/magic/send always gives the same response, whether or not the
+/// account exists. Requests are rate limited per email and per IP address.
+///
+/// Redis keys (the link token and code are only stored as sha256 hashes):
+///     magic_login:{token hash}      => MagicLoginRecord (json)
+///     magic_login_email:{email}     => token hash of the latest link for an email
+///     magic_login_attempts:{hash}   => number of wrong codes tried for a link
+///     magic_login_limit:email:{email} => requests for an email this window
+///     magic_login_limit:ip:{ip}       => requests from an IP this window
+
+pub const MAGIC_LINK_TTL_SECONDS: i64 = 900; // 15min
+pub const MAGIC_CODE_MAX_ATTEMPTS: i64 = 5;
+
+const RATE_LIMIT_WINDOW_SECONDS: i64 = 3600;
+const MAX_REQUESTS_PER_EMAIL: i64 = 5;
+const MAX_REQUESTS_PER_IP: i64 = 20;
+
+const MAGIC_CODE_DIGITS: usize = 6;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MagicLoginRecord {
+    pub user_id: String,
+    pub email: String,
+    pub code_hash: String,
+}
+
+impl MagicLoginRecord {
+    /// Links and codes only work for the email they were sent to,
+    /// so ones sent before an email change stop working.
+    pub fn matches_user(&self, user: &User) -> bool {
+        self.user_id == user.id
+            && email_identity(&self.email) == email_identity(&user.email)
+    }
+}
+
+/// A new link token and code, sent to the user and never stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLogin {
+    pub token: String,
+    pub code: String,
+}
+
+/// Emails are compared case-insensitively for rate limits and codes
+pub fn magic_email_key(email: &str) -> String {
+    email_identity(email)
+}
+
+fn magic_login_key(token_hash: &str) -> String {
+    format!("magic_login:{}", token_hash)
+}
+
+fn magic_login_email_key(email: &str) -> String {
+    format!("magic_login_email:{}", magic_email_key(email))
+}
+
+fn magic_login_attempts_key(token_hash: &str) -> String {
+    format!("magic_login_attempts:{}", token_hash)
+}
+
+fn generate_magic_token() -> Option<String> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).ok()?;
+    Some(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+/// Random numeric code, e.g. "042817"
+pub fn generate_magic_code() -> Option<String> {
+    let mut bytes = [0u8; MAGIC_CODE_DIGITS];
+    SystemRandom::new().fill(&mut bytes).ok()?;
+    // `% 10` is very slightly biased towards 0-5, fine for a 15min code
+    Some(bytes.iter().map(|b| char::from(b'0' + b % 10)).collect())
+}
+
+pub fn codes_match(code_hash: &str, code: &str) -> bool {
+    let code: String = code.chars().filter(|c| c.is_ascii_digit()).collect();
+    verify_slices_are_equal(code_hash.as_bytes(), hash_token(&code).as_bytes()).is_ok()
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+/// Counts a send request against the email and IP rate limits.
+/// Returns false once either limit is reached for the current window.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountMagicLinkRequest {
+    pub email: String,
+    pub ip: Option<String>,
+}
+
+impl Message for CountMagicLinkRequest {
+    type Result = Result<bool, MagicLinkError>;
+}
+
+impl Handler<CountMagicLinkRequest> for DatabaseActor {
+    type Result = Result<bool, MagicLinkError>;
+
+    fn handle(
+        &mut self,
+        msg: CountMagicLinkRequest,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<bool, MagicLinkError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let email_key = format!("magic_login_limit:email:{}", magic_email_key(&msg.email));
+        let email_count = count_in_window(&mut conn, &email_key)?;
+
+        let ip_count = match &msg.ip {
+            Some(ip) => count_in_window(&mut conn, &format!("magic_login_limit:ip:{}", ip))?,
+            None => 0,
+        };
+
+        Ok(email_count <= MAX_REQUESTS_PER_EMAIL && ip_count <= MAX_REQUESTS_PER_IP)
+    }
+}
+
+/// Fixed window counter: the window starts with the first request
+fn count_in_window(
+    conn: &mut redis::Connection,
+    key: &str
+) -> Result<i64, MagicLinkError> {
+    let (count,): (i64,) = redis::pipe()
+        .atomic()
+        .cmd("SET").arg(key).arg(0).arg("NX").arg("EX").arg(RATE_LIMIT_WINDOW_SECONDS).ignore()
+        .cmd("INCR").arg(key)
+        .query(conn)?;
+    Ok(count)
+}
+
+/// Creates a login link and code for a user.
+/// Replaces any earlier link for the same email.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateMagicLogin {
+    pub user_id: String,
+    pub email: String,
+}
+
+impl Message for CreateMagicLogin {
+    type Result = Result<MagicLogin, MagicLinkError>;
+}
+
+impl Handler<CreateMagicLogin> for DatabaseActor {
+    type Result = Result<MagicLogin, MagicLinkError>;
+
+    fn handle(
+        &mut self,
+        msg: CreateMagicLogin,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<MagicLogin, MagicLinkError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let token = generate_magic_token()
+            .ok_or(MagicLinkError::Internal(String::from("Could not generate login link")))?;
+        let code = generate_magic_code()
+            .ok_or(MagicLinkError::Internal(String::from("Could not generate login code")))?;
+
+        let token_hash = hash_token(&token);
+        let record = MagicLoginRecord {
+            user_id: msg.user_id,
+            email: magic_email_key(&msg.email),
+            code_hash: hash_token(&code),
+        };
+        let record_json = serde_json::to_string(&record)
+            .map_err(|e| MagicLinkError::Internal(e.to_string()))?;
+
+        let previous: Option<String> = redis::cmd("GET")
+            .arg(magic_login_email_key(&msg.email))
+            .query(&mut conn)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(previous_hash) = previous {
+            pipe.cmd("DEL").arg(magic_login_key(&previous_hash)).ignore();
+        }
+        pipe.cmd("SETEX").arg(magic_login_key(&token_hash))
+                .arg(MAGIC_LINK_TTL_SECONDS).arg(record_json).ignore()
+            .cmd("SETEX").arg(magic_login_email_key(&msg.email))
+                .arg(MAGIC_LINK_TTL_SECONDS).arg(&token_hash).ignore();
+        let _: () = pipe.query(&mut conn)?;
+
+        Ok(MagicLogin {
+            token: token,
+            code: code,
+        })
+    }
+}
+
+/// Exchanges a login link token, or an email and code, for the record.
+/// Either way the link and code are deleted, so they only work once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RedeemMagicLogin {
+    Token(String),
+    Code {
+        email: String,
+        code: String,
+    },
+}
+
+impl Message for RedeemMagicLogin {
+    type Result = Result<MagicLoginRecord, MagicLinkError>;
+}
+
+impl Handler<RedeemMagicLogin> for DatabaseActor {
+    type Result = Result<MagicLoginRecord, MagicLinkError>;
+
+    fn handle(
+        &mut self,
+        msg: RedeemMagicLogin,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<MagicLoginRecord, MagicLinkError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let token_hash = match &msg {
+            RedeemMagicLogin::Token(token) => hash_token(token),
+            RedeemMagicLogin::Code { email, .. } => {
+                redis::cmd("GET")
+                    .arg(magic_login_email_key(email))
+                    .query::<Option<String>>(&mut conn)?
+                    .ok_or(MagicLinkError::InvalidToken)?
+            }
+        };
+
+        let record: MagicLoginRecord = match redis::cmd("GET")
+            .arg(magic_login_key(&token_hash))
+            .query::<Option<String>>(&mut conn)?
+        {
+            None => return Err(MagicLinkError::InvalidToken),
+            Some(s) => serde_json::from_str(&s)
+                .map_err(|_| MagicLinkError::InvalidToken)?,
+        };
+
+        if let RedeemMagicLogin::Code { code, .. } = &msg {
+            if !codes_match(&record.code_hash, code) {
+                let attempts_key = magic_login_attempts_key(&token_hash);
+                let (attempts,): (i64,) = redis::pipe()
+                    .atomic()
+                    .cmd("INCR").arg(&attempts_key)
+                    .cmd("EXPIRE").arg(&attempts_key).arg(MAGIC_LINK_TTL_SECONDS).ignore()
+                    .query(&mut conn)?;
+
+                if attempts >= MAGIC_CODE_MAX_ATTEMPTS {
+                    let _: () = redis::pipe()
+                        .cmd("DEL").arg(magic_login_key(&token_hash)).ignore()
+                        .cmd("DEL").arg(magic_login_email_key(&record.email)).ignore()
+                        .query(&mut conn)?;
+                    return Err(MagicLinkError::TooManyAttempts)
+                }
+                return Err(MagicLinkError::InvalidCode)
+            }
+        }
+
+        // DEL returns 0 if another request redeemed the link first
+        let (deleted,): (i64,) = redis::pipe()
+            .atomic()
+            .cmd("DEL").arg(magic_login_key(&token_hash))
+            .cmd("DEL").arg(magic_login_email_key(&record.email)).ignore()
+            .cmd("DEL").arg(magic_login_attempts_key(&token_hash)).ignore()
+            .query(&mut conn)?;
+
+        if deleted == 0 {
+            return Err(MagicLinkError::InvalidToken)
+        }
+
+        Ok(record)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum MagicLinkError {
+    #[fail(display = "{{\"status\":\"Login link invalid or expired\"}}")]
+    InvalidToken,
+    #[fail(display = "{{\"status\":\"Login code invalid\"}}")]
+    InvalidCode,
+    #[fail(display = "{{\"status\":\"Too many login code attempts\"}}")]
+    TooManyAttempts,
+    #[fail(display = "{{\"status\":\"Too many login link requests\"}}")]
+    RateLimited,
+    #[fail(display = "{{\"status\":\"Login link error: {}\"}}", _0)]
+    Internal(String),
+}
+
+impl From<redis::RedisError> for MagicLinkError {
+    fn from(e: redis::RedisError) -> Self {
+        MagicLinkError::Internal(e.to_string())
+    }
+}
+
+impl ResponseError for MagicLinkError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            MagicLinkError::InvalidToken => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "INVALID_LOGIN_LINK",
+                    "message": "Login link is invalid, expired or was already used."
+                }))
+            },
+            MagicLinkError::InvalidCode => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "INVALID_CODE",
+                    "message": "Login code is invalid."
+                }))
+            },
+            MagicLinkError::TooManyAttempts => {
+                HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                .json(json!({
+                    "status": "TOO_MANY_ATTEMPTS",
+                    "message": "Too many invalid codes, request a new login link."
+                }))
+            },
+            MagicLinkError::RateLimited => {
+                HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                .header("Retry-After", RATE_LIMIT_WINDOW_SECONDS.to_string())
+                .json(json!({
+                    "status": "RATE_LIMITED",
+                    "message": "Too many login link requests, try again later."
+                }))
+            },
+            MagicLinkError::Internal(e) => {
+                warn!("magic link error: {}", e);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                .json(json!({
+                    "status": "ERROR",
+                    "message": "Something went wrong with the login link."
+                }))
+            },
+       }
+    }
+}
+
+
+#[test]
+fn magic_codes_are_six_digits() {
+    let code = generate_magic_code().unwrap();
+    assert_eq!(code.len(), 6);
+    assert!(code.chars().all(|c| c.is_ascii_digit()));
+}
+
+#[test]
+fn magic_codes_match_their_hash_only() {
+    let code_hash = hash_token("042817");
+    assert!(codes_match(&code_hash, "042817"));
+    assert!(codes_match(&code_hash, " 042 817 "));
+    assert!(!codes_match(&code_hash, "042818"));
+    assert_eq!(magic_email_key(" Alice@Example.com "), "alice@example.com");
+}
+
+#[test]
+fn magic_links_are_only_valid_for_the_email_they_were_sent_to() {
+    let user = User::new(
+        String::from("jack@black.com"),
+        String::from("tenacious"),
+        None,
+        None,
+    );
+    let record = MagicLoginRecord {
+        user_id: user.id.clone(),
+        email: String::from("Jack@Black.com"),
+        code_hash: hash_token("042817"),
+    };
+    assert!(record.matches_user(&user));
+    assert!(!MagicLoginRecord { email: String::from("kyle@gass.com"), ..record }.matches_user(&user));
+}
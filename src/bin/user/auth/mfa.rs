@@ -0,0 +1,169 @@
+//// External Imports
+use actix::{Handler, SyncContext, Message};
+use actix_web::{
+    http::StatusCode,
+    error::ResponseError,
+    HttpRequest,
+    HttpResponse,
+};
+use chrono::Duration;
+
+//// Internal Imports
+use crate::db::DatabaseActor;
+use crate::auth::{
+    Claims,
+    encode_claims,
+    revoked_jti_key,
+    MFA_PENDING_SCOPE,
+};
+use crate::models::{User, LoginError};
+use crate::redis_client::{RedisCommand, Setex};
+use crate::AppState;
+
+/////////////////////////////////////////////
+/// Two-factor login
+/////////////////////////////////////////////
+/// When a user with TOTP enabled logs in with the right password, they are
+/// given a short-lived "mfa_pending" token instead of a session. The token
+/// is exchanged at /login/mfa, with a TOTP code or a recovery code, for
+/// the usual JWT and refresh token.
+///
+/// "mfa_pending" tokens are rejected everywhere else (see `verify_jwt`),
+/// are single use, and allow at most MFA_MAX_ATTEMPTS wrong codes.
+///
+/// Redis keys:
+///     mfa_attempts:{jti}  => number of codes tried with an mfa_pending token
+
+const MFA_PENDING_TTL_MINUTES: i64 = 5;
+pub const MFA_MAX_ATTEMPTS: i64 = 5;
+
+pub fn create_mfa_pending_token(user: &User) -> Result<String, LoginError> {
+    let claims = Claims::with_scope(
+        user.email.clone(),
+        user.id.clone(),
+        user.user_role.clone(),
+        MFA_PENDING_SCOPE,
+        Duration::minutes(MFA_PENDING_TTL_MINUTES),
+    );
+    encode_claims(&claims)
+}
+
+/// Revokes an mfa_pending token once it has been used, or too many
+/// wrong codes were tried with it
+pub fn revoke_mfa_pending_token(req: &HttpRequest, claims: &Claims) {
+    AppState::redisActor(req)
+        .do_send(RedisCommand::Setex(
+            Setex {
+                key: revoked_jti_key(&claims.jti),
+                ttl: std::cmp::max(1, claims.remaining_lifetime()) as i32,
+                value: String::from("REVOKED")
+            }
+        ));
+}
+
+fn mfa_attempts_key(jti: &str) -> String {
+    format!("mfa_attempts:{}", jti)
+}
+
+/// Counts a code tried with an mfa_pending token.
+/// Returns the number of attempts so far, including this one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountMfaAttempt(pub Claims);
+
+impl Message for CountMfaAttempt {
+    type Result = Result<i64, MfaError>;
+}
+
+impl Handler<CountMfaAttempt> for DatabaseActor {
+    type Result = Result<i64, MfaError>;
+
+    fn handle(
+        &mut self,
+        msg: CountMfaAttempt,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<i64, MfaError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+        let key = mfa_attempts_key(&msg.0.jti);
+
+        let (attempts,): (i64,) = redis::pipe()
+            .atomic()
+            .cmd("INCR").arg(&key)
+            .cmd("EXPIRE").arg(&key).arg(std::cmp::max(1, msg.0.remaining_lifetime())).ignore()
+            .query(&mut conn)?;
+
+        Ok(attempts)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum MfaError {
+    #[fail(display = "{{\"status\":\"MFA code invalid\"}}")]
+    InvalidCode,
+    #[fail(display = "{{\"status\":\"MFA token invalid or expired\"}}")]
+    InvalidMfaToken,
+    #[fail(display = "{{\"status\":\"Too many MFA attempts\"}}")]
+    TooManyAttempts,
+    #[fail(display = "{{\"status\":\"TOTP not enrolled\"}}")]
+    NotEnrolled,
+    #[fail(display = "{{\"status\":\"TOTP already enabled\"}}")]
+    AlreadyEnabled,
+    #[fail(display = "{{\"status\":\"MFA error: {}\"}}", _0)]
+    Internal(String),
+}
+
+impl From<redis::RedisError> for MfaError {
+    fn from(e: redis::RedisError) -> Self {
+        MfaError::Internal(e.to_string())
+    }
+}
+
+impl ResponseError for MfaError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            MfaError::InvalidCode => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "INVALID_CODE",
+                    "message": "Authentication code is invalid, or was already used."
+                }))
+            },
+            MfaError::InvalidMfaToken => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "INVALID_MFA_TOKEN",
+                    "message": "MFA token is invalid or expired, login again."
+                }))
+            },
+            MfaError::TooManyAttempts => {
+                HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                .json(json!({
+                    "status": "TOO_MANY_ATTEMPTS",
+                    "message": "Too many invalid codes, login again."
+                }))
+            },
+            MfaError::NotEnrolled => {
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(json!({
+                    "status": "NOT_ENROLLED",
+                    "message": "Two-factor authentication is not set up, enroll first."
+                }))
+            },
+            MfaError::AlreadyEnabled => {
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(json!({
+                    "status": "ALREADY_ENABLED",
+                    "message": "Two-factor authentication is already enabled, disable it first."
+                }))
+            },
+            MfaError::Internal(e) => {
+                warn!("MFA error: {}", e);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                .json(json!({
+                    "status": "ERROR",
+                    "message": "Something went wrong with two-factor authentication."
+                }))
+            },
+       }
+    }
+}
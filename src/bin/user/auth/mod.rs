@@ -1,9 +1,38 @@
-
+pub mod account_deletion;
 pub mod actor;
+pub mod extractor;
+pub mod impersonation;
 pub mod jwt;
+pub mod keys;
+pub mod lockout;
+pub mod magic_link;
+pub mod mfa;
+pub mod permissions;
+pub mod refresh;
+pub mod service;
+pub mod session;
+pub mod suspension;
+pub mod totp;
+pub mod verify;
+pub mod webauthn;
 
+pub use account_deletion::*;
 pub use actor::*;
+pub use extractor::*;
+pub use impersonation::*;
 pub use jwt::*;
+pub use keys::*;
+pub use lockout::*;
+pub use magic_link::*;
+pub use mfa::*;
+pub use permissions::*;
+pub use refresh::*;
+pub use service::*;
+pub use session::*;
+pub use suspension::*;
+pub use totp::*;
+pub use verify::*;
+pub use webauthn::*;
 
 pub fn create_jwt_secret() -> (String, String) {
     let secret = std::env::var("JWT_ID_KEY")
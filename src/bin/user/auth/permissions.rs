@@ -0,0 +1,290 @@
+use actix_web::{
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+    FromRequest,
+    HttpMessage,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::auth::AuthInfo;
+use crate::models::{
+    LoginError,
+    ErrJson,
+    UserRole,
+};
+
+//////////////////////////////////////////////
+/// Permissions
+//////////////////////////////////////////////
+/// Handlers are authorized by named permissions, not by checking roles.
+/// Each `UserRole` is granted a set of permissions by the permission table.
+///
+/// The table is read from the JSON file in PERMISSIONS_FILE, mapping role
+/// names to permission names:
+///     {
+///         "PLATFORM_ADMIN": ["users:read_private", "users:suspend", "users:unlock", "users:revoke_sessions", "users:list", "users:restore", "users:impersonate", "audit:read"]
+///     }
+/// Roles missing from the file have no permissions. If PERMISSIONS_FILE
+/// is not set, the defaults in `PermissionTable::default` are used.
+///
+/// Routes are guarded with the `RequirePermission` middleware:
+///     web::resource("/profile/suspendUser")
+///         .wrap(RequirePermission::new(Permission::UsersSuspend))
+///
+/// For handlers which only change behaviour based on permissions,
+/// use `AuthInfo::has_permission` instead.
+
+lazy_static! {
+    pub static ref PERMISSIONS: PermissionTable = PermissionTable::from_env()
+        .unwrap_or_else(|e| panic!("Error loading permissions: {}", e));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    /// Read private user fields (email, stripe ids...) of other users
+    #[serde(rename = "users:read_private")]
+    UsersReadPrivate,
+    /// Suspend and unsuspend users
+    #[serde(rename = "users:suspend")]
+    UsersSuspend,
+    /// Clear login lockouts
+    #[serde(rename = "users:unlock")]
+    UsersUnlock,
+    /// Sign out other users' sessions
+    #[serde(rename = "users:revoke_sessions")]
+    UsersRevokeSessions,
+    /// List and search all users at /auth/admin/users
+    #[serde(rename = "users:list")]
+    UsersList,
+    /// Restore deleted accounts during their grace period
+    #[serde(rename = "users:restore")]
+    UsersRestore,
+    /// Get tokens to act as other users, see `auth::impersonation`
+    #[serde(rename = "users:impersonate")]
+    UsersImpersonate,
+    /// Search the security audit log, see `audit`
+    #[serde(rename = "audit:read")]
+    AuditRead,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Permission::UsersReadPrivate => "users:read_private",
+            Permission::UsersSuspend => "users:suspend",
+            Permission::UsersUnlock => "users:unlock",
+            Permission::UsersRevokeSessions => "users:revoke_sessions",
+            Permission::UsersList => "users:list",
+            Permission::UsersRestore => "users:restore",
+            Permission::UsersImpersonate => "users:impersonate",
+            Permission::AuditRead => "audit:read",
+        }
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PermissionTable {
+    roles: HashMap<String, HashSet<Permission>>,
+}
+
+impl PermissionTable {
+
+    pub fn from_env() -> Result<Self, failure::Error> {
+        dotenv::dotenv().ok();
+        match std::env::var("PERMISSIONS_FILE").ok() {
+            None => Ok(PermissionTable::default()),
+            Some(path) => {
+                let table = PermissionTable::from_json(&std::fs::read_to_string(&path)?)?;
+                info!("Loaded permissions from {}: {:?}", path, table.roles);
+                Ok(table)
+            }
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        let roles: HashMap<String, HashSet<Permission>> = serde_json::from_str(json)?;
+        for role in roles.keys() {
+            // UserRole::from(String) falls back to ANON for unknown roles,
+            // so check the name round-trips to catch typos in the config.
+            if format!("{:?}", UserRole::from(role.clone())) != *role {
+                return Err(format_err!("Unknown role in permissions: {}", role))
+            }
+        }
+        Ok(PermissionTable { roles: roles })
+    }
+
+    pub fn has_permission(&self, role: &UserRole, permission: Permission) -> bool {
+        self.roles
+            .get(&format!("{:?}", role))
+            .map(|permissions| permissions.contains(&permission))
+            .unwrap_or(false)
+    }
+
+    pub fn permissions(&self, role: &UserRole) -> Vec<Permission> {
+        let mut permissions = self.roles
+            .get(&format!("{:?}", role))
+            .map(|p| p.iter().cloned().collect::<Vec<Permission>>())
+            .unwrap_or_default();
+        permissions.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        permissions
+    }
+}
+
+impl std::default::Default for PermissionTable {
+    fn default() -> Self {
+        let mut roles = HashMap::new();
+        roles.insert(
+            format!("{:?}", UserRole::PLATFORM_ADMIN),
+            vec![
+                Permission::UsersReadPrivate,
+                Permission::UsersSuspend,
+                Permission::UsersUnlock,
+                Permission::UsersRevokeSessions,
+                Permission::UsersList,
+                Permission::UsersRestore,
+                Permission::UsersImpersonate,
+                Permission::AuditRead,
+            ].into_iter().collect::<HashSet<Permission>>()
+        );
+        // Only admins read full user rows by default. Internal services use
+        // service credentials, or are granted permissions in PERMISSIONS_FILE.
+        PermissionTable { roles: roles }
+    }
+}
+
+impl AuthInfo {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        PERMISSIONS.has_permission(&self.user_role, permission)
+    }
+
+    /// Errors with 403 Forbidden unless the user has the permission
+    pub fn require_permission(&self, permission: Permission) -> Result<(), LoginError> {
+        if self.has_permission(permission) {
+            Ok(())
+        } else {
+            Err(LoginError::Forbidden(
+                errJson!(format!("Missing permission: {}", permission))))
+        }
+    }
+}
+
+
+//////////////////////////////////////////////
+/// RequirePermission Middleware
+//////////////////////////////////////////////
+/// Authenticates the request with the `AuthInfo` extractor, then checks
+/// the user has the permission before calling the handler.
+/// The verified AuthInfo is stored in the request extensions, so handlers
+/// taking an `AuthInfo` argument do not verify the JWT a second time.
+
+pub struct RequirePermission {
+    permission: Permission,
+}
+
+impl RequirePermission {
+    pub fn new(permission: Permission) -> Self {
+        RequirePermission { permission: permission }
+    }
+}
+
+impl<S, B> Transform<S> for RequirePermission
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequirePermissionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequirePermissionMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            permission: self.permission,
+        })
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    permission: Permission,
+}
+
+impl<S, B> Service for RequirePermissionMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let permission = self.permission;
+
+        Box::pin(async move {
+            let auth_info = {
+                let http_req = req.request().clone();
+                AuthInfo::from_request(&http_req, &mut Payload::None).await?
+            };
+            auth_info.require_permission(permission)?;
+
+            req.extensions_mut().insert(auth_info);
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+
+
+#[test]
+fn default_permissions_only_grant_admins_suspend() {
+    let table = PermissionTable::default();
+    assert!(table.has_permission(&UserRole::PLATFORM_ADMIN, Permission::UsersSuspend));
+    assert!(!table.has_permission(&UserRole::SYSTEM, Permission::UsersSuspend));
+    assert!(!table.has_permission(&UserRole::USER, Permission::UsersSuspend));
+    assert!(!table.has_permission(&UserRole::DEALER, Permission::UsersReadPrivate));
+}
+
+#[test]
+fn default_permissions_dont_let_system_tokens_read_private_users() {
+    let table = PermissionTable::default();
+    assert!(!table.has_permission(&UserRole::SYSTEM, Permission::UsersReadPrivate));
+    assert_eq!(table.permissions(&UserRole::SYSTEM), vec![]);
+}
+
+#[test]
+fn loads_permissions_from_json() {
+    let table = PermissionTable::from_json(r#"{
+        "DEALER": ["users:read_private"],
+        "PLATFORM_ADMIN": ["users:read_private", "users:suspend"]
+    }"#).expect("permissions json");
+    assert!(table.has_permission(&UserRole::DEALER, Permission::UsersReadPrivate));
+    assert!(!table.has_permission(&UserRole::DEALER, Permission::UsersSuspend));
+    // roles missing from the config get nothing
+    assert_eq!(table.permissions(&UserRole::SYSTEM), vec![]);
+
+    assert!(PermissionTable::from_json(r#"{ "ADMIN": ["users:suspend"] }"#).is_err());
+    assert!(PermissionTable::from_json(r#"{ "USER": ["users:fly"] }"#).is_err());
+}
@@ -0,0 +1,435 @@
+//// External Imports
+use actix::{Handler, SyncContext, Message};
+use actix_web::{
+    http::StatusCode,
+    error::ResponseError,
+    cookie::{Cookie, SameSite},
+    HttpRequest,
+    HttpResponse,
+    Error,
+};
+use actix_identity::{Identity};
+use chrono::{Duration, Local};
+use redis::RedisResult;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::digest;
+
+//// Internal Imports
+use crate::db::DatabaseActor;
+use crate::auth::{
+    revoked_before_key,
+    parse_unix_millis,
+    revoked_by_watermark,
+    SessionClient,
+    SessionError,
+    store_session,
+    touch_session,
+    revoke_session,
+};
+use crate::models::User;
+use crate::AppState;
+
+/////////////////////////////////////////////
+/// Refresh Tokens
+/////////////////////////////////////////////
+/// Access JWTs are short-lived (see `access_token_ttl`). Sessions are kept
+/// alive with opaque refresh tokens which are stored server-side in redis.
+///
+/// Every login starts a new token "family". Each time a refresh token is used
+/// it is rotated: the old token is marked as used and a new token is issued
+/// in the same family. If a used refresh token is presented again, it has been
+/// replayed (e.g. stolen), so the whole family is revoked and the user must
+/// login again.
+///
+/// Redis keys (tokens are only ever stored as sha256 hashes):
+///     refresh:{hash}          => RefreshTokenRecord (json)
+///     refresh_used:{hash}     => "USED", set once the token is rotated
+///     refresh_family:{family} => unix time in ms the family (session) started,
+///                                deleted to revoke the family
+///
+/// Families started before the user's `revoked_before` watermark are revoked,
+/// see `auth::actor::RevokeUserTokens`.
+///
+/// A family is the user's session, its id is the `sid` of the access JWTs
+/// issued with it, see `auth::session`.
+
+pub const REFRESH_COOKIE_NAME: &str = "degen-refresh";
+const DEFAULT_REFRESH_TTL_DAYS: i64 = 30;
+
+pub fn refresh_token_ttl() -> Duration {
+    dotenv::dotenv().ok();
+    let days = std::env::var("JWT_REFRESH_TTL_DAYS").ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_REFRESH_TTL_DAYS);
+    Duration::days(days)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshTokenRecord {
+    pub user_id: String,
+    pub family_id: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+}
+
+/// Access + refresh token pair returned to clients on login
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenPair {
+    pub jwt: String,
+    pub refresh_token: String,
+    /// Access token lifetime in seconds
+    pub expires_in: i64,
+}
+
+fn generate_refresh_token() -> Result<String, RefreshTokenError> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| RefreshTokenError::Redis(String::from("Could not generate refresh token")))?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+pub fn hash_token(token: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, token.as_bytes());
+    data_encoding::HEXLOWER.encode(hash.as_ref())
+}
+
+fn refresh_key(token: &str) -> String {
+    format!("refresh:{}", hash_token(token))
+}
+
+fn refresh_used_key(token: &str) -> String {
+    format!("refresh_used:{}", hash_token(token))
+}
+
+pub fn family_key(family_id: &str) -> String {
+    format!("refresh_family:{}", family_id)
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+/// Redis commands for refresh tokens run on DatabaseActor's sync redis
+/// client, so that the multi-step checks below run in one handler.
+
+/// Issue a refresh token. Starts a new family (session) when `family_id` is None.
+/// Returns the refresh token and its family id.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IssueRefreshToken {
+    pub user_id: String,
+    pub family_id: Option<String>,
+    pub client: SessionClient,
+}
+
+impl Message for IssueRefreshToken {
+    type Result = Result<(String, String), RefreshTokenError>;
+}
+
+impl Handler<IssueRefreshToken> for DatabaseActor {
+    type Result = Result<(String, String), RefreshTokenError>;
+
+    fn handle(
+        &mut self,
+        msg: IssueRefreshToken,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<(String, String), RefreshTokenError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+        store_refresh_token(&mut conn, msg.user_id, msg.family_id, msg.client)
+    }
+}
+
+fn store_refresh_token(
+    conn: &mut redis::Connection,
+    user_id: String,
+    family_id: Option<String>,
+    client: SessionClient,
+) -> Result<(String, String), RefreshTokenError> {
+
+    let ttl = refresh_token_ttl().num_seconds();
+
+    let family_id = match family_id {
+        Some(f) => {
+            touch_session(conn, &user_id, &f, client)?;
+            f
+        },
+        None => {
+            let family_id = uuid::Uuid::new_v4().to_string();
+            let _: String = redis::cmd("SETEX")
+                .arg(family_key(&family_id))
+                .arg(ttl)
+                .arg(Local::now().timestamp_millis())
+                .query(conn)?;
+            store_session(conn, &user_id, &family_id, client)?;
+            family_id
+        }
+    };
+
+    let token = generate_refresh_token()?;
+    let now = Local::now().timestamp();
+    let record = RefreshTokenRecord {
+        user_id: user_id,
+        family_id: family_id.clone(),
+        issued_at: now,
+        expires_at: now + ttl,
+    };
+    let record_json = serde_json::to_string(&record)
+        .map_err(|e| RefreshTokenError::Redis(e.to_string()))?;
+
+    let _: String = redis::cmd("SETEX")
+        .arg(refresh_key(&token))
+        .arg(ttl)
+        .arg(record_json)
+        .query(conn)?;
+
+    Ok((token, family_id))
+}
+
+/// Exchange a refresh token for a new one in the same family.
+/// Returns the record of the presented token, and the new refresh token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RotateRefreshToken {
+    pub refresh_token: String,
+    pub client: SessionClient,
+}
+
+impl Message for RotateRefreshToken {
+    type Result = Result<(RefreshTokenRecord, String), RefreshTokenError>;
+}
+
+impl Handler<RotateRefreshToken> for DatabaseActor {
+    type Result = Result<(RefreshTokenRecord, String), RefreshTokenError>;
+
+    fn handle(
+        &mut self,
+        msg: RotateRefreshToken,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let record: RefreshTokenRecord = match redis::cmd("GET")
+            .arg(refresh_key(&msg.refresh_token))
+            .query::<Option<String>>(&mut conn)?
+        {
+            None => return Err(RefreshTokenError::Invalid),
+            Some(s) => serde_json::from_str(&s)
+                .map_err(|_| RefreshTokenError::Invalid)?,
+        };
+
+        let (family_started, revoked_before): (Option<String>, Option<String>) = redis::cmd("MGET")
+            .arg(family_key(&record.family_id))
+            .arg(revoked_before_key(&record.user_id))
+            .query(&mut conn)?;
+
+        let family_started = match family_started.as_deref().and_then(parse_unix_millis) {
+            None => return Err(RefreshTokenError::Revoked),
+            Some(t) => t,
+        };
+        let watermark = revoked_before.as_deref().and_then(parse_unix_millis);
+        if revoked_by_watermark(family_started, watermark) {
+            return Err(RefreshTokenError::Revoked)
+        }
+
+        // Mark token as used. SET NX makes this atomic across actor threads:
+        // only the first rotation wins, any later use is a replay.
+        let remaining_ttl = std::cmp::max(1, record.expires_at - Local::now().timestamp());
+        let first_use: RedisResult<Option<String>> = redis::cmd("SET")
+            .arg(refresh_used_key(&msg.refresh_token))
+            .arg("USED")
+            .arg("NX")
+            .arg("EX")
+            .arg(remaining_ttl)
+            .query(&mut conn);
+
+        match first_use? {
+            None => {
+                warn!("refresh token reused, revoking family: {}", record.family_id);
+                revoke_session(&mut conn, &record.user_id, &record.family_id)?;
+                Err(RefreshTokenError::Reused)
+            },
+            Some(_) => {
+                let (new_token, _family_id) = store_refresh_token(
+                    &mut conn,
+                    record.user_id.clone(),
+                    Some(record.family_id.clone()),
+                    msg.client,
+                )?;
+                Ok((record, new_token))
+            }
+        }
+    }
+}
+
+/// Revoke the family a refresh token belongs to, e.g. on logout.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevokeRefreshToken(pub String);
+
+impl Message for RevokeRefreshToken {
+    type Result = Result<(), RefreshTokenError>;
+}
+
+impl Handler<RevokeRefreshToken> for DatabaseActor {
+    type Result = Result<(), RefreshTokenError>;
+
+    fn handle(
+        &mut self,
+        msg: RevokeRefreshToken,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<(), RefreshTokenError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let record: Option<String> = redis::cmd("GET")
+            .arg(refresh_key(&msg.0))
+            .query(&mut conn)?;
+
+        if let Some(r) = record {
+            if let Ok(record) = serde_json::from_str::<RefreshTokenRecord>(&r) {
+                revoke_session(&mut conn, &record.user_id, &record.family_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////
+/// Helpers for handlers
+////////////////////////////////////
+
+/// Starts a new session for a user: a refresh token family,
+/// and a short-lived access JWT for it.
+/// Sets the JWT as the HttpOnly identity cookie. Attach the refresh token
+/// to the response with `refresh_cookie()`.
+pub async fn issue_token_pair(
+    req: &HttpRequest,
+    id: &Identity,
+    user: &User,
+) -> Result<TokenPair, Error> {
+
+    let (refresh_token, session_id) = AppState::databaseActor(req)
+        .send(IssueRefreshToken {
+            user_id: user.id.clone(),
+            family_id: None,
+            client: SessionClient::from_request(req),
+        })
+        .await??;
+
+    let jwt = crate::auth::create_token(
+        user.email.clone(),
+        user.id.clone(),
+        user.user_role.clone(),
+        session_id,
+    ).map_err(Error::from)?;
+
+    // Set JWT as HttpOnly cookie to pass to the client
+    id.remember(jwt.clone());
+
+    Ok(TokenPair {
+        jwt: jwt,
+        refresh_token: refresh_token,
+        expires_in: crate::auth::access_token_ttl().num_seconds(),
+    })
+}
+
+/// Reads the refresh token from its HttpOnly cookie, if any
+pub fn current_refresh_token(req: &HttpRequest) -> Option<String> {
+    req.cookie(REFRESH_COOKIE_NAME)
+        .map(|c| c.value().to_string())
+}
+
+pub fn refresh_cookie(refresh_token: &str) -> Cookie<'static> {
+    let (_secret, domain) = crate::auth::create_jwt_secret();
+    Cookie::build(REFRESH_COOKIE_NAME, refresh_token.to_string())
+        .path("/")
+        .domain(domain)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(false) // only true if https
+        .max_age(time::Duration::seconds(refresh_token_ttl().num_seconds()))
+        .finish()
+}
+
+/// Expired cookie, used to clear the refresh token on logout
+pub fn removal_refresh_cookie() -> Cookie<'static> {
+    let (_secret, domain) = crate::auth::create_jwt_secret();
+    let mut cookie = Cookie::build(REFRESH_COOKIE_NAME, "")
+        .path("/")
+        .domain(domain)
+        .finish();
+    cookie.make_removal();
+    cookie
+}
+
+#[derive(Debug, Fail, Serialize)]
+pub enum RefreshTokenError {
+    #[fail(display = "{{\"status\":\"Refresh token invalid or expired, please login\"}}")]
+    Invalid,
+    #[fail(display = "{{\"status\":\"Refresh token revoked, please login\"}}")]
+    Revoked,
+    #[fail(display = "{{\"status\":\"Refresh token reused, session revoked\"}}")]
+    Reused,
+    #[fail(display = "{{\"redis_error\": \"{}\"}}", _0)]
+    Redis(String),
+}
+
+impl From<redis::RedisError> for RefreshTokenError {
+    fn from(e: redis::RedisError) -> Self {
+        RefreshTokenError::Redis(e.to_string())
+    }
+}
+
+impl From<SessionError> for RefreshTokenError {
+    fn from(e: SessionError) -> Self {
+        RefreshTokenError::Redis(e.to_string())
+    }
+}
+
+impl ResponseError for RefreshTokenError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            RefreshTokenError::Invalid => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "INVALID",
+                    "message": "Refresh token invalid or expired, login again."
+                }))
+            },
+            RefreshTokenError::Revoked => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "REVOKED",
+                    "message": "Refresh token revoked, login again."
+                }))
+            },
+            RefreshTokenError::Reused => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "REUSED",
+                    "message": "Refresh token was already used, all sessions in this family are revoked. Login again."
+                }))
+            },
+            RefreshTokenError::Redis(s) => {
+                warn!("{}", s);
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            },
+       }
+    }
+}
+
+
+#[test]
+fn generates_unique_url_safe_refresh_tokens() {
+    let t1 = generate_refresh_token().expect("refresh token");
+    let t2 = generate_refresh_token().expect("refresh token");
+    assert_ne!(t1, t2);
+    // 32 bytes => 43 base64 chars without padding
+    assert_eq!(t1.len(), 43);
+    assert!(!t1.contains("+") && !t1.contains("/") && !t1.contains("="));
+}
+
+#[test]
+fn stores_refresh_tokens_as_hashes() {
+    let token = "some-refresh-token";
+    assert_eq!(refresh_key(token), refresh_key(token));
+    assert!(!refresh_key(token).contains(token));
+}
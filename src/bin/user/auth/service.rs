@@ -0,0 +1,118 @@
+use actix_web::{
+    dev::Payload,
+    http::{header, StatusCode},
+    error::ResponseError,
+    Error,
+    FromRequest,
+    HttpRequest,
+    HttpResponse,
+};
+use futures::future::{ready, Ready};
+use ring::constant_time::verify_slices_are_equal;
+
+//////////////////////////////////////////////
+/// Service-to-service authentication
+//////////////////////////////////////////////
+/// Internal services (gateway, payments, shopping...) authenticate with
+/// HTTP Basic auth: `Authorization: Basic base64(client_id:client_secret)`.
+/// Clients are configured in SERVICE_CLIENTS as a comma separated list:
+///     SERVICE_CLIENTS="gateway:secret1,dt-payments:secret2"
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAuth {
+    pub client_id: String,
+}
+
+impl ServiceAuth {
+    pub fn from_request_headers(req: &HttpRequest) -> Result<Self, ServiceAuthError> {
+
+        let credentials = req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| {
+                if h.starts_with("Basic ") {
+                    Some(h.trim_start_matches("Basic ").trim())
+                } else {
+                    None
+                }
+            })
+            .ok_or(ServiceAuthError::Missing)?;
+
+        let decoded = base64::decode(credentials)
+            .map_err(|_| ServiceAuthError::Invalid)?;
+        let decoded = String::from_utf8(decoded)
+            .map_err(|_| ServiceAuthError::Invalid)?;
+
+        let mut parts = decoded.splitn(2, ":");
+        let client_id = parts.next().unwrap_or("");
+        let client_secret = parts.next().unwrap_or("");
+
+        match service_clients().iter().find(|(id, _)| id == client_id) {
+            Some((id, secret)) if verify_slices_are_equal(
+                secret.as_bytes(),
+                client_secret.as_bytes()
+            ).is_ok() => Ok(ServiceAuth { client_id: id.clone() }),
+            _ => Err(ServiceAuthError::Invalid),
+        }
+    }
+}
+
+impl FromRequest for ServiceAuth {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(ServiceAuth::from_request_headers(req).map_err(Error::from))
+    }
+}
+
+fn service_clients() -> Vec<(String, String)> {
+    dotenv::dotenv().ok();
+    std::env::var("SERVICE_CLIENTS")
+        .unwrap_or_default()
+        .split(",")
+        .filter_map(|client| {
+            let mut parts = client.trim().splitn(2, ":");
+            match (parts.next(), parts.next()) {
+                (Some(id), Some(secret)) if !id.is_empty() && !secret.is_empty() => {
+                    Some((id.to_string(), secret.to_string()))
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Fail, Serialize)]
+pub enum ServiceAuthError {
+    #[fail(display = "{{\"status\":\"Service credentials missing\"}}")]
+    Missing,
+    #[fail(display = "{{\"status\":\"Service credentials invalid\"}}")]
+    Invalid,
+}
+
+impl ResponseError for ServiceAuthError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            ServiceAuthError::Missing => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .header(header::WWW_AUTHENTICATE, "Basic realm=\"dt-user\"")
+                .json(json!({
+                    "status": "MISSING",
+                    "message": "Service credentials missing from Authorization header."
+                }))
+            },
+            ServiceAuthError::Invalid => {
+                warn!("invalid service credentials");
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .header(header::WWW_AUTHENTICATE, "Basic realm=\"dt-user\"")
+                .json(json!({
+                    "status": "INVALID",
+                    "message": "Service credentials invalid."
+                }))
+            },
+       }
+    }
+}
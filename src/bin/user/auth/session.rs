@@ -0,0 +1,341 @@
+//// External Imports
+use actix::{Handler, SyncContext, Message};
+use actix_web::{
+    http::{header, StatusCode},
+    error::ResponseError,
+    HttpRequest,
+    HttpResponse,
+};
+use chrono::Local;
+
+//// Internal Imports
+use crate::db::DatabaseActor;
+use crate::auth::{
+    client_ip,
+    family_key,
+    refresh_token_ttl,
+    revoked_before_key,
+    parse_unix_millis,
+    revoked_by_watermark,
+};
+
+/////////////////////////////////////////////
+/// Sessions
+/////////////////////////////////////////////
+/// Every login starts a session, which is a refresh token family
+/// (see `auth::refresh`). Access JWTs carry the session id in their `sid`
+/// claim, and stop working as soon as their session is revoked.
+///
+/// Redis keys:
+///     session:{sid}           => SessionRecord (json), expires with the family
+///     user_sessions:{user_id} => set of the user's session ids
+///
+/// The IP, user agent and last seen time are updated each time the session
+/// is refreshed, so they lag by at most one access token lifetime.
+///
+/// Revoking a session deletes its refresh family and record. Sessions revoked
+/// by the user's `revoked_before` watermark (password changes, suspensions)
+/// are cleaned up the next time sessions are listed.
+
+const USER_AGENT_MAX_LEN: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRecord {
+    pub id: String,
+    pub user_id: String,
+    /// unix timestamps
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Where a session is being used from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl SessionClient {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        SessionClient {
+            user_agent: req.headers()
+                .get(header::USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(|ua| ua.chars().take(USER_AGENT_MAX_LEN).collect::<String>()),
+            ip: client_ip(req),
+        }
+    }
+}
+
+pub fn session_key(session_id: &str) -> String {
+    format!("session:{}", session_id)
+}
+
+pub fn user_sessions_key(user_id: &str) -> String {
+    format!("user_sessions:{}", user_id)
+}
+
+/// Records a new session, called when a refresh token family is started
+pub fn store_session(
+    conn: &mut redis::Connection,
+    user_id: &str,
+    session_id: &str,
+    client: SessionClient,
+) -> Result<(), SessionError> {
+
+    let now = Local::now().timestamp();
+    let record = SessionRecord {
+        id: session_id.to_string(),
+        user_id: user_id.to_string(),
+        created_at: now,
+        last_seen_at: now,
+        user_agent: client.user_agent,
+        ip: client.ip,
+    };
+    save_session(conn, &record)
+}
+
+/// Updates the last seen time, IP and user agent when a session is refreshed
+pub fn touch_session(
+    conn: &mut redis::Connection,
+    user_id: &str,
+    session_id: &str,
+    client: SessionClient,
+) -> Result<(), SessionError> {
+
+    match get_session(conn, session_id)? {
+        // Families started before sessions were recorded
+        None => store_session(conn, user_id, session_id, client),
+        Some(mut record) => {
+            record.last_seen_at = Local::now().timestamp();
+            record.user_agent = client.user_agent.or(record.user_agent);
+            record.ip = client.ip.or(record.ip);
+            save_session(conn, &record)
+        }
+    }
+}
+
+fn save_session(
+    conn: &mut redis::Connection,
+    record: &SessionRecord,
+) -> Result<(), SessionError> {
+
+    let ttl = refresh_token_ttl().num_seconds();
+    let record_json = serde_json::to_string(record)
+        .map_err(|e| SessionError::Redis(e.to_string()))?;
+
+    let _: () = redis::pipe()
+        .atomic()
+        .cmd("SETEX").arg(session_key(&record.id)).arg(ttl).arg(record_json).ignore()
+        .cmd("SADD").arg(user_sessions_key(&record.user_id)).arg(&record.id).ignore()
+        .cmd("EXPIRE").arg(user_sessions_key(&record.user_id)).arg(ttl).ignore()
+        .query(conn)?;
+    Ok(())
+}
+
+fn get_session(
+    conn: &mut redis::Connection,
+    session_id: &str,
+) -> Result<Option<SessionRecord>, SessionError> {
+
+    let record: Option<String> = redis::cmd("GET")
+        .arg(session_key(session_id))
+        .query(conn)?;
+    Ok(record.and_then(|r| serde_json::from_str::<SessionRecord>(&r).ok()))
+}
+
+/// Ends a session: its refresh tokens stop working,
+/// and `CheckJwt` rejects its access tokens.
+pub fn revoke_session(
+    conn: &mut redis::Connection,
+    user_id: &str,
+    session_id: &str,
+) -> Result<(), SessionError> {
+
+    let _: () = redis::pipe()
+        .atomic()
+        .cmd("DEL").arg(family_key(session_id)).ignore()
+        .cmd("DEL").arg(session_key(session_id)).ignore()
+        .cmd("SREM").arg(user_sessions_key(user_id)).arg(session_id).ignore()
+        .query(conn)?;
+    debug!("revoked session {} for user: {}", session_id, user_id);
+    Ok(())
+}
+
+/// The user's live sessions, most recently seen first.
+/// Drops sessions which have expired or were revoked by the watermark.
+fn live_sessions(
+    conn: &mut redis::Connection,
+    user_id: &str,
+) -> Result<Vec<SessionRecord>, SessionError> {
+
+    let session_ids: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(user_sessions_key(user_id))
+        .query(conn)?;
+
+    let revoked_before: Option<i64> = redis::cmd("GET")
+        .arg(revoked_before_key(user_id))
+        .query::<Option<String>>(conn)?
+        .as_deref()
+        .and_then(parse_unix_millis);
+
+    let mut sessions = vec![];
+    for session_id in session_ids {
+        let family_started: Option<String> = redis::cmd("GET")
+            .arg(family_key(&session_id))
+            .query(conn)?;
+
+        let is_live = family_started
+            .as_deref()
+            .and_then(parse_unix_millis)
+            .map(|started| !revoked_by_watermark(started, revoked_before))
+            .unwrap_or(false);
+
+        match get_session(conn, &session_id)? {
+            Some(record) if is_live => sessions.push(record),
+            _ => revoke_session(conn, user_id, &session_id)?,
+        }
+    }
+
+    sessions.sort_by(|a, b| b.last_seen_at.cmp(&a.last_seen_at));
+    Ok(sessions)
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+/// Lists a user's sessions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ListSessions(pub String);
+
+impl Message for ListSessions {
+    type Result = Result<Vec<SessionRecord>, SessionError>;
+}
+
+impl Handler<ListSessions> for DatabaseActor {
+    type Result = Result<Vec<SessionRecord>, SessionError>;
+
+    fn handle(
+        &mut self,
+        msg: ListSessions,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+        live_sessions(&mut conn, &msg.0)
+    }
+}
+
+/// Revokes one of a user's sessions.
+/// Errors with NotFound if the session isn't the user's.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevokeSession {
+    pub user_id: String,
+    pub session_id: String,
+}
+
+impl Message for RevokeSession {
+    type Result = Result<(), SessionError>;
+}
+
+impl Handler<RevokeSession> for DatabaseActor {
+    type Result = Result<(), SessionError>;
+
+    fn handle(
+        &mut self,
+        msg: RevokeSession,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let is_member: bool = redis::cmd("SISMEMBER")
+            .arg(user_sessions_key(&msg.user_id))
+            .arg(&msg.session_id)
+            .query(&mut conn)?;
+
+        if !is_member {
+            return Err(SessionError::NotFound)
+        }
+        revoke_session(&mut conn, &msg.user_id, &msg.session_id)
+    }
+}
+
+/// Revokes all of a user's sessions, except `keep` (e.g. the current one).
+/// Returns how many were revoked.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevokeSessions {
+    pub user_id: String,
+    pub keep: Option<String>,
+}
+
+impl Message for RevokeSessions {
+    type Result = Result<usize, SessionError>;
+}
+
+impl Handler<RevokeSessions> for DatabaseActor {
+    type Result = Result<usize, SessionError>;
+
+    fn handle(
+        &mut self,
+        msg: RevokeSessions,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()?;
+
+        let session_ids: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(user_sessions_key(&msg.user_id))
+            .query(&mut conn)?;
+
+        let mut revoked = 0;
+        for session_id in session_ids {
+            if Some(&session_id) != msg.keep.as_ref() {
+                revoke_session(&mut conn, &msg.user_id, &session_id)?;
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+}
+
+#[derive(Debug, Fail, Serialize)]
+pub enum SessionError {
+    #[fail(display = "{{\"status\":\"Session not found\"}}")]
+    NotFound,
+    #[fail(display = "{{\"redis_error\": \"{}\"}}", _0)]
+    Redis(String),
+}
+
+impl From<redis::RedisError> for SessionError {
+    fn from(e: redis::RedisError) -> Self {
+        SessionError::Redis(e.to_string())
+    }
+}
+
+impl ResponseError for SessionError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            SessionError::NotFound => {
+                HttpResponse::build(StatusCode::NOT_FOUND)
+                .json(json!({
+                    "status": "NOT_FOUND",
+                    "message": "Session not found, it may have already ended."
+                }))
+            },
+            SessionError::Redis(s) => {
+                warn!("{}", s);
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            },
+       }
+    }
+}
+
+
+#[test]
+fn session_keys_are_namespaced() {
+    assert_eq!(session_key("abc"), "session:abc");
+    assert_eq!(user_sessions_key("u123"), "user_sessions:u123");
+}
@@ -0,0 +1,110 @@
+//// External Imports
+use actix::{Addr, Handler, Message, SyncContext};
+
+//// Internal Imports
+use crate::db::{
+    DatabaseActor,
+    getExpiredSuspensions,
+    liftSuspension,
+    insertAuditEvent,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    AuditAction,
+    AuditOutcome,
+    NewAuditEvent,
+};
+
+/////////////////////////////////////////////
+/// Suspension Expiry
+/////////////////////////////////////////////
+/// Suspensions with an `until` time are lifted by a background task, which
+/// checks every SUSPENSION_LIFT_INTERVAL_SECONDS (default 60).
+/// Logins between the expiry and the next check lift it themselves,
+/// see `checkSuspension`.
+
+const DEFAULT_SUSPENSION_LIFT_INTERVAL_SECONDS: u64 = 60;
+
+fn suspension_lift_interval() -> std::time::Duration {
+    let seconds = std::env::var("SUSPENSION_LIFT_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(DEFAULT_SUSPENSION_LIFT_INTERVAL_SECONDS);
+    std::time::Duration::from_secs(seconds)
+}
+
+/// Runs for as long as the server, call from main
+pub fn spawn_suspension_lifter(database_actor: Addr<DatabaseActor>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(suspension_lift_interval());
+        loop {
+            interval.tick().await;
+            match database_actor.send(LiftExpiredSuspensions).await {
+                Ok(Ok(0)) => {},
+                Ok(Ok(lifted)) => info!("lifted {} expired suspensions", lifted),
+                Ok(Err(e)) => warn!("error lifting expired suspensions: {}", e),
+                Err(e) => warn!("error lifting expired suspensions: {}", e),
+            }
+        }
+    });
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+/// Lifts every suspension past its `until` time, returns how many were lifted.
+/// Ones which fail are logged and skipped.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LiftExpiredSuspensions;
+
+impl Message for LiftExpiredSuspensions {
+    type Result = Result<usize, LoginError>;
+}
+
+impl Handler<LiftExpiredSuspensions> for DatabaseActor {
+    type Result = Result<usize, LoginError>;
+
+    fn handle(
+        &mut self,
+        _msg: LiftExpiredSuspensions,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let conn = self.pool.get()
+            .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+        let expired = getExpiredSuspensions(&conn)?;
+        let mut lifted = 0;
+        for suspension in &expired {
+            // One failure shouldn't hold up the others, it's retried next run
+            match liftSuspension(&conn, suspension, None) {
+                Ok(Some(_user)) => {},
+                // Replaced or lifted by an admin since it was loaded
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("suspension {} not lifted for user {}: {}",
+                        suspension.id, suspension.user_id, e);
+                    continue
+                }
+            }
+            lifted += 1;
+            debug!("suspension {} expired for user: {}", suspension.id, suspension.user_id);
+
+            let event = NewAuditEvent {
+                id: format!("aud_{}", uuid::Uuid::new_v4()),
+                actor_id: None,
+                user_id: Some(suspension.user_id.clone()),
+                action: AuditAction::Unsuspend.as_str().to_string(),
+                outcome: AuditOutcome::Success.as_str().to_string(),
+                ip: None,
+                user_agent: None,
+                payload: json!({ "suspensionId": suspension.id, "expired": true }),
+            };
+            if let Err(e) = insertAuditEvent(&conn, event) {
+                warn!("audit event not recorded: {}", e);
+            }
+        }
+        Ok(lifted)
+    }
+}
@@ -0,0 +1,171 @@
+use data_encoding::BASE32_NOPAD;
+use ring::constant_time::verify_slices_are_equal;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::auth::hash_token;
+
+//////////////////////////////////////////////
+/// TOTP (RFC 6238)
+//////////////////////////////////////////////
+/// Time-based one time passwords, compatible with Google Authenticator,
+/// Authy, 1Password etc: HMAC-SHA1, 30 second steps, 6 digit codes.
+/// Secrets are 160 random bits, shared with the app as base32.
+
+pub const TOTP_STEP_SECONDS: i64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+/// Also accept codes from one step either side of now, for clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+const TOTP_SECRET_LEN: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+
+fn random_bytes(len: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new().fill(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// New base32 encoded TOTP secret
+pub fn generate_totp_secret() -> Option<String> {
+    random_bytes(TOTP_SECRET_LEN).map(|b| BASE32_NOPAD.encode(&b))
+}
+
+/// HOTP (RFC 4226) code for a counter
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let hash = tag.as_ref();
+
+    // dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+
+    format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+}
+
+pub fn totp_step(unix_time: i64) -> i64 {
+    unix_time / TOTP_STEP_SECONDS
+}
+
+/// Checks a code against a base32 secret.
+/// Returns the time step the code matched, which the caller must record
+/// so the same code can't be used again.
+pub fn verify_totp(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+
+    if code.len() != TOTP_DIGITS as usize {
+        return None
+    }
+
+    let step = totp_step(unix_time);
+    (step - TOTP_SKEW_STEPS..=step + TOTP_SKEW_STEPS)
+        .filter(|s| *s >= 0)
+        .find(|s| verify_slices_are_equal(
+            hotp(&secret, *s as u64, TOTP_DIGITS).as_bytes(),
+            code.as_bytes(),
+        ).is_ok())
+}
+
+/// Key URI for authenticator apps, usually shown as a QR code:
+/// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_STEP_SECONDS,
+    )
+}
+
+pub fn totp_issuer() -> String {
+    dotenv::dotenv().ok();
+    std::env::var("TOTP_ISSUER")
+        .unwrap_or(String::from("DegenTracker"))
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            },
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// One-time recovery codes, formatted for reading: "abcd-efgh-ijkl-mnop"
+/// Only their hashes are stored, see `hash_recovery_code`.
+pub fn generate_recovery_codes() -> Option<Vec<String>> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = BASE32_NOPAD.encode(&random_bytes(RECOVERY_CODE_LEN)?)
+                .to_lowercase();
+            Some(code.as_bytes()
+                .chunks(4)
+                .map(|c| String::from_utf8_lossy(c).to_string())
+                .collect::<Vec<String>>()
+                .join("-"))
+        })
+        .collect()
+}
+
+/// Recovery codes are typed in by hand: ignore case, dashes and spaces
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized = code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    hash_token(&normalized)
+}
+
+
+
+#[test]
+fn hotp_matches_rfc6238_test_vectors() {
+    // RFC 6238 Appendix B, SHA1 with 8 digits
+    let secret = b"12345678901234567890";
+    let vectors = vec![
+        (59, "94287082"),
+        (1111111109, "07081804"),
+        (1111111111, "14050471"),
+        (1234567890, "89005924"),
+        (2000000000, "69279037"),
+        (20000000000, "65353130"),
+    ];
+    for (time, code) in vectors {
+        assert_eq!(hotp(secret, totp_step(time) as u64, 8), code);
+    }
+}
+
+#[test]
+fn verify_totp_accepts_adjacent_steps_only() {
+    let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+    let code = hotp(b"12345678901234567890", totp_step(1111111109) as u64, TOTP_DIGITS);
+
+    assert_eq!(verify_totp(&secret, &code, 1111111109), Some(totp_step(1111111109)));
+    assert!(verify_totp(&secret, &code, 1111111109 + TOTP_STEP_SECONDS).is_some());
+    assert!(verify_totp(&secret, &code, 1111111109 + 3 * TOTP_STEP_SECONDS).is_none());
+    assert!(verify_totp(&secret, "12345", 1111111109).is_none());
+}
+
+#[test]
+fn recovery_codes_hash_ignoring_formatting() {
+    let codes = generate_recovery_codes().expect("recovery codes");
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+    assert_eq!(
+        hash_recovery_code(&codes[0]),
+        hash_recovery_code(&codes[0].to_uppercase().replace("-", " ")),
+    );
+}
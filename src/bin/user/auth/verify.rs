@@ -0,0 +1,77 @@
+use actix_web::{
+    Error,
+    HttpRequest,
+};
+
+use crate::auth::{
+    Claims,
+    CheckJwt,
+    decode_claims,
+    SESSION_SCOPE,
+};
+use crate::db::{
+    getUser,
+    GetPool,
+    checkSuspension,
+};
+use crate::models::{
+    User,
+    LoginError,
+    ErrJson,
+};
+use crate::AppState;
+
+/// Fully verifies an access JWT:
+/// 1. signature and expiry, and that it is a session token
+///    (not e.g. an "mfa_pending" token),
+/// 2. not revoked (by jti, or by the user's revocation watermark),
+/// 3. the user still exists, and is not suspended or deleted.
+///    Suspended users are told why, even though their tokens are revoked.
+pub async fn verify_jwt(
+    req: &HttpRequest,
+    jwt: &str,
+) -> Result<(Claims, User), Error> {
+
+    let claims: Claims = decode_claims(jwt)
+        .map_err(Error::from)?;
+
+    if !claims.has_scope(SESSION_SCOPE) {
+        return Err(Error::from(
+            LoginError::Unauthorized(errJson!("JWT is not a session token, login again"))
+        ))
+    }
+
+    let conn = AppState::databaseActor(req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // Check if JWT exists in blacklist, return early with error if so.
+    let claims = match AppState::databaseActor(req)
+                .send(CheckJwt(claims.clone()))
+                .await? {
+        Ok(claims) => claims,
+        Err(e) => {
+            // Suspending revokes the user's tokens,
+            // so tell them why instead of asking them to login again
+            if let Ok(user) = getUser(&conn, None, Some(&claims.sub)) {
+                checkSuspension(&conn, user)?;
+            }
+            return Err(Error::from(e))
+        }
+    };
+
+    let user: User = getUser(&conn, None, Some(&claims.sub))
+        .map_err(Error::from)?;
+
+    // Tells suspended users why, and until when
+    let user = checkSuspension(&conn, user)
+        .map_err(Error::from)?;
+
+    if user.is_deleted {
+        return Err(Error::from(
+            LoginError::Suspended(ErrJson::new("User is deleted"))
+        ))
+    }
+
+    Ok((claims, user))
+}
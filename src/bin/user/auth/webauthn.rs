@@ -0,0 +1,747 @@
+use actix_web::{
+    http::StatusCode,
+    error::ResponseError,
+    HttpResponse,
+};
+use ring::digest;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+use crate::models::email_identity;
+
+//////////////////////////////////////////////
+/// WebAuthn / Passkeys
+//////////////////////////////////////////////
+/// Users can register FIDO2 authenticators (passkeys, security keys,
+/// Touch ID...) and login with them instead of a password.
+///
+/// Only what we need from the spec (https://www.w3.org/TR/webauthn-2/)
+/// is implemented:
+/// - "none" attestation: we don't check which authenticator model is used,
+/// - ES256 and RS256 public keys,
+/// - user verification (PIN, biometrics) is always required, so a passkey
+///   login is as strong as a password + 2FA login.
+///
+/// Each ceremony is two requests. The first returns options with a random
+/// challenge for `navigator.credentials.create()` or `.get()`, and stores the
+/// challenge in redis (through RedisActor) for CHALLENGE_TTL_SECONDS:
+///     webauthn_challenge:{challenge_id} => WebauthnChallenge (json)
+/// The second request posts the authenticator's response with the
+/// challenge_id, and the challenge is deleted as it is read (single use).
+///
+/// Binary fields in requests and responses are base64url without padding.
+///
+/// Relying party config:
+///     WEBAUTHN_RP_ID      domain passkeys are bound to, e.g. degentracker.com
+///     WEBAUTHN_RP_NAME    name shown by the browser
+///     WEBAUTHN_RP_ORIGIN  origin of the frontend, e.g. https://www.degentracker.com
+///     WEBAUTHN_FAKE_ID_SECRET  key for `fake_credential_ids`, the same on every instance
+
+pub const CHALLENGE_TTL_SECONDS: i32 = 300;
+
+const COSE_ALG_ES256: i128 = -7;
+const COSE_ALG_RS256: i128 = -257;
+
+// authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn from_env() -> Self {
+        dotenv::dotenv().ok();
+        RelyingParty {
+            id: std::env::var("WEBAUTHN_RP_ID")
+                .unwrap_or(String::from("localhost")),
+            name: std::env::var("WEBAUTHN_RP_NAME")
+                .unwrap_or(String::from("DegenTracker")),
+            origin: std::env::var("WEBAUTHN_RP_ORIGIN")
+                .unwrap_or(String::from("http://localhost:3000")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChallengeKind {
+    Register,
+    Authenticate,
+}
+
+/// Challenge state kept in redis between the two requests of a ceremony
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnChallenge {
+    pub kind: ChallengeKind,
+    pub challenge: String,
+    /// The user registering, or logging in if they gave their email.
+    /// None for usernameless (discoverable passkey) logins.
+    pub user_id: Option<String>,
+}
+
+impl WebauthnChallenge {
+    pub fn new(kind: ChallengeKind, user_id: Option<String>) -> Result<Self, WebauthnError> {
+        Ok(WebauthnChallenge {
+            kind: kind,
+            challenge: b64url(&random_bytes(32)?),
+            user_id: user_id,
+        })
+    }
+}
+
+pub fn webauthn_challenge_key(challenge_id: &str) -> String {
+    format!("webauthn_challenge:{}", challenge_id)
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>, WebauthnError> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| WebauthnError::Internal(String::from("Could not generate challenge")))?;
+    Ok(bytes)
+}
+
+pub fn b64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn from_b64url(s: &str) -> Result<Vec<u8>, WebauthnError> {
+    // Some clients pad their base64url
+    base64::decode_config(s.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(|e| WebauthnError::InvalidResponse(e.to_string()))
+}
+
+/////////////////////////////////////////////
+/// Options for navigator.credentials
+/////////////////////////////////////////////
+
+/// PublicKeyCredentialCreationOptions for registering a new credential
+pub fn creation_options(
+    rp: &RelyingParty,
+    challenge: &WebauthnChallenge,
+    user_id: &str,
+    user_email: &str,
+    exclude_credential_ids: Vec<String>,
+) -> serde_json::Value {
+    json!({
+        "rp": {
+            "id": rp.id,
+            "name": rp.name,
+        },
+        "user": {
+            "id": b64url(user_id.as_bytes()),
+            "name": user_email,
+            "displayName": user_email,
+        },
+        "challenge": challenge.challenge,
+        "pubKeyCredParams": [
+            { "type": "public-key", "alg": COSE_ALG_ES256 as i64 },
+            { "type": "public-key", "alg": COSE_ALG_RS256 as i64 },
+        ],
+        "timeout": CHALLENGE_TTL_SECONDS * 1000,
+        "attestation": "none",
+        "excludeCredentials": exclude_credential_ids.iter()
+            .map(|id| json!({ "type": "public-key", "id": id }))
+            .collect::<Vec<serde_json::Value>>(),
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "requireResidentKey": false,
+            "userVerification": "required",
+        },
+    })
+}
+
+lazy_static! {
+    /// Without WEBAUTHN_FAKE_ID_SECRET each instance makes up its own key,
+    /// so fake ids change between instances and restarts, which gives them away
+    pub static ref FAKE_CREDENTIAL_KEY: hmac::Key = {
+        let secret = match std::env::var("WEBAUTHN_FAKE_ID_SECRET") {
+            Ok(secret) => secret.into_bytes(),
+            Err(_) => {
+                warn!("WEBAUTHN_FAKE_ID_SECRET not set, using a random key");
+                random_bytes(32).expect("random WebAuthn fake id key")
+            }
+        };
+        hmac::Key::new(hmac::HMAC_SHA256, &secret)
+    };
+}
+
+/// Credential ids for the login options of emails with no passkeys, or no
+/// account, so they look like a user's with passkeys. They're always the same
+/// for an email, as real ids would be, and match no credential.
+pub fn fake_credential_ids(email: &str) -> Vec<String> {
+    let identity = email_identity(email);
+    let tag = hmac::sign(&FAKE_CREDENTIAL_KEY, identity.as_bytes());
+    let count = 1 + (tag.as_ref()[0] % 2) as usize;
+    (0..count)
+        .map(|i| {
+            let id = hmac::sign(&FAKE_CREDENTIAL_KEY, format!("{}:{}", identity, i).as_bytes());
+            b64url(id.as_ref())
+        })
+        .collect()
+}
+
+/// PublicKeyCredentialRequestOptions for logging in.
+/// An empty allow list lets the user pick any passkey for this site.
+pub fn request_options(
+    rp: &RelyingParty,
+    challenge: &WebauthnChallenge,
+    allow_credential_ids: Vec<String>,
+) -> serde_json::Value {
+    json!({
+        "rpId": rp.id,
+        "challenge": challenge.challenge,
+        "timeout": CHALLENGE_TTL_SECONDS * 1000,
+        "allowCredentials": allow_credential_ids.iter()
+            .map(|id| json!({ "type": "public-key", "id": id }))
+            .collect::<Vec<serde_json::Value>>(),
+        "userVerification": "required",
+    })
+}
+
+/////////////////////////////////////////////
+/// Authenticator responses
+/////////////////////////////////////////////
+
+/// Result of navigator.credentials.create()
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterCredential {
+    pub id: String,
+    pub raw_id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// Result of navigator.credentials.get()
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionCredential {
+    pub id: String,
+    pub raw_id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+/// A newly registered credential, to be stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedCredential {
+    /// base64url credential ID
+    pub credential_id: String,
+    /// COSE_Key encoded public key
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// (credential ID, COSE public key), only present when registering
+    attested_credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData, WebauthnError> {
+    if bytes.len() < 37 {
+        return Err(WebauthnError::InvalidResponse(String::from("authenticator data too short")))
+    }
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // aaguid (16 bytes), credential ID length (2 bytes), credential ID,
+        // then the CBOR public key (followed by extensions, if any)
+        let rest = &bytes[37..];
+        if rest.len() < 18 {
+            return Err(WebauthnError::InvalidResponse(String::from("attested credential data too short")))
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        if rest.len() < 18 + id_len {
+            return Err(WebauthnError::InvalidResponse(String::from("credential ID too short")))
+        }
+        let credential_id = rest[18..18 + id_len].to_vec();
+
+        let key_bytes = &rest[18 + id_len..];
+        let mut deserializer = serde_cbor::Deserializer::from_slice(key_bytes);
+        let _key: Value = serde::Deserialize::deserialize(&mut deserializer)
+            .map_err(|e| WebauthnError::InvalidResponse(e.to_string()))?;
+        let key_len = deserializer.byte_offset();
+
+        Some((credential_id, key_bytes[..key_len].to_vec()))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: bytes[..32].to_vec(),
+        flags: flags,
+        sign_count: sign_count,
+        attested_credential: attested_credential,
+    })
+}
+
+fn check_client_data(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    ceremony: &str,
+    challenge: &WebauthnChallenge,
+) -> Result<(), WebauthnError> {
+
+    let client_data: CollectedClientData = serde_json::from_slice(client_data_json)
+        .map_err(|e| WebauthnError::InvalidResponse(e.to_string()))?;
+
+    if client_data.ceremony != ceremony {
+        return Err(WebauthnError::InvalidResponse(format!("expected {}", ceremony)))
+    }
+    // Both are base64url of the same bytes, but compare decoded
+    // in case the client padded it.
+    if from_b64url(&client_data.challenge)? != from_b64url(&challenge.challenge)? {
+        return Err(WebauthnError::InvalidResponse(String::from("challenge does not match")))
+    }
+    if client_data.origin != rp.origin {
+        return Err(WebauthnError::InvalidResponse(format!("unexpected origin {}", client_data.origin)))
+    }
+    Ok(())
+}
+
+fn check_authenticator_data(
+    rp: &RelyingParty,
+    auth_data: &AuthenticatorData,
+) -> Result<(), WebauthnError> {
+
+    if auth_data.rp_id_hash != digest::digest(&digest::SHA256, rp.id.as_bytes()).as_ref() {
+        return Err(WebauthnError::InvalidResponse(String::from("credential is for another site")))
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebauthnError::InvalidResponse(String::from("user not present")))
+    }
+    if auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebauthnError::InvalidResponse(String::from("user not verified")))
+    }
+    Ok(())
+}
+
+/// Verifies the response to `creation_options`
+pub fn verify_registration(
+    rp: &RelyingParty,
+    challenge: &WebauthnChallenge,
+    credential: &RegisterCredential,
+) -> Result<VerifiedCredential, WebauthnError> {
+
+    if challenge.kind != ChallengeKind::Register {
+        return Err(WebauthnError::ChallengeExpired)
+    }
+
+    let client_data_json = from_b64url(&credential.response.client_data_json)?;
+    check_client_data(rp, &client_data_json, "webauthn.create", challenge)?;
+
+    let attestation: BTreeMap<String, Value> = serde_cbor::from_slice(
+        &from_b64url(&credential.response.attestation_object)?
+    ).map_err(|e| WebauthnError::InvalidResponse(e.to_string()))?;
+
+    match attestation.get("fmt") {
+        Some(Value::Text(fmt)) if fmt == "none" => {},
+        fmt => return Err(WebauthnError::Unsupported(format!("attestation format {:?}", fmt))),
+    }
+
+    let auth_data = match attestation.get("authData") {
+        Some(Value::Bytes(bytes)) => parse_authenticator_data(bytes)?,
+        _ => return Err(WebauthnError::InvalidResponse(String::from("missing authData"))),
+    };
+    check_authenticator_data(rp, &auth_data)?;
+
+    let (credential_id, public_key) = auth_data.attested_credential
+        .ok_or(WebauthnError::InvalidResponse(String::from("missing attested credential")))?;
+
+    if credential_id != from_b64url(&credential.raw_id)? {
+        return Err(WebauthnError::InvalidResponse(String::from("credential ID does not match")))
+    }
+
+    // Reject keys we can't verify assertions with, now rather than at login
+    CosePublicKey::from_cbor(&public_key)?;
+
+    Ok(VerifiedCredential {
+        credential_id: b64url(&credential_id),
+        public_key: public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Verifies the response to `request_options`, signed by a stored credential.
+/// Returns the authenticator's new signature counter.
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    challenge: &WebauthnChallenge,
+    credential: &AssertionCredential,
+    public_key: &[u8],
+    stored_sign_count: i64,
+) -> Result<u32, WebauthnError> {
+
+    if challenge.kind != ChallengeKind::Authenticate {
+        return Err(WebauthnError::ChallengeExpired)
+    }
+
+    let client_data_json = from_b64url(&credential.response.client_data_json)?;
+    check_client_data(rp, &client_data_json, "webauthn.get", challenge)?;
+
+    let auth_data_bytes = from_b64url(&credential.response.authenticator_data)?;
+    let auth_data = parse_authenticator_data(&auth_data_bytes)?;
+    check_authenticator_data(rp, &auth_data)?;
+
+    // signature is over authenticatorData || sha256(clientDataJSON)
+    let mut signed = auth_data_bytes.clone();
+    signed.extend_from_slice(digest::digest(&digest::SHA256, &client_data_json).as_ref());
+
+    let signature = from_b64url(&credential.response.signature)?;
+    if !CosePublicKey::from_cbor(public_key)?.verify(&signed, &signature) {
+        return Err(WebauthnError::VerificationFailed)
+    }
+
+    check_sign_count(stored_sign_count, auth_data.sign_count)?;
+    Ok(auth_data.sign_count)
+}
+
+/// Authenticators that keep a signature counter increase it on every use.
+/// A counter that goes backwards means the credential has been cloned.
+/// Passkeys synced between devices always report 0.
+pub fn check_sign_count(stored: i64, new: u32) -> Result<(), WebauthnError> {
+    if (stored != 0 || new != 0) && (new as i64) <= stored {
+        Err(WebauthnError::CloneDetected)
+    } else {
+        Ok(())
+    }
+}
+
+enum CosePublicKey {
+    Es256 { x: Vec<u8>, y: Vec<u8> },
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl CosePublicKey {
+
+    fn from_cbor(bytes: &[u8]) -> Result<Self, WebauthnError> {
+        let key: BTreeMap<i128, Value> = serde_cbor::from_slice(bytes)
+            .map_err(|e| WebauthnError::InvalidResponse(e.to_string()))?;
+
+        let int = |label: i128| match key.get(&label) {
+            Some(Value::Integer(i)) => Some(*i),
+            _ => None,
+        };
+        let bytes = |label: i128| match key.get(&label) {
+            Some(Value::Bytes(b)) => Ok(b.clone()),
+            _ => Err(WebauthnError::InvalidResponse(format!("COSE key missing {}", label))),
+        };
+
+        // 1: kty, 3: alg, -1: crv or n, -2: x or e, -3: y
+        match (int(1), int(3)) {
+            (Some(2), Some(COSE_ALG_ES256)) if int(-1) == Some(1) => {
+                Ok(CosePublicKey::Es256 { x: bytes(-2)?, y: bytes(-3)? })
+            },
+            (Some(3), Some(COSE_ALG_RS256)) => {
+                Ok(CosePublicKey::Rs256 { n: bytes(-1)?, e: bytes(-2)? })
+            },
+            (kty, alg) => Err(WebauthnError::Unsupported(
+                format!("public key type {:?} with algorithm {:?}", kty, alg)
+            )),
+        }
+    }
+
+    fn verify(&self, message: &[u8], sig: &[u8]) -> bool {
+        match self {
+            CosePublicKey::Es256 { x, y } => {
+                // uncompressed point
+                let mut point = vec![0x04];
+                point.extend_from_slice(x);
+                point.extend_from_slice(y);
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                    .verify(message, sig)
+                    .is_ok()
+            },
+            CosePublicKey::Rs256 { n, e } => {
+                signature::RsaPublicKeyComponents { n: n, e: e }
+                    .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig)
+                    .is_ok()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum WebauthnError {
+    #[fail(display = "{{\"status\":\"WebAuthn challenge expired\"}}")]
+    ChallengeExpired,
+    #[fail(display = "{{\"status\":\"WebAuthn response invalid: {}\"}}", _0)]
+    InvalidResponse(String),
+    #[fail(display = "{{\"status\":\"WebAuthn unsupported: {}\"}}", _0)]
+    Unsupported(String),
+    #[fail(display = "{{\"status\":\"WebAuthn signature invalid\"}}")]
+    VerificationFailed,
+    #[fail(display = "{{\"status\":\"WebAuthn credential may be cloned\"}}")]
+    CloneDetected,
+    #[fail(display = "{{\"status\":\"WebAuthn credential unknown\"}}")]
+    UnknownCredential,
+    #[fail(display = "{{\"status\":\"WebAuthn error: {}\"}}", _0)]
+    Internal(String),
+}
+
+impl ResponseError for WebauthnError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            WebauthnError::ChallengeExpired => {
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(json!({
+                    "status": "CHALLENGE_EXPIRED",
+                    "message": "Passkey request expired, please try again."
+                }))
+            },
+            WebauthnError::InvalidResponse(e) => {
+                warn!("invalid webauthn response: {}", e);
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(json!({
+                    "status": "INVALID_RESPONSE",
+                    "message": format!("Passkey response invalid: {}", e)
+                }))
+            },
+            WebauthnError::Unsupported(e) => {
+                HttpResponse::build(StatusCode::BAD_REQUEST)
+                .json(json!({
+                    "status": "UNSUPPORTED",
+                    "message": format!("Passkey not supported: {}", e)
+                }))
+            },
+            WebauthnError::VerificationFailed => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "VERIFICATION_FAILED",
+                    "message": "Passkey signature invalid."
+                }))
+            },
+            WebauthnError::CloneDetected => {
+                warn!("webauthn signature counter went backwards, credential may be cloned");
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "CLONE_DETECTED",
+                    "message": "Passkey rejected, please contact support."
+                }))
+            },
+            WebauthnError::UnknownCredential => {
+                HttpResponse::build(StatusCode::UNAUTHORIZED)
+                .json(json!({
+                    "status": "UNKNOWN_CREDENTIAL",
+                    "message": "Passkey is not registered."
+                }))
+            },
+            WebauthnError::Internal(e) => {
+                warn!("webauthn error: {}", e);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                .json(json!({
+                    "status": "ERROR",
+                    "message": "Something went wrong with passkeys."
+                }))
+            },
+       }
+    }
+}
+
+
+
+/// Software authenticator for tests: an ES256 key pair with a counter
+#[cfg(test)]
+struct SoftAuthenticator {
+    key_pair: signature::EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+#[cfg(test)]
+impl SoftAuthenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng
+        ).expect("p256 key");
+        SoftAuthenticator {
+            key_pair: signature::EcdsaKeyPair::from_pkcs8(
+                &signature::ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()
+            ).expect("p256 key pair"),
+            credential_id: random_bytes(16).expect("credential id"),
+            sign_count: 0,
+        }
+    }
+
+    fn client_data(rp: &RelyingParty, ceremony: &str, challenge: &WebauthnChallenge) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": ceremony,
+            "challenge": challenge.challenge,
+            "origin": rp.origin,
+        })).unwrap()
+    }
+
+    fn authenticator_data(&mut self, rp: &RelyingParty, attested: bool) -> Vec<u8> {
+        use signature::KeyPair;
+        self.sign_count += 1;
+        let mut data = digest::digest(&digest::SHA256, rp.id.as_bytes()).as_ref().to_vec();
+        let mut flags = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+        if attested {
+            flags |= FLAG_ATTESTED_CREDENTIAL;
+        }
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        if attested {
+            // uncompressed point: 0x04 || x || y
+            let point = self.key_pair.public_key().as_ref();
+            let mut cose_key = BTreeMap::new();
+            cose_key.insert(Value::Integer(1), Value::Integer(2));
+            cose_key.insert(Value::Integer(3), Value::Integer(COSE_ALG_ES256));
+            cose_key.insert(Value::Integer(-1), Value::Integer(1));
+            cose_key.insert(Value::Integer(-2), Value::Bytes(point[1..33].to_vec()));
+            cose_key.insert(Value::Integer(-3), Value::Bytes(point[33..65].to_vec()));
+
+            data.extend_from_slice(&[0u8; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            data.extend_from_slice(&serde_cbor::to_vec(&Value::Map(cose_key)).unwrap());
+        }
+        data
+    }
+
+    fn register(&mut self, rp: &RelyingParty, challenge: &WebauthnChallenge) -> RegisterCredential {
+        let mut attestation = BTreeMap::new();
+        attestation.insert(Value::Text(String::from("fmt")), Value::Text(String::from("none")));
+        attestation.insert(Value::Text(String::from("attStmt")), Value::Map(BTreeMap::new()));
+        attestation.insert(
+            Value::Text(String::from("authData")),
+            Value::Bytes(self.authenticator_data(rp, true))
+        );
+        RegisterCredential {
+            id: b64url(&self.credential_id),
+            raw_id: b64url(&self.credential_id),
+            response: AttestationResponse {
+                client_data_json: b64url(&Self::client_data(rp, "webauthn.create", challenge)),
+                attestation_object: b64url(&serde_cbor::to_vec(&Value::Map(attestation)).unwrap()),
+            },
+        }
+    }
+
+    fn assert(&mut self, rp: &RelyingParty, challenge: &WebauthnChallenge) -> AssertionCredential {
+        let client_data = Self::client_data(rp, "webauthn.get", challenge);
+        let auth_data = self.authenticator_data(rp, false);
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(digest::digest(&digest::SHA256, &client_data).as_ref());
+        let sig = self.key_pair.sign(&SystemRandom::new(), &signed).expect("signature");
+        AssertionCredential {
+            id: b64url(&self.credential_id),
+            raw_id: b64url(&self.credential_id),
+            response: AssertionResponse {
+                client_data_json: b64url(&client_data),
+                authenticator_data: b64url(&auth_data),
+                signature: b64url(sig.as_ref()),
+                user_handle: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_rp() -> RelyingParty {
+    RelyingParty {
+        id: String::from("degentracker.com"),
+        name: String::from("DegenTracker"),
+        origin: String::from("https://www.degentracker.com"),
+    }
+}
+
+#[test]
+fn registers_and_logs_in_with_software_authenticator() {
+    let rp = test_rp();
+    let mut authenticator = SoftAuthenticator::new();
+
+    let challenge = WebauthnChallenge::new(ChallengeKind::Register, Some(String::from("u1"))).unwrap();
+    let registered = verify_registration(&rp, &challenge, &authenticator.register(&rp, &challenge))
+        .expect("registration verified");
+    assert_eq!(registered.credential_id, b64url(&authenticator.credential_id));
+    assert_eq!(registered.sign_count, 1);
+
+    let challenge = WebauthnChallenge::new(ChallengeKind::Authenticate, None).unwrap();
+    let sign_count = verify_assertion(
+        &rp,
+        &challenge,
+        &authenticator.assert(&rp, &challenge),
+        &registered.public_key,
+        registered.sign_count as i64,
+    ).expect("assertion verified");
+    assert_eq!(sign_count, 2);
+}
+
+#[test]
+fn rejects_wrong_challenge_origin_and_key() {
+    let rp = test_rp();
+    let mut authenticator = SoftAuthenticator::new();
+
+    let challenge = WebauthnChallenge::new(ChallengeKind::Register, None).unwrap();
+    let registered = verify_registration(&rp, &challenge, &authenticator.register(&rp, &challenge))
+        .expect("registration verified");
+
+    // signed for a different challenge
+    let challenge = WebauthnChallenge::new(ChallengeKind::Authenticate, None).unwrap();
+    let other_challenge = WebauthnChallenge::new(ChallengeKind::Authenticate, None).unwrap();
+    let assertion = authenticator.assert(&rp, &other_challenge);
+    assert!(verify_assertion(&rp, &challenge, &assertion, &registered.public_key, 1).is_err());
+
+    // from a phishing site
+    let phishing_rp = RelyingParty { origin: String::from("https://degentracker.co"), ..test_rp() };
+    let assertion = authenticator.assert(&phishing_rp, &challenge);
+    assert!(verify_assertion(&rp, &challenge, &assertion, &registered.public_key, 1).is_err());
+
+    // signed by another authenticator
+    let assertion = SoftAuthenticator::new().assert(&rp, &challenge);
+    assert!(verify_assertion(&rp, &challenge, &assertion, &registered.public_key, 0).is_err());
+}
+
+#[test]
+fn sign_count_must_increase() {
+    assert!(check_sign_count(0, 0).is_ok());
+    assert!(check_sign_count(5, 6).is_ok());
+    assert!(check_sign_count(5, 5).is_err());
+    assert!(check_sign_count(5, 0).is_err());
+}
+
+#[test]
+fn fake_credential_ids_are_the_same_for_an_email() {
+    let ids = fake_credential_ids("jack@example.com");
+    assert!(!ids.is_empty());
+    assert_eq!(fake_credential_ids(" Jack@EXAMPLE.com"), ids);
+    assert_ne!(fake_credential_ids("jill@example.com"), ids);
+}
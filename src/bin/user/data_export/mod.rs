@@ -0,0 +1,580 @@
+//// External Imports
+use actix::{Addr, Handler, Message, SyncContext};
+use actix_web::{
+    http::StatusCode,
+    error::ResponseError,
+    HttpResponse,
+};
+use chrono::Local;
+use ring::rand::{SecureRandom, SystemRandom};
+
+//// Internal Imports
+use crate::auth::{hash_token, ListSessions};
+use crate::db::{
+    DatabaseActor,
+    GetPool,
+    getUserRecordJson,
+    getFollowingStoresJson,
+    getUserAuditEvents,
+    getSuspensions,
+    getImpersonationsOfUser,
+    getPendingEmailChanges,
+    getTotp,
+    getWebauthnCredentials,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+};
+use crate::rpc::rpc_export_user_payment_data;
+
+/////////////////////////////////////////////
+/// Personal Data Exports
+/////////////////////////////////////////////
+/// Users can download everything this service holds about them
+/// as a json archive (GDPR article 15/20, CCPA right to know).
+///
+/// STEPS
+/// 1. POST /auth/export: an export is started in the background, and the
+///    response has a one-time download token. Only one export per user can
+///    be in progress, and a new export replaces the previous one.
+/// 2. GET /auth/export/status?export_id= until the status is READY
+///    (or FAILED, then start a new export).
+/// 3. POST /export/download with { "token": ... } downloads the archive once.
+///    The token and archive are deleted as it's downloaded.
+///
+/// The archive has the user's row (every column but the password hash),
+/// the stores they follow, their sessions, audit events, suspensions,
+/// impersonations, email changes, 2FA status and passkeys, and optionally
+/// what the payment service holds about them.
+/// Licenses (user_licenses has no user column) and consents aren't
+/// stored by this service, so aren't in the archive.
+///
+/// Redis keys, all expiring after DATA_EXPORT_TTL_SECONDS:
+///     data_export:{export_id}         => DataExportRecord (json)
+///     data_export_user:{user_id}      => the user's latest export_id
+///     data_export_token:{token hash}  => export_id
+///     data_export_archive:{export_id} => the archive (json)
+
+pub const DATA_EXPORT_TTL_SECONDS: i64 = 86400; // 24hrs
+/// Shown to the user when building the archive fails
+const DATA_EXPORT_FAILED_MESSAGE: &str = "The export couldn't be built, please start a new one";
+/// Pending exports older than this were lost (e.g. a restart),
+/// and don't stop the user starting a new one
+const DATA_EXPORT_STALE_SECONDS: i64 = 3600; // 1hr
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DataExportStatus {
+    Pending,
+    Ready,
+    Failed,
+    Downloaded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataExportRecord {
+    pub id: String,
+    pub user_id: String,
+    pub status: DataExportStatus,
+    pub include_payments: bool,
+    /// unix timestamps
+    pub created_at: i64,
+    pub completed_at: Option<i64>,
+    pub expires_at: i64,
+    pub error: Option<String>,
+    /// see `auth::hash_token`
+    pub token_hash: String,
+}
+
+impl DataExportRecord {
+    /// What the user sees, without the token hash
+    pub fn status_json(&self) -> serde_json::Value {
+        json!({
+            "exportId": self.id,
+            "status": self.status,
+            "includePayments": self.include_payments,
+            "createdAt": self.created_at,
+            "completedAt": self.completed_at,
+            "expiresAt": self.expires_at,
+            "error": self.error,
+        })
+    }
+
+    fn ttl(&self) -> i64 {
+        std::cmp::max(1, self.expires_at - Local::now().timestamp())
+    }
+}
+
+fn data_export_key(export_id: &str) -> String {
+    format!("data_export:{}", export_id)
+}
+
+fn data_export_user_key(user_id: &str) -> String {
+    format!("data_export_user:{}", user_id)
+}
+
+fn data_export_token_key(token_hash: &str) -> String {
+    format!("data_export_token:{}", token_hash)
+}
+
+fn data_export_archive_key(export_id: &str) -> String {
+    format!("data_export_archive:{}", export_id)
+}
+
+fn generate_download_token() -> Result<String, DataExportError> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| DataExportError::Other(errJson!("Could not generate download token")))?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+fn get_record(
+    conn: &mut redis::Connection,
+    export_id: &str,
+) -> Result<Option<DataExportRecord>, DataExportError> {
+
+    let record: Option<String> = redis::cmd("GET")
+        .arg(data_export_key(export_id))
+        .query(conn)?;
+    Ok(record.and_then(|r| serde_json::from_str::<DataExportRecord>(&r).ok()))
+}
+
+fn save_record(
+    conn: &mut redis::Connection,
+    record: &DataExportRecord,
+) -> Result<(), DataExportError> {
+
+    let record_json = serde_json::to_string(record)
+        .map_err(|e| DataExportError::Other(errJson!(e)))?;
+    let _: () = redis::cmd("SETEX")
+        .arg(data_export_key(&record.id))
+        .arg(record.ttl())
+        .arg(record_json)
+        .query(conn)?;
+    Ok(())
+}
+
+//////////////////////////////////////////////////
+///// Building the archive
+//////////////////////////////////////////////////
+
+/// Starts building the archive in the background.
+/// The result is saved with `FinishDataExport`.
+pub fn spawn_data_export(
+    database_actor: Addr<DatabaseActor>,
+    http_client: actix_web::client::Client,
+    record: DataExportRecord,
+) {
+    actix_rt::spawn(async move {
+        let archive = build_archive(&database_actor, &http_client, &record).await
+            .and_then(|archive| serde_json::to_string_pretty(&archive)
+                .map_err(|e| DataExportError::Other(errJson!(e))));
+
+        // The details are only logged, the user sees a generic error
+        if let Err(e) = &archive {
+            warn!("data export {} failed: {}", record.id, e);
+        }
+        database_actor.do_send(FinishDataExport {
+            export_id: record.id,
+            archive: archive.map_err(|_| String::from(DATA_EXPORT_FAILED_MESSAGE)),
+        });
+    });
+}
+
+async fn build_archive(
+    database_actor: &Addr<DatabaseActor>,
+    http_client: &actix_web::client::Client,
+    record: &DataExportRecord,
+) -> Result<serde_json::Value, DataExportError> {
+
+    let user_id = record.user_id.as_str();
+
+    let sessions = database_actor
+        .send(ListSessions(user_id.to_string()))
+        .await
+        .map_err(|e| DataExportError::Other(errJson!(e)))?
+        .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+    let mut archive = {
+        let conn = database_actor
+            .send(GetPool::Postgres)
+            .await
+            .map_err(|e| DataExportError::Other(errJson!(e)))?
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        let totp = getTotp(&conn, user_id)?;
+
+        json!({
+            "exportId": record.id,
+            "exportedAt": Local::now().naive_utc(),
+            "userId": user_id,
+            "user": getUserRecordJson(&conn, user_id)?,
+            "followingStores": getFollowingStoresJson(&conn, user_id)?,
+            "sessions": sessions,
+            // as at GET /auth/audit, without the admins' details
+            "auditEvents": getUserAuditEvents(&conn, user_id)?
+                .into_iter()
+                .map(|event| event.for_user(user_id))
+                .collect::<Vec<_>>(),
+            "suspensions": getSuspensions(&conn, user_id)?,
+            "impersonations": getImpersonationsOfUser(&conn, user_id)?,
+            "emailChanges": getPendingEmailChanges(&conn, user_id)?,
+            // never the TOTP secret or recovery codes
+            "twoFactor": {
+                "totpEnabled": totp.as_ref().map(|t| t.enabled).unwrap_or(false),
+                "totpEnabledAt": totp.as_ref().and_then(|t| t.enabled_at),
+            },
+            "passkeys": getWebauthnCredentials(&conn, user_id)?,
+        })
+    };
+
+    if record.include_payments {
+        // The rest of the archive is still useful if the payment service is down
+        archive["payments"] = match rpc_export_user_payment_data(http_client, user_id).await {
+            Ok(payments) => payments,
+            Err(e) => {
+                warn!("data export {}: payment data unavailable: {}", record.id, e);
+                json!({ "error": "Payment data was unavailable, please request a new export later." })
+            }
+        };
+    }
+
+    Ok(archive)
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+/// Starts a new export for the user.
+/// Returns the record and the one-time download token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateDataExport {
+    pub user_id: String,
+    pub include_payments: bool,
+}
+
+impl Message for CreateDataExport {
+    type Result = Result<(DataExportRecord, String), DataExportError>;
+}
+
+impl Handler<CreateDataExport> for DatabaseActor {
+    type Result = Result<(DataExportRecord, String), DataExportError>;
+
+    fn handle(
+        &mut self,
+        msg: CreateDataExport,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        let previous_id: Option<String> = redis::cmd("GET")
+            .arg(data_export_user_key(&msg.user_id))
+            .query(&mut conn)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        if let Some(previous) = previous_id.and_then(|id| get_record(&mut conn, &id).transpose()) {
+            let previous = previous?;
+            let is_stale = Local::now().timestamp() - previous.created_at > DATA_EXPORT_STALE_SECONDS;
+            if previous.status == DataExportStatus::Pending && !is_stale {
+                return Err(DataExportError::InProgress)
+            }
+            pipe.cmd("DEL").arg(data_export_key(&previous.id)).ignore()
+                .cmd("DEL").arg(data_export_archive_key(&previous.id)).ignore()
+                .cmd("DEL").arg(data_export_token_key(&previous.token_hash)).ignore();
+        }
+
+        let token = generate_download_token()?;
+        let now = Local::now().timestamp();
+        let record = DataExportRecord {
+            id: format!("exp_{}", uuid::Uuid::new_v4()),
+            user_id: msg.user_id.clone(),
+            status: DataExportStatus::Pending,
+            include_payments: msg.include_payments,
+            created_at: now,
+            completed_at: None,
+            expires_at: now + DATA_EXPORT_TTL_SECONDS,
+            error: None,
+            token_hash: hash_token(&token),
+        };
+        let record_json = serde_json::to_string(&record)
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        pipe.cmd("SETEX").arg(data_export_key(&record.id))
+                .arg(DATA_EXPORT_TTL_SECONDS).arg(record_json).ignore()
+            .cmd("SETEX").arg(data_export_user_key(&record.user_id))
+                .arg(DATA_EXPORT_TTL_SECONDS).arg(&record.id).ignore()
+            .cmd("SETEX").arg(data_export_token_key(&record.token_hash))
+                .arg(DATA_EXPORT_TTL_SECONDS).arg(&record.id).ignore();
+        let _: () = pipe.query(&mut conn)?;
+
+        Ok((record, token))
+    }
+}
+
+/// Saves the archive, or why it failed
+#[derive(Debug, Clone)]
+pub struct FinishDataExport {
+    pub export_id: String,
+    pub archive: Result<String, String>,
+}
+
+impl Message for FinishDataExport {
+    type Result = Result<(), DataExportError>;
+}
+
+impl Handler<FinishDataExport> for DatabaseActor {
+    type Result = Result<(), DataExportError>;
+
+    fn handle(
+        &mut self,
+        msg: FinishDataExport,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        // Replaced or expired while it was being built
+        let mut record = match get_record(&mut conn, &msg.export_id)? {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+
+        record.completed_at = Some(Local::now().timestamp());
+        match msg.archive {
+            Ok(archive) => {
+                let _: () = redis::cmd("SETEX")
+                    .arg(data_export_archive_key(&record.id))
+                    .arg(record.ttl())
+                    .arg(archive)
+                    .query(&mut conn)?;
+                record.status = DataExportStatus::Ready;
+            },
+            Err(e) => {
+                record.status = DataExportStatus::Failed;
+                record.error = Some(e);
+            }
+        }
+        save_record(&mut conn, &record)
+    }
+}
+
+/// Gets one of the user's exports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetDataExport {
+    pub user_id: String,
+    pub export_id: String,
+}
+
+impl Message for GetDataExport {
+    type Result = Result<DataExportRecord, DataExportError>;
+}
+
+impl Handler<GetDataExport> for DatabaseActor {
+    type Result = Result<DataExportRecord, DataExportError>;
+
+    fn handle(
+        &mut self,
+        msg: GetDataExport,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        get_record(&mut conn, &msg.export_id)?
+            .filter(|record| record.user_id == msg.user_id)
+            .ok_or(DataExportError::NotFound)
+    }
+}
+
+/// Takes the archive with the download token, which only works once.
+/// Returns the record and the archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedeemDataExport(pub String);
+
+impl Message for RedeemDataExport {
+    type Result = Result<(DataExportRecord, String), DataExportError>;
+}
+
+impl Handler<RedeemDataExport> for DatabaseActor {
+    type Result = Result<(DataExportRecord, String), DataExportError>;
+
+    fn handle(
+        &mut self,
+        msg: RedeemDataExport,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        let token_hash = hash_token(&msg.0);
+        let export_id: Option<String> = redis::cmd("GET")
+            .arg(data_export_token_key(&token_hash))
+            .query(&mut conn)?;
+
+        let mut record = match export_id {
+            Some(id) => get_record(&mut conn, &id)?.ok_or(DataExportError::InvalidToken)?,
+            None => return Err(DataExportError::InvalidToken),
+        };
+
+        // Don't use up the token before the archive is ready
+        if record.status != DataExportStatus::Ready {
+            return Err(DataExportError::NotReady(record.status))
+        }
+
+        // Single use: only one request can GET the archive before it's deleted
+        let (archive,): (Option<String>,) = redis::pipe()
+            .atomic()
+            .cmd("GET").arg(data_export_archive_key(&record.id))
+            .cmd("DEL").arg(data_export_archive_key(&record.id)).ignore()
+            .cmd("DEL").arg(data_export_token_key(&token_hash)).ignore()
+            .query(&mut conn)?;
+
+        let archive = archive.ok_or(DataExportError::InvalidToken)?;
+
+        record.status = DataExportStatus::Downloaded;
+        save_record(&mut conn, &record)?;
+
+        Ok((record, archive))
+    }
+}
+
+/// Deletes the user's latest export, its token and archive,
+/// e.g. when they delete their account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteDataExports(pub String);
+
+impl Message for DeleteDataExports {
+    type Result = Result<(), DataExportError>;
+}
+
+impl Handler<DeleteDataExports> for DatabaseActor {
+    type Result = Result<(), DataExportError>;
+
+    fn handle(
+        &mut self,
+        msg: DeleteDataExports,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| DataExportError::Other(errJson!(e)))?;
+
+        let export_id: Option<String> = redis::cmd("GET")
+            .arg(data_export_user_key(&msg.0))
+            .query(&mut conn)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("DEL").arg(data_export_user_key(&msg.0)).ignore();
+
+        // An export still being built is dropped by `FinishDataExport`
+        // once its record is gone
+        if let Some(export_id) = export_id {
+            if let Some(record) = get_record(&mut conn, &export_id)? {
+                pipe.cmd("DEL").arg(data_export_token_key(&record.token_hash)).ignore();
+            }
+            pipe.cmd("DEL").arg(data_export_key(&export_id)).ignore()
+                .cmd("DEL").arg(data_export_archive_key(&export_id)).ignore();
+        }
+        let _: () = pipe.query(&mut conn)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Fail, Serialize)]
+pub enum DataExportError {
+    #[fail(display = "{{\"status\":\"Data export not found\"}}")]
+    NotFound,
+    #[fail(display = "{{\"status\":\"A data export is already in progress\"}}")]
+    InProgress,
+    #[fail(display = "{{\"status\":\"Data export is {:?}\"}}", _0)]
+    NotReady(DataExportStatus),
+    #[fail(display = "{{\"status\":\"Download link is invalid or has already been used\"}}")]
+    InvalidToken,
+    #[fail(display = "{{\"redis_error\": \"{}\"}}", _0)]
+    Redis(String),
+    #[fail(display = "{}", _0)]
+    Other(ErrJson),
+}
+
+impl From<redis::RedisError> for DataExportError {
+    fn from(e: redis::RedisError) -> Self {
+        DataExportError::Redis(e.to_string())
+    }
+}
+
+impl From<LoginError> for DataExportError {
+    fn from(e: LoginError) -> Self {
+        DataExportError::Other(errJson!(e))
+    }
+}
+
+impl ResponseError for DataExportError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            DataExportError::NotFound => {
+                HttpResponse::build(StatusCode::NOT_FOUND)
+                .json(json!({
+                    "status": "NOT_FOUND",
+                    "message": "Data export not found, it may have expired."
+                }))
+            },
+            DataExportError::InProgress => {
+                HttpResponse::build(StatusCode::CONFLICT)
+                .json(json!({
+                    "status": "IN_PROGRESS",
+                    "message": "A data export is already in progress."
+                }))
+            },
+            DataExportError::NotReady(status) => {
+                HttpResponse::build(StatusCode::CONFLICT)
+                .json(json!({
+                    "status": status,
+                    "message": "Data export is not ready to download."
+                }))
+            },
+            DataExportError::InvalidToken => {
+                HttpResponse::build(StatusCode::NOT_FOUND)
+                .json(json!({
+                    "status": "INVALID_TOKEN",
+                    "message": "Download link is invalid, expired, or has already been used."
+                }))
+            },
+            DataExportError::Redis(s) => {
+                warn!("{}", s);
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            },
+            DataExportError::Other(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            },
+       }
+    }
+}
+
+
+#[test]
+fn data_export_record_status_hides_token_hash() {
+    let record = DataExportRecord {
+        id: String::from("exp_1"),
+        user_id: String::from("u123"),
+        status: DataExportStatus::Ready,
+        include_payments: false,
+        created_at: 0,
+        completed_at: Some(10),
+        expires_at: DATA_EXPORT_TTL_SECONDS,
+        error: None,
+        token_hash: String::from("secret_hash"),
+    };
+    let status = record.status_json();
+    assert_eq!(status["status"], json!("READY"));
+    assert!(!status.to_string().contains("secret_hash"));
+}
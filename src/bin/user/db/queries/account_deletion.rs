@@ -0,0 +1,207 @@
+use chrono::Local;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::sql_types::{Nullable, Text};
+use diesel::PgConnection;
+
+use dt::db::schema::{
+    account_deletions,
+    mfa_recovery_codes,
+    pending_email_changes,
+    user_totp,
+    users,
+    webauthn_credentials,
+};
+use crate::db::users_raw::get_user_profile_by_email;
+use crate::models::{
+    LoginError,
+    ErrJson,
+    User,
+    AccountDeletion,
+    email_identity,
+};
+
+//////////////////////////////////////////
+///////// Account Deletion Queries ///////
+//////////////////////////////////////////
+
+/// The user's deletion which can still be restored, if any
+pub fn getRestorableAccountDeletion(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Option<AccountDeletion>, LoginError> {
+
+    account_deletions::table
+        .filter(account_deletions::user_id.eq(user_id))
+        .filter(account_deletions::restored_at.is_null())
+        .filter(account_deletions::erased_at.is_null())
+        .filter(account_deletions::erase_after.gt(Local::now().naive_utc()))
+        .order(account_deletions::created_at.desc())
+        .first::<AccountDeletion>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Looks up a restorable deletion by the account's original email
+pub fn getRestorableAccountDeletionByEmail(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    email: &str,
+) -> Result<Option<AccountDeletion>, LoginError> {
+
+    account_deletions::table
+        .filter(account_deletions::original_email_identity.eq(email_identity(email)))
+        .filter(account_deletions::restored_at.is_null())
+        .filter(account_deletions::erased_at.is_null())
+        .filter(account_deletions::erase_after.gt(Local::now().naive_utc()))
+        .order(account_deletions::created_at.desc())
+        .first::<AccountDeletion>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Deletions whose grace period has ended, oldest first
+pub fn getAccountDeletionsDue(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<AccountDeletion>, LoginError> {
+
+    account_deletions::table
+        .filter(account_deletions::restored_at.is_null())
+        .filter(account_deletions::erased_at.is_null())
+        .filter(account_deletions::erase_after.le(Local::now().naive_utc()))
+        .order(account_deletions::erase_after.asc())
+        .load::<AccountDeletion>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Undeletes the account with its original email.
+/// `restored_by` is the user, or the admin.
+/// Errors with DuplicateUser if the email was taken in the meantime,
+/// or BadRequest if it was erased or restored since it was looked up.
+pub fn restoreUser(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    deletion: &AccountDeletion,
+    restored_by: &str,
+) -> Result<User, LoginError> {
+
+    let original_email = deletion.original_email.clone()
+        .ok_or(LoginError::BadRequest(errJson!("Account was erased and can't be restored")))?;
+
+    if get_user_profile_by_email(conn, &original_email).is_ok() {
+        return Err(LoginError::DuplicateUser(
+            errJson!("The account's email is now used by another account")))
+    }
+
+    conn.transaction::<User, LoginError, _>(|| {
+        let now = Local::now().naive_utc();
+        // The eraser may have got to it first
+        let restored = diesel::update(account_deletions::table
+            .filter(account_deletions::id.eq(&deletion.id))
+            .filter(account_deletions::restored_at.is_null())
+            .filter(account_deletions::erased_at.is_null())
+            .filter(account_deletions::erase_after.gt(now)))
+            .set((
+                account_deletions::restored_at.eq(now),
+                account_deletions::restored_by.eq(restored_by),
+            ))
+            .execute(conn)?;
+
+        if restored != 1 {
+            return Err(LoginError::BadRequest(
+                errJson!("Account was erased or already restored")))
+        }
+
+        diesel::update(users::table.filter(users::id.eq(&deletion.user_id)))
+            .set((
+                users::email.eq(&original_email),
+                users::email_identity.eq(email_identity(&original_email)),
+                users::is_deleted.eq(false),
+            ))
+            .get_result::<User>(conn)
+            .map_err(|e| LoginError::NoUserError(errJson!(e)))
+    })
+}
+
+/// Permanently erases a deleted account: the user's row is anonymized,
+/// and their followed stores, 2FA, passkeys and email changes are deleted.
+/// Their audit events are kept as the record of what happened, without
+/// the IPs, user agents and emails in them.
+/// Suspensions and impersonations are kept, see `auth::account_deletion`.
+/// Returns false, erasing nothing, if the account was restored
+/// since `deletion` was loaded.
+pub fn eraseUser(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    deletion: &AccountDeletion,
+) -> Result<bool, LoginError> {
+
+    let user_id = deletion.user_id.as_str();
+
+    conn.transaction::<bool, diesel::result::Error, _>(|| {
+        // Claims the deletion first, so a restore in the meantime
+        // either wins, or waits for this and then fails
+        let claimed = diesel::update(account_deletions::table
+            .filter(account_deletions::id.eq(&deletion.id))
+            .filter(account_deletions::restored_at.is_null())
+            .filter(account_deletions::erased_at.is_null()))
+            .set((
+                account_deletions::erased_at.eq(Local::now().naive_utc()),
+                account_deletions::original_email.eq(None as Option<String>),
+                account_deletions::original_email_identity.eq(None as Option<String>),
+            ))
+            .execute(conn)?;
+
+        if claimed == 0 {
+            return Ok(false)
+        }
+
+        // following_stores isn't in the schema
+        diesel::sql_query("DELETE FROM following_stores WHERE user_id = $1")
+            .bind::<Text, _>(user_id)
+            .execute(conn)?;
+        diesel::delete(mfa_recovery_codes::table
+            .filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(user_totp::table
+            .filter(user_totp::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(webauthn_credentials::table
+            .filter(webauthn_credentials::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(pending_email_changes::table
+            .filter(pending_email_changes::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        // Events by or about the user, and failed logins or restores with
+        // their email, which is recorded as its `email_identity` (older events
+        // have the email as typed). The trigger only allows this redaction.
+        diesel::sql_query("UPDATE audit_events \
+            SET ip = NULL, user_agent = NULL, \
+                payload = payload - 'email' - 'oldEmail' - 'newEmail' \
+            WHERE (user_id = $1 OR actor_id = $1 \
+                OR payload->>'email' = $2 OR lower(trim(payload->>'email')) = $2) \
+            AND (ip IS NOT NULL OR user_agent IS NOT NULL \
+                OR payload ?| array['email', 'oldEmail', 'newEmail'])")
+            .bind::<Text, _>(user_id)
+            .bind::<Nullable<Text>, _>(deletion.original_email.as_ref().map(|e| email_identity(e)))
+            .execute(conn)?;
+
+        diesel::sql_query("UPDATE users SET following_stores_ids = '{}' WHERE id = $1")
+            .bind::<Text, _>(user_id)
+            .execute(conn)?;
+        // The row is kept for other services which refer to the user id,
+        // and an empty password hash never matches
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set((
+                users::email.eq(format!("erased_{}", user_id)),
+                users::email_identity.eq(None as Option<String>),
+                users::first_name.eq(None as Option<String>),
+                users::last_name.eq(None as Option<String>),
+                users::password_hash.eq(""),
+                users::email_verified.eq(false),
+                users::user_role.eq(None as Option<String>),
+                users::is_deleted.eq(true),
+            ))
+            .execute(conn)?;
+        Ok(true)
+    })
+    .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
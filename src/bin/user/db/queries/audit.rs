@@ -0,0 +1,91 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use dt::db::schema::audit_events;
+use crate::models::{
+    LoginError,
+    ErrJson,
+    AuditEvent,
+    AuditEventQuery,
+    NewAuditEvent,
+};
+
+//////////////////////////////////////////
+///////// Audit Event Queries ////////////
+//////////////////////////////////////////
+
+/// Audit events are append only, there are no update or delete queries.
+/// The table's trigger rejects them anyway.
+pub fn insertAuditEvent(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    event: NewAuditEvent,
+) -> Result<AuditEvent, LoginError> {
+
+    diesel::insert_into(audit_events::table)
+        .values(&event)
+        .get_result::<AuditEvent>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Newest first, keyset paginated on `(created_at, id)`: only events
+/// before `before` (the cursor) are returned. The id breaks ties between
+/// events written in the same instant, so none are skipped or repeated.
+/// Fetches up to `limit` events, callers overfetch by 1 to find the last page.
+pub fn getAuditEvents(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    query: &AuditEventQuery,
+    before: Option<(chrono::NaiveDateTime, String)>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, LoginError> {
+
+    let mut q = audit_events::table.into_boxed();
+
+    if let Some(user_id) = &query.user_id {
+        q = q.filter(audit_events::user_id.eq(user_id));
+    }
+    if let Some(actor_id) = &query.actor_id {
+        q = q.filter(audit_events::actor_id.eq(actor_id));
+    }
+    if let Some(action) = &query.action {
+        q = q.filter(audit_events::action.eq(action.as_str().to_string()));
+    }
+    if let Some(outcome) = &query.outcome {
+        q = q.filter(audit_events::outcome.eq(outcome.as_str().to_string()));
+    }
+    if let Some(ip) = &query.ip {
+        q = q.filter(audit_events::ip.eq(ip));
+    }
+    if let Some(created_after) = query.created_after {
+        q = q.filter(audit_events::created_at.ge(created_after));
+    }
+    if let Some(created_before) = query.created_before {
+        q = q.filter(audit_events::created_at.lt(created_before));
+    }
+    if let Some((before_created_at, before_id)) = before {
+        // (created_at, id) < (before_created_at, before_id)
+        q = q.filter(
+            audit_events::created_at.lt(before_created_at)
+                .or(audit_events::created_at.eq(before_created_at)
+                    .and(audit_events::id.lt(before_id)))
+        );
+    }
+
+    q.order((audit_events::created_at.desc(), audit_events::id.desc()))
+        .limit(limit)
+        .load::<AuditEvent>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Every event about the user, newest first
+pub fn getUserAuditEvents(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Vec<AuditEvent>, LoginError> {
+
+    audit_events::table
+        .filter(audit_events::user_id.eq(user_id))
+        .order(audit_events::created_at.desc())
+        .load::<AuditEvent>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
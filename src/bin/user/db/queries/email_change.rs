@@ -0,0 +1,168 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use dt::db::schema::{pending_email_changes, users};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    User,
+    PendingEmailChange,
+    NewPendingEmailChange,
+    email_identity,
+};
+
+//////////////////////////////////////////
+///////// Email Change Queries ///////////
+//////////////////////////////////////////
+
+/// Saves a new email change, cancelling any earlier unconfirmed
+/// change for the user so only the latest links work
+pub fn insertPendingEmailChange(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    change: NewPendingEmailChange,
+) -> Result<PendingEmailChange, LoginError> {
+
+    conn.transaction::<PendingEmailChange, diesel::result::Error, _>(|| {
+
+        diesel::delete(pending_email_changes::table
+            .filter(pending_email_changes::user_id.eq(&change.user_id))
+            .filter(pending_email_changes::confirmed_at.is_null())
+            .filter(pending_email_changes::reverted_at.is_null()))
+            .execute(conn)?;
+
+        diesel::insert_into(pending_email_changes::table)
+            .values(&change)
+            .get_result::<PendingEmailChange>(conn)
+    })
+    .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+pub fn getPendingEmailChangeByConfirmHash(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    confirm_token_hash: &str,
+) -> Result<Option<PendingEmailChange>, LoginError> {
+
+    pending_email_changes::table
+        .filter(pending_email_changes::confirm_token_hash.eq(confirm_token_hash))
+        .get_result::<PendingEmailChange>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+pub fn getPendingEmailChangeByRevertHash(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    revert_token_hash: &str,
+) -> Result<Option<PendingEmailChange>, LoginError> {
+
+    pending_email_changes::table
+        .filter(pending_email_changes::revert_token_hash.eq(revert_token_hash))
+        .get_result::<PendingEmailChange>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Applies a confirmed change. The new email is marked verified,
+/// as the user has just clicked a link sent to it.
+pub fn confirmEmailChange(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    change: &PendingEmailChange,
+) -> Result<User, LoginError> {
+
+    conn.transaction::<User, LoginError, _>(|| {
+
+        let updated = diesel::update(pending_email_changes::table
+            .filter(pending_email_changes::id.eq(&change.id))
+            .filter(pending_email_changes::confirmed_at.is_null())
+            .filter(pending_email_changes::reverted_at.is_null()))
+            .set(pending_email_changes::confirmed_at.eq(diesel::dsl::now.nullable()))
+            .execute(conn)
+            .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+        if updated != 1 {
+            return Err(LoginError::BadRequest(errJson!("Email change was already confirmed or cancelled")))
+        }
+
+        // Only if the email hasn't changed since the request
+        diesel::update(users::table
+            .filter(users::id.eq(&change.user_id))
+            .filter(users::email.eq(&change.old_email)))
+            .set((
+                users::email.eq(&change.new_email),
+                users::email_identity.eq(email_identity(&change.new_email)),
+                users::email_verified.eq(true),
+            ))
+            .get_result::<User>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation, _
+                ) => LoginError::BadRequest(errJson!("Email is already in use")),
+                diesel::result::Error::NotFound => LoginError::BadRequest(
+                    errJson!("Email has changed since this link was sent")
+                ),
+                _ => LoginError::DatabaseError(errJson!(e)),
+            })
+    })
+}
+
+/// Cancels an unconfirmed change, or puts the old email back
+/// if the change was already confirmed
+pub fn revertEmailChange(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    change: &PendingEmailChange,
+) -> Result<User, LoginError> {
+
+    conn.transaction::<User, LoginError, _>(|| {
+
+        let updated = diesel::update(pending_email_changes::table
+            .filter(pending_email_changes::id.eq(&change.id))
+            .filter(pending_email_changes::reverted_at.is_null()))
+            .set(pending_email_changes::reverted_at.eq(diesel::dsl::now.nullable()))
+            .execute(conn)
+            .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+        if updated != 1 {
+            return Err(LoginError::BadRequest(errJson!("Email change was already cancelled")))
+        }
+
+        if change.confirmed_at.is_none() {
+            return users::table
+                .filter(users::id.eq(&change.user_id))
+                .get_result::<User>(conn)
+                .map_err(|e| LoginError::NoUserError(errJson!(e)))
+        }
+
+        // The user clicked a link sent to the old email, so it's verified
+        diesel::update(users::table
+            .filter(users::id.eq(&change.user_id))
+            .filter(users::email.eq(&change.new_email)))
+            .set((
+                users::email.eq(&change.old_email),
+                users::email_identity.eq(email_identity(&change.old_email)),
+                users::email_verified.eq(true),
+            ))
+            .get_result::<User>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation, _
+                ) => LoginError::BadRequest(errJson!("Old email is now used by another account")),
+                diesel::result::Error::NotFound => LoginError::BadRequest(
+                    errJson!("Email has changed again since this link was sent")
+                ),
+                _ => LoginError::DatabaseError(errJson!(e)),
+            })
+    })
+}
+
+/// All of the user's email changes, newest first
+pub fn getPendingEmailChanges(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Vec<PendingEmailChange>, LoginError> {
+
+    pending_email_changes::table
+        .filter(pending_email_changes::user_id.eq(user_id))
+        .order(pending_email_changes::created_at.desc())
+        .load::<PendingEmailChange>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
@@ -0,0 +1,52 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::sql_types::{Jsonb, Text};
+use diesel::PgConnection;
+
+use crate::models::{
+    LoginError,
+    ErrJson,
+};
+
+//////////////////////////////////////////
+///////// Data Export Queries ////////////
+//////////////////////////////////////////
+
+/// Rows are read as jsonb, so exports include every column,
+/// not only the ones the `User` model and schema know about.
+
+#[derive(Debug, Clone, QueryableByName)]
+struct JsonRow {
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+/// The user's row, without the password hash
+pub fn getUserRecordJson(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<serde_json::Value, LoginError> {
+
+    diesel::sql_query("SELECT to_jsonb(u) - 'password_hash' AS data FROM users u WHERE u.id = $1")
+        .bind::<Text, _>(user_id)
+        .get_result::<JsonRow>(conn)
+        .map(|row| row.data)
+        .map_err(|e| LoginError::NoUserError(errJson!(e)))
+}
+
+/// Stores the user follows, as a json array
+pub fn getFollowingStoresJson(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<serde_json::Value, LoginError> {
+
+    diesel::sql_query("
+        SELECT coalesce(jsonb_agg(to_jsonb(f) ORDER BY f.created_at), '[]'::jsonb) AS data
+        FROM following_stores f
+        WHERE f.user_id = $1
+    ")
+        .bind::<Text, _>(user_id)
+        .get_result::<JsonRow>(conn)
+        .map(|row| row.data)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
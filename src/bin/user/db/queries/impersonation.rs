@@ -0,0 +1,40 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use dt::db::schema::impersonations;
+use crate::models::{
+    LoginError,
+    ErrJson,
+    Impersonation,
+    NewImpersonation,
+};
+
+//////////////////////////////////////////
+///////// Impersonation Queries //////////
+//////////////////////////////////////////
+
+/// Impersonation records are append only
+pub fn insertImpersonation(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    impersonation: NewImpersonation,
+) -> Result<Impersonation, LoginError> {
+
+    diesel::insert_into(impersonations::table)
+        .values(&impersonation)
+        .get_result::<Impersonation>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Impersonations of the user, newest first
+pub fn getImpersonationsOfUser(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Vec<Impersonation>, LoginError> {
+
+    impersonations::table
+        .filter(impersonations::user_id.eq(user_id))
+        .order(impersonations::created_at.desc())
+        .load::<Impersonation>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
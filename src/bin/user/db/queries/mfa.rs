@@ -0,0 +1,157 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use dt::db::schema::{user_totp, mfa_recovery_codes};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    UserTotp,
+    NewRecoveryCode,
+};
+
+//////////////////////////////////////////
+///////// Two-factor auth Queries ////////
+//////////////////////////////////////////
+
+pub fn getTotp(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Option<UserTotp>, LoginError> {
+
+    user_totp::table
+        .filter(user_totp::user_id.eq(user_id))
+        .get_result::<UserTotp>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Starts (or restarts) enrolment with a new secret.
+/// The authenticator stays disabled until `enableTotp`.
+pub fn insertPendingTotp(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    secret: &str,
+) -> Result<UserTotp, LoginError> {
+
+    diesel::insert_into(user_totp::table)
+        .values((
+            user_totp::user_id.eq(user_id),
+            user_totp::secret.eq(secret),
+            user_totp::enabled.eq(false),
+        ))
+        .on_conflict(user_totp::user_id)
+        .do_update()
+        .set((
+            user_totp::secret.eq(secret),
+            user_totp::enabled.eq(false),
+            user_totp::last_used_step.eq(None as Option<i64>),
+            user_totp::created_at.eq(diesel::dsl::now),
+            user_totp::enabled_at.eq(None as Option<chrono::NaiveDateTime>),
+        ))
+        .get_result::<UserTotp>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Enables the authenticator after the first code is verified,
+/// and replaces any previous recovery codes.
+pub fn enableTotp(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    verified_step: i64,
+    recovery_code_hashes: Vec<String>,
+) -> Result<UserTotp, LoginError> {
+
+    let new_codes = recovery_code_hashes.into_iter()
+        .map(|hash| NewRecoveryCode::new(user_id, hash))
+        .collect::<Vec<NewRecoveryCode>>();
+
+    conn.transaction::<UserTotp, diesel::result::Error, _>(|| {
+
+        diesel::delete(mfa_recovery_codes::table
+            .filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        diesel::insert_into(mfa_recovery_codes::table)
+            .values(&new_codes)
+            .execute(conn)?;
+
+        diesel::update(user_totp::table.filter(user_totp::user_id.eq(user_id)))
+            .set((
+                user_totp::enabled.eq(true),
+                user_totp::enabled_at.eq(diesel::dsl::now.nullable()),
+                user_totp::last_used_step.eq(Some(verified_step)),
+            ))
+            .get_result::<UserTotp>(conn)
+    })
+    .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Records the time step of an accepted code.
+/// Returns false if this step (or a later one) was already used,
+/// i.e. the code is being replayed.
+pub fn useTotpStep(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    step: i64,
+) -> Result<bool, LoginError> {
+
+    diesel::update(user_totp::table
+        .filter(user_totp::user_id.eq(user_id))
+        .filter(user_totp::enabled.eq(true))
+        .filter(user_totp::last_used_step.is_null()
+            .or(user_totp::last_used_step.lt(step))))
+        .set(user_totp::last_used_step.eq(Some(step)))
+        .execute(conn)
+        .map(|updated| updated == 1)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Marks an unused recovery code as used. Returns false if there is
+/// no unused code with this hash.
+pub fn useRecoveryCode(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    code_hash: &str,
+) -> Result<bool, LoginError> {
+
+    diesel::update(mfa_recovery_codes::table
+        .filter(mfa_recovery_codes::user_id.eq(user_id))
+        .filter(mfa_recovery_codes::code_hash.eq(code_hash))
+        .filter(mfa_recovery_codes::used_at.is_null()))
+        .set(mfa_recovery_codes::used_at.eq(diesel::dsl::now.nullable()))
+        .execute(conn)
+        .map(|updated| updated == 1)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+pub fn countUnusedRecoveryCodes(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<i64, LoginError> {
+
+    mfa_recovery_codes::table
+        .filter(mfa_recovery_codes::user_id.eq(user_id))
+        .filter(mfa_recovery_codes::used_at.is_null())
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Removes the authenticator and all recovery codes
+pub fn deleteTotp(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<(), LoginError> {
+
+    conn.transaction::<(), diesel::result::Error, _>(|| {
+        diesel::delete(mfa_recovery_codes::table
+            .filter(mfa_recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(user_totp::table
+            .filter(user_totp::user_id.eq(user_id)))
+            .execute(conn)?;
+        Ok(())
+    })
+    .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
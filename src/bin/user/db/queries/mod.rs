@@ -1,6 +1,14 @@
 #![allow(dead_code)]
+pub mod account_deletion;
+pub mod audit;
+pub mod email_change;
+pub mod export;
+pub mod impersonation;
+pub mod mfa;
+pub mod suspension;
 pub mod users;
 pub mod users_raw;
+pub mod webauthn;
 ///  Contains raw/direct queries to Database
 
 
@@ -18,4 +26,12 @@ use crate::models::{
     UserPublic,
 };
 
+pub use account_deletion::*;
+pub use audit::*;
+pub use email_change::*;
+pub use export::*;
+pub use impersonation::*;
+pub use mfa::*;
+pub use suspension::*;
 pub use users::*;
+pub use webauthn::*;
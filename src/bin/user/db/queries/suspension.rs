@@ -0,0 +1,180 @@
+use chrono::Local;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use dt::db::schema::{suspensions, users};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    User,
+    Suspension,
+    NewSuspension,
+    SuspensionNotice,
+};
+
+//////////////////////////////////////////
+///////// Suspension Queries /////////////
+//////////////////////////////////////////
+
+/// Suspends the user, replacing any active suspension
+pub fn suspendUser(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    suspension: NewSuspension,
+) -> Result<(User, Suspension), LoginError> {
+
+    let now = Local::now().naive_utc();
+
+    conn.transaction::<(User, Suspension), LoginError, _>(|| {
+        diesel::update(suspensions::table
+            .filter(suspensions::user_id.eq(&suspension.user_id))
+            .filter(suspensions::lifted_at.is_null()))
+            .set((
+                suspensions::lifted_at.eq(now),
+                suspensions::lifted_by.eq(&suspension.admin_id),
+            ))
+            .execute(conn)?;
+
+        let suspension = diesel::insert_into(suspensions::table)
+            .values(&suspension)
+            .get_result::<Suspension>(conn)?;
+
+        let user = diesel::update(users::table.filter(users::id.eq(&suspension.user_id)))
+            .set(users::is_suspended.eq(true))
+            .get_result::<User>(conn)
+            .map_err(|e| LoginError::NoUserError(errJson!(e)))?;
+
+        Ok((user, suspension))
+    })
+}
+
+/// Lifts all of the user's active suspensions, for admins unsuspending them
+pub fn unsuspendUser(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    lifted_by: &str,
+) -> Result<User, LoginError> {
+
+    let now = Local::now().naive_utc();
+
+    conn.transaction::<User, LoginError, _>(|| {
+        diesel::update(suspensions::table
+            .filter(suspensions::user_id.eq(user_id))
+            .filter(suspensions::lifted_at.is_null()))
+            .set((
+                suspensions::lifted_at.eq(now),
+                suspensions::lifted_by.eq(lifted_by),
+            ))
+            .execute(conn)?;
+
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set(users::is_suspended.eq(false))
+            .get_result::<User>(conn)
+            .map_err(|e| LoginError::NoUserError(errJson!(e)))
+    })
+}
+
+/// Lifts this suspension, when it expired, and unsuspends the user unless
+/// they have another active suspension, e.g. an admin suspended them again
+/// since it was loaded. None if it had already been lifted.
+pub fn liftSuspension(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    suspension: &Suspension,
+    lifted_by: Option<&str>,
+) -> Result<Option<User>, LoginError> {
+
+    let now = Local::now().naive_utc();
+
+    conn.transaction::<Option<User>, LoginError, _>(|| {
+        let lifted = diesel::update(suspensions::table
+            .filter(suspensions::id.eq(&suspension.id))
+            .filter(suspensions::lifted_at.is_null()))
+            .set((
+                suspensions::lifted_at.eq(now),
+                suspensions::lifted_by.eq(lifted_by),
+            ))
+            .execute(conn)?;
+        if lifted == 0 {
+            return Ok(None)
+        }
+
+        let still_suspended = diesel::select(diesel::dsl::exists(suspensions::table
+            .filter(suspensions::user_id.eq(&suspension.user_id))
+            .filter(suspensions::lifted_at.is_null())))
+            .get_result::<bool>(conn)?;
+
+        diesel::update(users::table.filter(users::id.eq(&suspension.user_id)))
+            .set(users::is_suspended.eq(still_suspended))
+            .get_result::<User>(conn)
+            .map(Some)
+            .map_err(|e| LoginError::NoUserError(errJson!(e)))
+    })
+}
+
+pub fn getActiveSuspension(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Option<Suspension>, LoginError> {
+
+    suspensions::table
+        .filter(suspensions::user_id.eq(user_id))
+        .filter(suspensions::lifted_at.is_null())
+        .order(suspensions::created_at.desc())
+        .first::<Suspension>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Active suspensions whose `until` has passed
+pub fn getExpiredSuspensions(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Suspension>, LoginError> {
+
+    suspensions::table
+        .filter(suspensions::lifted_at.is_null())
+        .filter(suspensions::until.le(Local::now().naive_utc()))
+        .load::<Suspension>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Passes users who aren't suspended, lifting suspensions which have expired
+/// but haven't been lifted by the background task yet.
+/// Otherwise errors with why the user is suspended and until when.
+pub fn checkSuspension(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user: User,
+) -> Result<User, LoginError> {
+
+    if !user.is_suspended {
+        return Ok(user)
+    }
+
+    match getActiveSuspension(conn, &user.id)? {
+        None => Err(LoginError::AccountSuspended(SuspensionNotice::unknown())),
+        Some(suspension) => match suspension.until {
+            Some(until) if until <= Local::now().naive_utc() => {
+                liftSuspension(conn, &suspension, None)?;
+                // They may have another suspension
+                let user = users::table
+                    .filter(users::id.eq(&user.id))
+                    .first::<User>(conn)
+                    .map_err(|e| LoginError::NoUserError(errJson!(e)))?;
+                checkSuspension(conn, user)
+            },
+            _ => Err(LoginError::AccountSuspended(SuspensionNotice::from(&suspension))),
+        }
+    }
+}
+
+/// The user's suspension history, newest first
+pub fn getSuspensions(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Vec<Suspension>, LoginError> {
+
+    suspensions::table
+        .filter(suspensions::user_id.eq(user_id))
+        .order(suspensions::created_at.desc())
+        .load::<Suspension>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
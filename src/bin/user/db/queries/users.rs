@@ -10,6 +10,10 @@ use crate::models::{
     UpdateUserProfile,
     ErrJson,
     UserPublic,
+    normalize_email,
+    check_password_policy,
+    Connection,
+    UsersConnectionQuery,
 };
 
 use super::users_raw::{
@@ -18,6 +22,7 @@ use super::users_raw::{
     get_user_profile_by_email,
     get_user_profile_by_id,
     get_user_profiles_by_ids,
+    search_users,
     delete_user_profile,
     insert_user_profile,
     update_user_profile,
@@ -72,6 +77,15 @@ pub fn getUsersByIds(
     get_user_profiles_by_ids(conn, &user_ids)
 }
 
+/// Admin user listing, paginated with `B64Cursor`s
+pub fn searchUsers(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    query: &UsersConnectionQuery,
+) -> Result<Connection<User>, LoginError> {
+
+    search_users(conn, query)
+}
+
 pub fn deleteUser(
     conn: &PooledConnection<ConnectionManager<PgConnection>>,
     user_id: &str,
@@ -88,6 +102,13 @@ pub fn createUser(
     last_name: Option<String>,
 ) -> Result<User, LoginError> {
 
+    let email = normalize_email(&email)?;
+    check_password_policy(&password, &[
+        email.as_str(),
+        first_name.as_deref().unwrap_or(""),
+        last_name.as_deref().unwrap_or(""),
+    ])?;
+
     let user = User::new(
         email,
         password,
@@ -102,10 +123,10 @@ pub fn createUser(
     }
 }
 
+/// Emails are changed with `insertPendingEmailChange` and `confirmEmailChange`
 pub fn updateUser(
     conn: &PooledConnection<ConnectionManager<PgConnection>>,
     user_id: UserId,
-    new_email: Option<String>,
     new_first_name: Option<String>,
     new_last_name: Option<String>,
 ) -> Result<User, LoginError> {
@@ -114,9 +135,6 @@ pub fn updateUser(
     let user = get_user_profile_by_id(&conn, &user_id)?;
     let mut update_profile = UpdateUserProfile::from(&user);
 
-    if let Some(e) = new_email {
-        update_profile.update_email(e);
-    }
     if let Some(f) = new_first_name {
         update_profile.update_first_name(f);
     }
@@ -139,10 +157,10 @@ pub fn setNewPassword(
 ) -> Result<User, LoginError> {
 
     let mut user = get_user_profile_by_id(&conn, user_id)?;
-    // credential = hash(salt-id + password)
     match user.verify_credentials(&conn, current_password.to_string()) {
         Err(e) => Err(e),
         Ok(user) => {
+            check_password_policy(new_password, &user.password_inputs())?;
             let new_password_hash = user.generate_new_password_hash(new_password);
             set_new_password(conn, &user.id, &new_password_hash)
         }
@@ -13,7 +13,29 @@ use crate::models::{
 use crate::models::{
     ConnectionQuery,
     PageBasedConnectionQuery,
+    UsersConnectionQuery,
+    UserSortField,
+    Connection,
+    Edge,
+    PageInfo,
+    B64Cursor,
+    PaginateCursor,
+    decode_datetime_cursor,
+    get_page_direction,
 };
+use crate::models::{
+    email_identity,
+    email_identity_collisions,
+    normalize_email,
+};
+use crate::models::NewAccountDeletion;
+
+// Emails are matched on users.email_identity, which has a unique index.
+// See models::email_address.
+sql_function!(fn coalesce(x: diesel::sql_types::Nullable<diesel::sql_types::Text>, y: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+const DEFAULT_USERS_PER_PAGE: i64 = 25;
+const MAX_USERS_PER_PAGE: i64 = 100;
 
 ///////////////////////////////////
 ///  Raw queries direct to Database
@@ -28,7 +50,7 @@ pub fn get_user_profile_by_email(
     use db::schema::users;
 
     users::table
-        .filter(users::email.eq(email))
+        .filter(users::email_identity.eq(email_identity(email)))
         .get_result::<User>(conn)
         .map_err(|e| LoginError::NoUserError(errJson!(e)))
 }
@@ -72,7 +94,7 @@ pub fn login(
     use db::schema::users;
 
     let user = users::table
-        .filter(users::email.eq(&email))
+        .filter(users::email_identity.eq(email_identity(&email)))
         .get_result::<User>(conn);
 
     match user {
@@ -129,19 +151,27 @@ pub fn delete_user_profile(
         Ok(mut user) => match user.verify_credentials(conn, password) {
             Err(e) => Err(LoginError::CredentialsError(errJson!(e))),
             Ok(auth_user) => {
-                // clear the user profile from DB (soft delete)
-                let deleted_user = diesel::update(users::table.filter(users::id.eq(&auth_user.id)))
-                  .set((
-                        users::email.eq(format!("deleted_{}", auth_user.id)),
-                        users::first_name.eq(None as Option<String>),
-                        users::last_name.eq(None as Option<String>),
-                        users::user_role.eq(auth_user.user_role),
-                        users::is_deleted.eq(true),
-                  ))
-                  .get_result::<User>(conn);
+                // Soft delete: the email is freed, and kept in account_deletions
+                // so the account can be restored until it's erased.
+                // See auth::account_deletion
+                use db::schema::account_deletions;
+                let deleted_user = conn.transaction::<User, LoginError, _>(|| {
+                    diesel::insert_into(account_deletions::table)
+                        .values(&NewAccountDeletion::new(&auth_user.id, &auth_user.email))
+                        .execute(conn)?;
+
+                    diesel::update(users::table.filter(users::id.eq(&auth_user.id)))
+                      .set((
+                            users::email.eq(format!("deleted_{}", auth_user.id)),
+                            users::email_identity.eq(None as Option<String>),
+                            users::is_deleted.eq(true),
+                      ))
+                      .get_result::<User>(conn)
+                      .map_err(|e| LoginError::NoUserError(errJson!(e)))
+                });
 
                 match deleted_user {
-                    Err(e) => Err(LoginError::NoUserError(errJson!(e))),
+                    Err(e) => Err(e),
                     Ok(_return_code) => Ok(format!("Deleted user: {}", auth_user.email)),
                 }
             },
@@ -165,9 +195,11 @@ pub fn update_user_profile(
     // import table `users`
     use db::schema::users;
 
+    let email = new.email.unwrap();
     let new_user = diesel::update(users::table.filter(users::id.eq(&new.id)))
         .set((
-            users::email.eq(&new.email.unwrap()),
+            users::email_identity.eq(email_identity(&email)),
+            users::email.eq(&email),
             // users::password_hash.eq(&new.password_hash.unwrap()),
             users::first_name.eq(&new.first_name),
             users::last_name.eq(&new.last_name),
@@ -230,7 +262,7 @@ pub fn set_email_verified(
     // import table `users`
     use db::schema::users;
 
-    let new_user = diesel::update(users::table.filter(users::email.eq(&email)))
+    let new_user = diesel::update(users::table.filter(users::email_identity.eq(email_identity(&email))))
         .set(users::email_verified.eq(&email_verified))
         .get_result::<User>(conn);
 
@@ -241,4 +273,247 @@ pub fn set_email_verified(
 }
 
 
+/// Lists emails used by more than one (non-deleted) account once they're
+/// compared by `email_identity`, with the ids of the accounts sharing them.
+/// Loads every account, so it's only for one-off commands, see main.rs.
+pub fn find_email_identity_collisions(
+    conn: &PgConnection
+) -> Result<Vec<(String, Vec<String>)>, LoginError> {
+    use db::schema::users;
+    // Deleted accounts have placeholder emails, and no identity
+    let emails = users::table
+        .filter(users::is_deleted.eq(false))
+        .select((users::id, users::email))
+        .load::<(String, String)>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+    Ok(email_identity_collisions(&emails))
+}
+
+/// Counts (non-deleted) accounts with no `email_identity` yet, which
+/// can't be found by email until `backfill_email_identities` runs.
+pub fn count_missing_email_identities(conn: &PgConnection) -> Result<i64, LoginError> {
+    use db::schema::users;
+    users::table
+        .filter(users::is_deleted.eq(false))
+        .filter(users::email_identity.is_null())
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Fills in `email_identity` for accounts from before it was added,
+/// normalizing their emails with `normalize_email` on the way, so they
+/// match the lookups. Errors without changing anything if two accounts
+/// would end up with the same identity, they have to be merged or renamed
+/// by hand first. Returns the number of accounts updated.
+pub fn backfill_email_identities(conn: &PgConnection) -> Result<usize, LoginError> {
+    use db::schema::{users, account_deletions};
+
+    let collisions = find_email_identity_collisions(conn)?;
+    if !collisions.is_empty() {
+        for (identity, user_ids) in collisions.iter() {
+            warn!("email collision: {} users: {:?}", identity, user_ids);
+        }
+        return Err(LoginError::DuplicateUser(errJson!(format!(
+            "{} emails are used by more than one account", collisions.len()
+        ))))
+    }
+
+    let accounts = users::table
+        .filter(users::is_deleted.eq(false))
+        .filter(users::email_identity.is_null())
+        .select((users::id, users::email))
+        .load::<(String, String)>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+    let deletions = account_deletions::table
+        .filter(account_deletions::original_email.is_not_null())
+        .filter(account_deletions::original_email_identity.is_null())
+        .select((account_deletions::id, account_deletions::original_email))
+        .load::<(String, Option<String>)>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+    conn.transaction::<usize, diesel::result::Error, _>(|| {
+        let mut updated = 0;
+        for (id, email) in accounts.iter() {
+            // Emails which don't normalize are kept as they are
+            let normalized = normalize_email(email)
+                .unwrap_or_else(|_| email.clone());
+            diesel::update(users::table.filter(users::id.eq(id)))
+                .set((
+                    users::email.eq(&normalized),
+                    users::email_identity.eq(email_identity(email)),
+                ))
+                .execute(conn)?;
+            updated += 1;
+        }
+        for (id, email) in deletions.iter() {
+            let email = email.clone().unwrap_or_default();
+            let normalized = normalize_email(&email)
+                .unwrap_or_else(|_| email.clone());
+            diesel::update(account_deletions::table.filter(account_deletions::id.eq(id)))
+                .set((
+                    account_deletions::original_email.eq(&normalized),
+                    account_deletions::original_email_identity.eq(email_identity(&email)),
+                ))
+                .execute(conn)?;
+        }
+        Ok(updated)
+    })
+    .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+
+/// Escapes LIKE wildcards, so searches match them literally
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace("\\", "\\\\")
+        .replace("%", "\\%")
+        .replace("_", "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Users matching the search filters, without the cursor
+fn filtered_users<'a>(
+    query: &'a UsersConnectionQuery,
+) -> db::schema::users::BoxedQuery<'a, diesel::pg::Pg> {
+
+    use db::schema::users;
+
+    let mut q = users::table.into_boxed();
 
+    if let Some(search) = query.search.as_ref().filter(|s| !s.trim().is_empty()) {
+        let pattern = like_pattern(search.trim());
+        q = q.filter(
+            users::email.ilike(pattern.clone())
+                .or(coalesce(users::first_name, "").ilike(pattern.clone()))
+                .or(coalesce(users::last_name, "").ilike(pattern))
+        );
+    }
+    if let Some(role) = &query.role {
+        q = q.filter(users::user_role.eq(role.clone()));
+    }
+    if let Some(is_suspended) = query.is_suspended {
+        q = q.filter(users::is_suspended.eq(is_suspended));
+    }
+    if let Some(is_deleted) = query.is_deleted {
+        q = q.filter(users::is_deleted.eq(is_deleted));
+    }
+    if let Some(email_verified) = query.email_verified {
+        q = q.filter(users::email_verified.eq(email_verified));
+    }
+    if let Some(created_after) = query.created_after {
+        q = q.filter(users::created_at.ge(created_after));
+    }
+    if let Some(created_before) = query.created_before {
+        q = q.filter(users::created_at.lt(created_before));
+    }
+    // Rows without the sort field can't have a cursor
+    q = match query.sort_by.unwrap_or_default() {
+        UserSortField::CreatedAt => q.filter(users::created_at.is_not_null()),
+        UserSortField::UpdatedAt => q.filter(users::updated_at.is_not_null()),
+    };
+    q
+}
+
+pub fn search_users(
+    conn: &PgConnection,
+    query: &UsersConnectionQuery,
+) -> Result<Connection<User>, LoginError> {
+
+    use db::schema::users;
+
+    let sort_by = query.sort_by.unwrap_or_default();
+    let sort_ascending = query.sort_ascending.unwrap_or(false);
+    let page_backwards = query.page_backwards.unwrap_or(false);
+    let count = query.count
+        .unwrap_or(DEFAULT_USERS_PER_PAGE)
+        .max(1)
+        .min(MAX_USERS_PER_PAGE);
+
+    let total_count = filtered_users(query)
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+    // Results are always fetched moving away from the cursor,
+    // then flipped back if paging backwards.
+    let direction = get_page_direction(sort_ascending, page_backwards);
+    let mut q = filtered_users(query);
+
+    if let Some(cursor) = &query.cursor {
+        let cursor = decode_datetime_cursor(cursor)
+            .map_err(|e| LoginError::BadRequest(errJson!(e)))?;
+
+        if cursor.name != sort_by.column_name() {
+            return Err(LoginError::BadRequest(
+                errJson!(format!("Cursor is for {}, not {}", cursor.name, sort_by.column_name()))))
+        }
+        let (value, id) = match cursor.id {
+            Some(id) => (cursor.value, id),
+            None => return Err(LoginError::BadRequest(errJson!("Cursor is missing the user id"))),
+        };
+        // (sort field, id) compared to the cursor's, as users can share a timestamp
+        q = match (sort_by, direction.lessThan) {
+            (UserSortField::CreatedAt, true) => q.filter(
+                users::created_at.lt(value)
+                    .or(users::created_at.eq(value).and(users::id.lt(id)))),
+            (UserSortField::CreatedAt, false) => q.filter(
+                users::created_at.gt(value)
+                    .or(users::created_at.eq(value).and(users::id.gt(id)))),
+            (UserSortField::UpdatedAt, true) => q.filter(
+                users::updated_at.lt(value)
+                    .or(users::updated_at.eq(value).and(users::id.lt(id)))),
+            (UserSortField::UpdatedAt, false) => q.filter(
+                users::updated_at.gt(value)
+                    .or(users::updated_at.eq(value).and(users::id.gt(id)))),
+        };
+    }
+
+    let (users, _remaining_pages, is_last_page) = q
+        .paginate_by_cursor(
+            sort_by.column_name().to_string(),
+            Some(direction.queryAscending),
+            Some(page_backwards),
+        )
+        .per_page(count)
+        .tiebreak_by("id")
+        .load_and_count_pages::<User>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))?;
+
+    let edges = users.into_iter()
+        .map(|user| {
+            let value = match sort_by {
+                UserSortField::CreatedAt => user.created_at,
+                UserSortField::UpdatedAt => user.updated_at,
+            };
+            Edge {
+                cursor: value.map(|v| B64Cursor {
+                    name: sort_by.column_name().to_string(),
+                    value: v,
+                    id: Some(user.id.clone()),
+                }.to_b64_string()),
+                node: user,
+            }
+        })
+        .collect::<Vec<Edge<User>>>();
+
+    Ok(Connection {
+        pageInfo: PageInfo {
+            startCursor: edges.first().and_then(|e| e.cursor.clone()),
+            endCursor: edges.last().and_then(|e| e.cursor.clone()),
+            isLastPage: is_last_page,
+            totalPages: Some((total_count as f64 / count as f64).ceil() as i64),
+        },
+        edges: edges,
+        totalCount: Some(total_count),
+    })
+}
+
+
+#[test]
+fn like_pattern_escapes_wildcards() {
+    assert_eq!(like_pattern("alice"), "%alice%");
+    assert_eq!(like_pattern("100%_off"), "%100\\%\\_off%");
+    assert_eq!(like_pattern("a\\b"), "%a\\\\b%");
+}
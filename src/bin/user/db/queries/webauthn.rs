@@ -0,0 +1,87 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use dt::db::schema::webauthn_credentials;
+use crate::models::{
+    LoginError,
+    ErrJson,
+    WebauthnCredential,
+    NewWebauthnCredential,
+};
+
+//////////////////////////////////////////
+///////// WebAuthn Queries ///////////////
+//////////////////////////////////////////
+
+pub fn getWebauthnCredentials(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+) -> Result<Vec<WebauthnCredential>, LoginError> {
+
+    webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user_id))
+        .order(webauthn_credentials::created_at.asc())
+        .load::<WebauthnCredential>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+pub fn getWebauthnCredentialById(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    credential_id: &str,
+) -> Result<Option<WebauthnCredential>, LoginError> {
+
+    webauthn_credentials::table
+        .filter(webauthn_credentials::credential_id.eq(credential_id))
+        .get_result::<WebauthnCredential>(conn)
+        .optional()
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+pub fn insertWebauthnCredential(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    credential: NewWebauthnCredential,
+) -> Result<WebauthnCredential, LoginError> {
+
+    diesel::insert_into(webauthn_credentials::table)
+        .values(&credential)
+        .get_result::<WebauthnCredential>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation, _
+            ) => LoginError::BadRequest(errJson!("Passkey is already registered")),
+            _ => LoginError::DatabaseError(errJson!(e)),
+        })
+}
+
+/// Records a successful login with the credential
+pub fn updateWebauthnSignCount(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    id: &str,
+    sign_count: i64,
+) -> Result<WebauthnCredential, LoginError> {
+
+    diesel::update(webauthn_credentials::table
+        .filter(webauthn_credentials::id.eq(id)))
+        .set((
+            webauthn_credentials::sign_count.eq(sign_count),
+            webauthn_credentials::last_used_at.eq(diesel::dsl::now.nullable()),
+        ))
+        .get_result::<WebauthnCredential>(conn)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
+
+/// Returns false if the user has no credential with this id
+pub fn deleteWebauthnCredential(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    id: &str,
+) -> Result<bool, LoginError> {
+
+    diesel::delete(webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user_id))
+        .filter(webauthn_credentials::id.eq(id)))
+        .execute(conn)
+        .map(|deleted| deleted == 1)
+        .map_err(|e| LoginError::DatabaseError(errJson!(e)))
+}
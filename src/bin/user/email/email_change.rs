@@ -0,0 +1,32 @@
+/// STEPS
+/// 1. User asks to change their email at /auth/profile/changeEmail,
+///    with their current password. Nothing changes yet.
+/// 2. A confirmation link is sent to the new email,
+///    and a revert link to the old email.
+/// 3. Confirming at /verify/email/change/confirm applies the change,
+///    and marks the new email verified.
+/// 4. Reverting at /verify/email/change/revert cancels the change, or puts
+///    back the old email if it was already confirmed, and signs out every
+///    session in case the account was taken over.
+///
+/// Link tokens are random, and only stored as sha256 hashes,
+/// see `models::PendingEmailChange`.
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::email::EmailVerifyError;
+
+/// How long the confirmation link works for
+pub const EMAIL_CHANGE_CONFIRM_TTL_HOURS: i64 = 24;
+/// How long the revert link works for, so the real owner
+/// can undo a change made by someone who took over the account
+pub const EMAIL_CHANGE_REVERT_TTL_DAYS: i64 = 7;
+
+pub fn generate_email_change_token() -> Result<String, EmailVerifyError> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| EmailVerifyError::VerificationError(
+            errJson!("Could not generate email change link")
+        ))?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
@@ -4,7 +4,7 @@ use actix_web::{
     HttpResponse,
 };
 use failure::Error;
-use crate::models::ErrJson;
+use crate::models::{ErrJson, PasswordPolicyError};
 
 #[derive(Debug, Fail, Serialize, Deserialize)]
 pub enum EmailVerifyError {
@@ -22,6 +22,8 @@ pub enum EmailVerifyError {
     DeserializationError(ErrJson),
     #[fail(display = "{}", _0)]
     DbError(ErrJson),
+    #[fail(display = "{}", _0)]
+    NotVerified(ErrJson),
 }
 
 impl ResponseError for EmailVerifyError {
@@ -62,6 +64,14 @@ impl ResponseError for EmailVerifyError {
                 HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            EmailVerifyError::NotVerified(ejson) => {
+                HttpResponse::build(StatusCode::FORBIDDEN)
+                    .json(json!({
+                        "status": "EMAIL_NOT_VERIFIED",
+                        "file": ejson.file,
+                        "message": ejson.message,
+                    }))
+            },
        }
     }
 }
@@ -72,6 +82,12 @@ impl From<&serde_json::Error> for EmailVerifyError {
     }
 }
 
+impl From<redis::RedisError> for EmailVerifyError {
+    fn from(e: redis::RedisError) -> Self {
+        EmailVerifyError::DbError(errJson!(e))
+    }
+}
+
 impl From<serde_json::Error> for EmailVerifyError {
     fn from(e: serde_json::Error) -> Self {
         EmailVerifyError::DeserializationError(errJson!(e))
@@ -96,6 +112,8 @@ pub enum PasswordResetError {
     #[fail(display = "{}", _0)]
     DbError(ErrJson),
     #[fail(display = "{}", _0)]
+    PasswordPolicy(PasswordPolicyError),
+    #[fail(display = "{}", _0)]
     Other(ErrJson),
 }
 
@@ -137,6 +155,7 @@ impl ResponseError for PasswordResetError {
                 HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            PasswordResetError::PasswordPolicy(e) => e.error_response(),
             PasswordResetError::Other(ejson) => {
                 warn!("{}: {}", ejson.file, ejson.message);
                 HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
@@ -158,3 +177,14 @@ impl From<serde_json::Error> for PasswordResetError {
     }
 }
 
+impl From<redis::RedisError> for PasswordResetError {
+    fn from(e: redis::RedisError) -> Self {
+        PasswordResetError::Other(errJson!(e))
+    }
+}
+
+impl From<PasswordPolicyError> for PasswordResetError {
+    fn from(e: PasswordPolicyError) -> Self {
+        PasswordResetError::PasswordPolicy(e)
+    }
+}
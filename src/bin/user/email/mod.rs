@@ -1,6 +1,8 @@
 pub mod domain;
+pub mod email_change;
 pub mod password_reset;
 pub mod errors;
+pub mod verify_email;
 
 use actix::{Addr};
 use actix_web::{
@@ -13,6 +15,8 @@ use uuid::Uuid;
 
 pub use errors::EmailVerifyError;
 pub use errors::PasswordResetError;
+pub use verify_email::*;
+pub use email_change::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendgridStatus {
@@ -1,154 +1,212 @@
-
 /// STEPS
-/// 1. Send VerifyEmail: send after "createUser" has saved a user to DB
-/// 2. User checks email, clicks VerifyEmail link, which sends to our endpoint.
-/// 3. Handle VerifyEmail:
-///     if the request contains the correct GUID and before expiry, mark the user
-///     account in the DB as "email_verified"
-
-use actix::Addr;
-use actix_web::{
-    Error,
-    web,
-    HttpResponse, HttpRequest
-};
-use chrono::{Local, Utc, Duration};
-use diesel::PgConnection;
-use diesel::prelude::*;
-use std::convert::From;
-use uuid::Uuid;
+/// 1. /forgot/1/sendResetPasswordEmail: a random reset token is created for
+///    the user, and emailed to them in a reset link. The response is the same
+///    for unknown emails, so it can't be used to find accounts.
+/// 2. User checks email, clicks the link, and posts the token (`reset_id`)
+///    and their new password to /forgot/2/resetPassword.
+/// 3. Handle the reset:
+///     if the token exists and hasn't expired, it is deleted, the password is
+///     changed, every session is signed out, and the user is told by email.
+///
+/// Only a sha256 hash of the token is stored, with its expiry, in redis:
+///     password_reset:{token hash}     => PasswordResetRecord (json)
+///     password_reset_user:{user id}   => token hash of the user's latest reset
+/// A new reset request replaces the previous token. Tokens are taken with an
+/// atomic GET + DEL, so two requests racing with the same token can't both
+/// reset the password.
+
+use actix::{Handler, Message, SyncContext};
+use chrono::{Local, Duration};
+use ring::rand::{SecureRandom, SystemRandom};
 
 ////// Internal Modules //////
 
-use dt::utils::from_datetimestr_to_naivedatetime;
-use dt::utils::{
-    init_logging,
-};
-
+use crate::auth::hash_token;
 use crate::db::{
     DatabaseActor,
 };
-use crate::endpoints::Endpoint;
-use crate::email::{
-    PasswordResetError,
-    SendgridStatus,
-};
-use crate::models::auth::RequestResetPasswordForm;
-use crate::models::{User, ErrJson};
-use crate::{AppState};
-use crate::email::domain::DomainVars;
+use crate::email::PasswordResetError;
+use crate::models::{User, ErrJson, check_password_policy};
+
+/// How long reset links work for
+pub const PASSWORD_RESET_TTL_SECONDS: i64 = 3600; // 1hr
 
 
+/// Stored in redis under the token hash
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct PasswordResetResponse {
-    reset_id: String,
-    email_sent_to: String,
-    status: SendgridStatus,
+pub struct PasswordResetRecord {
+    pub user_id: String,
+    pub email: String,
+    /// unix time, checked as well as the redis TTL
+    pub expires_at: i64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PasswordReset {
+/// A new reset token, sent to the user and never stored
+#[derive(Debug, Clone)]
+pub struct PasswordResetLink {
     pub reset_id: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+fn password_reset_key(token_hash: &str) -> String {
+    format!("password_reset:{}", token_hash)
+}
+
+fn password_reset_user_key(user_id: &str) -> String {
+    format!("password_reset_user:{}", user_id)
+}
+
+fn generate_reset_token() -> Result<String, PasswordResetError> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| PasswordResetError::Other(errJson!("Could not generate reset token")))?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+//////////////////////////////////////////////////
+///// 1. Create a reset token
+//////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePasswordReset {
+    pub user_id: String,
     pub email: String,
-    pub new_password: Option<String>,
-    #[serde(deserialize_with = "from_datetimestr_to_naivedatetime")]
-    pub expires_at: chrono::NaiveDateTime
 }
-// Note on adding new fields: must also add to <password-reset-form.html>
-// for Askama. Otherwise parse error when reset_password_handler() tries
-// to deserialize PasswordReset with no new field.
 
-impl PasswordReset {
-    pub fn updated_password(&mut self, new_password: String) {
-        self.new_password = Some(new_password);
-    }
+impl Message for CreatePasswordReset {
+    type Result = Result<PasswordResetLink, PasswordResetError>;
 }
 
-impl From<&RequestResetPasswordForm> for PasswordReset {
-    fn from<'a>(reset: &'a RequestResetPasswordForm) -> Self {
-        PasswordReset {
-            reset_id: Uuid::new_v4().to_string(),
-            email: reset.email.clone(),
-            new_password: None,
-            expires_at: Local::now().naive_utc() + Duration::hours(48),
+impl Handler<CreatePasswordReset> for DatabaseActor {
+    type Result = Result<PasswordResetLink, PasswordResetError>;
+
+    fn handle(
+        &mut self,
+        msg: CreatePasswordReset,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| PasswordResetError::Other(errJson!(e)))?;
+
+        let reset_id = generate_reset_token()?;
+        let token_hash = hash_token(&reset_id);
+        let expires_at = Local::now() + Duration::seconds(PASSWORD_RESET_TTL_SECONDS);
+
+        let record = serde_json::to_string(&PasswordResetRecord {
+            user_id: msg.user_id.clone(),
+            email: msg.email,
+            expires_at: expires_at.timestamp(),
+        })?;
+
+        let previous: Option<String> = redis::cmd("GET")
+            .arg(password_reset_user_key(&msg.user_id))
+            .query(&mut conn)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(previous_hash) = previous {
+            pipe.cmd("DEL").arg(password_reset_key(&previous_hash)).ignore();
         }
+        pipe.cmd("SETEX").arg(password_reset_key(&token_hash))
+                .arg(PASSWORD_RESET_TTL_SECONDS).arg(record).ignore()
+            .cmd("SETEX").arg(password_reset_user_key(&msg.user_id))
+                .arg(PASSWORD_RESET_TTL_SECONDS).arg(&token_hash).ignore();
+        let _: () = pipe.query(&mut conn)?;
+
+        Ok(PasswordResetLink {
+            reset_id: reset_id,
+            expires_at: expires_at.naive_utc(),
+        })
     }
 }
 
 //////////////////////////////////////////////////
-///// 2. Password Reset response to DatabaseActor
+///// 2. Redeem a reset token
 //////////////////////////////////////////////////
 
-// impl PasswordReset as a Message for DatabaseActor Actor
-impl actix::Message for PasswordReset {
-    type Result = Result<PasswordReset, PasswordResetError>;
+/// Body of /forgot/2/resetPassword.
+/// Nothing else from the client is trusted, the user and expiry
+/// come from the stored record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedeemPasswordReset {
+    pub reset_id: String,
+    pub new_password: String,
+}
+
+impl Message for RedeemPasswordReset {
+    type Result = Result<User, PasswordResetError>;
 }
 
-// Handle PasswordReset Message
-impl actix::Handler<PasswordReset> for DatabaseActor {
-    type Result = Result<PasswordReset, PasswordResetError>;
+impl Handler<RedeemPasswordReset> for DatabaseActor {
+    type Result = Result<User, PasswordResetError>;
 
-    fn handle(&mut self, msg: PasswordReset, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: RedeemPasswordReset, _ctx: &mut Self::Context) -> Self::Result {
 
-        use dt::db::schema::users;
+        let mut rconn = self.get_redis_client()
+            .map_err(|e| PasswordResetError::Other(errJson!(e)))?;
 
-        if Utc::now().timestamp() > msg.expires_at.timestamp() {
-            debug!("password reset expired! {:?}", &msg.expires_at);
+        let token_hash = hash_token(&msg.reset_id);
+        let record: Option<String> = redis::cmd("GET")
+            .arg(password_reset_key(&token_hash))
+            .query(&mut rconn)?;
+
+        let record: PasswordResetRecord = match record {
+            Some(json) => serde_json::from_str(&json)?,
+            None => {
+                return Err(PasswordResetError::VerificationError(
+                    errJson!("Password reset link is invalid or has already been used")
+                ))
+            }
+        };
+
+        if Local::now().timestamp() > record.expires_at {
+            debug!("password reset expired at: {:?}", record.expires_at);
             return Err(
                 PasswordResetError::ResetExpired(errJson!("Password Reset Expired"))
             )
         }
 
-        // Check that the msg.email matches our registration email
-        let email = self.get_from_cache(&msg.reset_id)
-            .map_err(|_e| {
-                debug!("reset_id invalid! {:?}", &msg.reset_id);
-                debug!("for email! {:?}", &msg.email);
-                PasswordResetError::VerificationError(errJson!(&format!(
-                    "Email: {:?} is not the one which requested password reset!",
-                    &msg.email
-                )))
-            })?;
-
-        debug!("resetting password for user with email: {:?}", email);
-
         let conn = self.pool.get()
             .map_err(|e| PasswordResetError::ConnectionPoolError(errJson!(e)))?;
 
-        // Get old user profile, need the user id to generate new credential
-        let user: User = crate::db::getUser(&conn, Some(&email), None)
+        let user: User = crate::db::getUser(&conn, None, Some(&record.user_id))
             .map_err(|e| PasswordResetError::DbError(errJson!(e)))?;
 
-        debug!("success reading user profile: {:?}", user);
-
-        // create new credential = hash(user_id(salt) + password)
-        let new_password_hash = user.generate_new_password_hash(
-            &msg.new_password.clone().expect("new_password to exist"),
-        );
+        // Check the new password before using up the link,
+        // so the user can try again with a stronger one
+        check_password_policy(&msg.new_password, &user.password_inputs())?;
+
+        // Single use: only one request can GET the record before it's deleted
+        let (taken,): (Option<String>,) = redis::pipe()
+            .atomic()
+            .cmd("GET").arg(password_reset_key(&token_hash))
+            .cmd("DEL").arg(password_reset_key(&token_hash)).ignore()
+            .cmd("DEL").arg(password_reset_user_key(&record.user_id)).ignore()
+            .query(&mut rconn)?;
+
+        if taken.is_none() {
+            return Err(PasswordResetError::VerificationError(
+                errJson!("Password reset link is invalid or has already been used")
+            ))
+        }
 
-        let updated_user = diesel::update(
-                users::table.filter(users::email.eq(&email))
-            )
-            .set(users::password_hash.eq(new_password_hash))
-            .get_result::<User>(&conn)
-            .map_err(|e| PasswordResetError::DbError(errJson!(e)))?;
+        debug!("resetting password for user: {:?}", record.user_id);
 
-        debug!("Successfully updated user password in DB: {:?}", updated_user);
+        let new_password_hash = user.generate_new_password_hash(&msg.new_password);
+        let updated_user = crate::db::users_raw::set_new_password(
+            &conn,
+            &user.id,
+            &new_password_hash,
+        ).map_err(|e| PasswordResetError::DbError(errJson!(e)))?;
 
-        // try delete and invalidate reset_id from redis cache
-        let redis_conn = self.get_redis_client()
-            .map_err(|e| PasswordResetError::Other(errJson!(e)));
+        debug!("Successfully updated user password in DB: {:?}", updated_user.id);
 
-        match redis_conn {
-            Err(_e) => {},
-            Ok(mut rconn) => {
-                let _redis_res: redis::RedisResult<String> = redis::cmd("DEL")
-                    .arg(&msg.reset_id)
-                    .query(&mut rconn);
-            }
-        };
+        // Sign out every session issued with the old password
+        self.revoke_user_tokens(&updated_user.id)
+            .map_err(|e| PasswordResetError::Other(errJson!(e)))?;
 
-        Ok(msg)
+        Ok(updated_user)
     }
 }
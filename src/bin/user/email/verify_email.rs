@@ -0,0 +1,292 @@
+/// STEPS
+/// 1. Send VerifyEmail: sent after "createUser" has saved a user to DB,
+///    and again on request at /verify/email/resend
+/// 2. User checks email, clicks the VerifyEmail link, which sends the token
+///    to /verify/email
+/// 3. Handle VerifyEmail:
+///     if the token exists and hasn't expired it is deleted, and if it was
+///     issued for the user's current email, the account is marked "email_verified"
+///
+/// The token is random, and only its sha256 hash is stored in redis:
+///     email_verify:{token hash}      => EmailVerifyRecord (json)
+///     email_verify_user:{user id}    => token hash of the user's latest link
+/// Each link works once: tokens are taken with an atomic GET + DEL.
+/// Resending replaces the previous link.
+///
+/// EMAIL_VERIFICATION_BLOCKS lists actions which need a verified email,
+/// comma separated, e.g. "login,mfa_enroll". Nothing is blocked by default.
+///     login           password and passkey logins
+///     profile_update  /auth/profile/update
+///     password_change /auth/profile/changePassword
+///     mfa_enroll      TOTP and passkey registration
+
+use actix::{Handler, Message, SyncContext};
+use chrono::{Local, Duration};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::auth::hash_token;
+use crate::db::{getUser, DatabaseActor};
+use crate::email::EmailVerifyError;
+use crate::models::{User, ErrJson, email_identity};
+
+pub const EMAIL_VERIFY_TTL_HOURS: i64 = 48;
+
+/// Emails sent per address per window from /verify/email/resend
+pub const MAX_RESENDS_PER_EMAIL: i64 = 3;
+pub const RESEND_WINDOW_SECONDS: i64 = 3600;
+
+lazy_static! {
+    pub static ref EMAIL_VERIFICATION_BLOCKS: Vec<VerifiedEmailAction> =
+        VerifiedEmailAction::from_env();
+}
+
+/// Actions which can be blocked until the user's email is verified
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifiedEmailAction {
+    Login,
+    ProfileUpdate,
+    PasswordChange,
+    MfaEnroll,
+}
+
+impl VerifiedEmailAction {
+    pub fn from_env() -> Vec<Self> {
+        dotenv::dotenv().ok();
+        std::env::var("EMAIL_VERIFICATION_BLOCKS")
+            .map(|s| VerifiedEmailAction::parse_list(&s))
+            .unwrap_or_default()
+    }
+
+    pub fn parse_list(s: &str) -> Vec<Self> {
+        s.split(',')
+            .map(|a| a.trim())
+            .filter(|a| !a.is_empty())
+            .filter_map(|a| {
+                let action = VerifiedEmailAction::parse(a);
+                if action.is_none() {
+                    warn!("Unknown action in EMAIL_VERIFICATION_BLOCKS: {}", a);
+                }
+                action
+            })
+            .collect()
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "login" => Some(VerifiedEmailAction::Login),
+            "profile_update" => Some(VerifiedEmailAction::ProfileUpdate),
+            "password_change" => Some(VerifiedEmailAction::PasswordChange),
+            "mfa_enroll" => Some(VerifiedEmailAction::MfaEnroll),
+            _ => None,
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        EMAIL_VERIFICATION_BLOCKS.contains(self)
+    }
+}
+
+/// Errors with 403 if the action needs a verified email, and the user's isn't
+pub fn check_email_verified(
+    user: &User,
+    action: VerifiedEmailAction,
+) -> Result<(), EmailVerifyError> {
+    if action.is_blocked() && !user.email_verified {
+        return Err(EmailVerifyError::NotVerified(
+            errJson!("Please verify your email address first")
+        ))
+    }
+    Ok(())
+}
+
+/// Same as `check_email_verified`, for handlers which only have the user id.
+/// Only reads the user if the action can be blocked.
+pub fn require_verified_email(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: &str,
+    action: VerifiedEmailAction,
+) -> Result<(), EmailVerifyError> {
+    if !action.is_blocked() {
+        return Ok(())
+    }
+    let user = getUser(conn, None, Some(user_id))
+        .map_err(|e| EmailVerifyError::DbError(errJson!(e)))?;
+    check_email_verified(&user, action)
+}
+
+/// Stored in redis under the token hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailVerifyRecord {
+    pub user_id: String,
+    /// the email the link was sent to
+    pub email: String,
+    /// unix time, checked as well as the redis TTL
+    pub expires_at: i64,
+}
+
+impl EmailVerifyRecord {
+    /// Links are only valid for the email they were sent to,
+    /// so links sent before an email change stop working.
+    pub fn matches_user(&self, user: &User) -> bool {
+        self.user_id == user.id
+            && email_identity(&self.email) == email_identity(&user.email)
+    }
+}
+
+fn email_verify_key(token_hash: &str) -> String {
+    format!("email_verify:{}", token_hash)
+}
+
+fn email_verify_user_key(user_id: &str) -> String {
+    format!("email_verify_user:{}", user_id)
+}
+
+fn generate_verify_token() -> Result<String, EmailVerifyError> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)
+        .map_err(|_| EmailVerifyError::VerificationError(errJson!("Could not generate verification token")))?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+/////////// Message Handlers for DatabaseActor Actor
+
+/// Creates a verification token for the user's email,
+/// replacing their previous one. Returns the token for the link.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateEmailVerification {
+    pub user_id: String,
+    pub email: String,
+}
+
+impl Message for CreateEmailVerification {
+    type Result = Result<String, EmailVerifyError>;
+}
+
+impl Handler<CreateEmailVerification> for DatabaseActor {
+    type Result = Result<String, EmailVerifyError>;
+
+    fn handle(
+        &mut self,
+        msg: CreateEmailVerification,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| EmailVerifyError::DbError(errJson!(e)))?;
+
+        let token = generate_verify_token()?;
+        let token_hash = hash_token(&token);
+        let ttl = Duration::hours(EMAIL_VERIFY_TTL_HOURS);
+
+        let record = serde_json::to_string(&EmailVerifyRecord {
+            user_id: msg.user_id.clone(),
+            email: msg.email,
+            expires_at: (Local::now() + ttl).timestamp(),
+        })?;
+
+        let previous: Option<String> = redis::cmd("GET")
+            .arg(email_verify_user_key(&msg.user_id))
+            .query(&mut conn)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(previous_hash) = previous {
+            pipe.cmd("DEL").arg(email_verify_key(&previous_hash)).ignore();
+        }
+        pipe.cmd("SETEX").arg(email_verify_key(&token_hash))
+                .arg(ttl.num_seconds()).arg(record).ignore()
+            .cmd("SETEX").arg(email_verify_user_key(&msg.user_id))
+                .arg(ttl.num_seconds()).arg(&token_hash).ignore();
+        let _: () = pipe.query(&mut conn)?;
+
+        Ok(token)
+    }
+}
+
+/// Takes a verification token, which only works once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedeemEmailVerification(pub String);
+
+impl Message for RedeemEmailVerification {
+    type Result = Result<EmailVerifyRecord, EmailVerifyError>;
+}
+
+impl Handler<RedeemEmailVerification> for DatabaseActor {
+    type Result = Result<EmailVerifyRecord, EmailVerifyError>;
+
+    fn handle(
+        &mut self,
+        msg: RedeemEmailVerification,
+        _ctx: &mut SyncContext<Self>
+    ) -> Self::Result {
+
+        let mut conn = self.get_redis_client()
+            .map_err(|e| EmailVerifyError::DbError(errJson!(e)))?;
+
+        // Single use: only one request can GET the record before it's deleted
+        let token_hash = hash_token(&msg.0);
+        let (taken,): (Option<String>,) = redis::pipe()
+            .atomic()
+            .cmd("GET").arg(email_verify_key(&token_hash))
+            .cmd("DEL").arg(email_verify_key(&token_hash)).ignore()
+            .query(&mut conn)?;
+
+        let record: EmailVerifyRecord = match taken {
+            Some(json) => serde_json::from_str(&json)?,
+            None => return Err(EmailVerifyError::VerificationError(
+                errJson!("Email verification link is invalid, expired, or has already been used")
+            )),
+        };
+
+        if Local::now().timestamp() > record.expires_at {
+            return Err(EmailVerifyError::VerificationError(
+                errJson!("Email verification link is invalid, expired, or has already been used")
+            ))
+        }
+
+        let _: () = redis::cmd("DEL")
+            .arg(email_verify_user_key(&record.user_id))
+            .query(&mut conn)?;
+
+        Ok(record)
+    }
+}
+
+pub fn resend_count_key(email: &str) -> String {
+    format!("verify_email_resend:{}", email_identity(email))
+}
+
+
+#[test]
+fn parses_email_verification_blocks() {
+    assert_eq!(
+        VerifiedEmailAction::parse_list("login, mfa_enroll"),
+        vec![VerifiedEmailAction::Login, VerifiedEmailAction::MfaEnroll]
+    );
+    assert_eq!(
+        VerifiedEmailAction::parse_list("PROFILE_UPDATE,,password_change,bogus"),
+        vec![VerifiedEmailAction::ProfileUpdate, VerifiedEmailAction::PasswordChange]
+    );
+    assert!(VerifiedEmailAction::parse_list("").is_empty());
+}
+
+#[test]
+fn verification_links_are_only_valid_for_the_email_they_were_sent_to() {
+    let user = User::new(
+        String::from("jack@black.com"),
+        String::from("tenacious"),
+        None,
+        None,
+    );
+    let record = EmailVerifyRecord {
+        user_id: user.id.clone(),
+        email: String::from("Jack@Black.com"),
+        expires_at: 0,
+    };
+    assert!(record.matches_user(&user));
+    assert!(!EmailVerifyRecord { email: String::from("kyle@gass.com"), ..record }.matches_user(&user));
+    assert!(!email_verify_key("hash").contains("jack"));
+}
@@ -67,12 +67,15 @@ use dt::utils::{
 };
 
 #[macro_use] mod macros;
+mod audit;
 mod auth;
+mod data_export;
 mod db;
 mod email;
 mod endpoints;
 mod models;
 mod notify_client;
+mod rate_limit;
 mod redis_client;
 mod rest;
 mod rpc;
@@ -81,6 +84,10 @@ mod bug_reporting;
 use db::{
     DatabaseActor,
 };
+use auth::{
+    Permission,
+    RequirePermission,
+};
 use redis_client::{
     start_redis_server,
     start_redis_client,
@@ -89,9 +96,16 @@ use redis_client::{
 use notify_client::{
     NotifyActor
 };
+use rate_limit::{
+    RateLimit,
+    RateLimitBy,
+    RateLimitPolicy,
+    RedisStore,
+};
 use rest::{
     handle_404,
     login_handler,
+    login_mfa_handler,
     logout_handler,
     // read user profile
     get_profile_handler,
@@ -112,7 +126,54 @@ use rest::{
     // User suspension
     suspend_user_handler,
     unsuspend_user_handler,
+    clear_lockout_handler,
+    admin_list_users_handler,
     check_password_handler,
+    // Restoring deleted accounts
+    restore_account_handler,
+    admin_restore_user_handler,
+    // Refresh token rotation
+    refresh_token_handler,
+    // Public JWT verification keys
+    jwks_handler,
+    // Token introspection, for the gateway
+    introspect_handler,
+    // Two-factor authentication
+    get_mfa_status_handler,
+    enroll_totp_handler,
+    verify_totp_handler,
+    disable_totp_handler,
+    // Passkeys
+    webauthn_register_start_handler,
+    webauthn_register_finish_handler,
+    get_webauthn_credentials_handler,
+    delete_webauthn_credential_handler,
+    webauthn_login_start_handler,
+    webauthn_login_finish_handler,
+    // Passwordless login
+    send_magic_link_handler,
+    redeem_magic_link_handler,
+    // Email verification
+    verify_email_handler,
+    resend_verify_email_handler,
+    // Email changes
+    change_email_handler,
+    confirm_email_change_handler,
+    revert_email_change_handler,
+    // Sessions
+    list_sessions_handler,
+    revoke_session_handler,
+    revoke_other_sessions_handler,
+    admin_revoke_sessions_handler,
+    // Admin impersonation
+    impersonate_handler,
+    // Audit log
+    admin_audit_events_handler,
+    my_audit_events_handler,
+    // Personal data export
+    create_data_export_handler,
+    data_export_status_handler,
+    download_data_export_handler,
 };
 
 //// Constants
@@ -121,6 +182,36 @@ const IP: &str = "0.0.0.0";
 const DEFAULT_MAX_DB_CONNECTIONS: u32 = 4;
 const NUM_ACTOR_THREADS: usize = 2;
 
+/////////////////////////////////////////////////
+//// One-off maintenance commands
+/////////////////////////////////////////////////
+
+fn run_command(command: &str) -> std::io::Result<()> {
+    use db::queries::users_raw;
+    let conn = dt::db::establish_connection_pg();
+    let result = match command {
+        // Lists accounts which share an email once compared by email_identity,
+        // these have to be merged or renamed before the backfill
+        "email-collisions" => users_raw::find_email_identity_collisions(&conn)
+            .map(|collisions| {
+                for (identity, user_ids) in collisions.iter() {
+                    println!("{}\t{}", identity, user_ids.join(","));
+                }
+                info!("{} emails are used by more than one account", collisions.len());
+            }),
+        // Fills in email_identity for accounts from before it was added
+        "backfill-email-identities" => users_raw::backfill_email_identities(&conn)
+            .map(|n| info!("Backfilled email identities for {} accounts", n)),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown command: {}, expected email-collisions or backfill-email-identities", command),
+            ))
+        }
+    };
+    result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", e)))
+}
+
 /////////////////////////////////////////////////
 //// The entry point for the Login Service
 /////////////////////////////////////////////////
@@ -129,6 +220,10 @@ const NUM_ACTOR_THREADS: usize = 2;
 async fn main() -> std::io::Result<()> {
 
     init_logging("user", "debug");
+    // One-off maintenance commands, e.g. `dt_user email-collisions`
+    if let Some(command) = std::env::args().nth(1) {
+        return run_command(&command);
+    }
     start_redis_server().ok();
 
     //// DB Pool Constants
@@ -152,6 +247,29 @@ async fn main() -> std::io::Result<()> {
     // debug!("create_jwt_secret...");
     let (secret, domain) = auth::create_jwt_secret();
     // debug!("create_jwt_secret...{} {}", &secret, &domain);
+    // Load JWT signing keys now, rather than panic on the first login
+    lazy_static::initialize(&auth::JWT_KEYS);
+    lazy_static::initialize(&auth::PERMISSIONS);
+    lazy_static::initialize(&models::ARGON2_PARAMS);
+    lazy_static::initialize(&models::PASSWORD_POLICY);
+    lazy_static::initialize(&email::EMAIL_VERIFICATION_BLOCKS);
+    lazy_static::initialize(&auth::FAKE_CREDENTIAL_KEY);
+
+    // Accounts are looked up by email_identity, which is filled in for
+    // older accounts by the `backfill-email-identities` command
+    match db::queries::users_raw::count_missing_email_identities(&dt::db::establish_connection_pg()) {
+        Ok(0) => {},
+        Ok(n) => error!(
+            "{} accounts have no email identity and can't log in, run `dt_user backfill-email-identities`",
+            n
+        ),
+        Err(e) => error!("Couldn't count missing email identities: {:?}", e),
+    };
+
+    // Lift suspensions when their `until` time passes
+    auth::spawn_suspension_lifter(database_actor.clone());
+    // Erase deleted accounts when their grace period ends
+    auth::spawn_account_eraser(database_actor.clone());
 
     // Start the http server
     HttpServer::new(move || {
@@ -187,16 +305,78 @@ async fn main() -> std::io::Result<()> {
                 .route(web::post().to(update_profile_handler)))
             .service(web::resource("/profile/changePassword")
                 .route(web::post().to(change_password_handler)))
+            .service(web::resource("/profile/changeEmail")
+                .route(web::post().to(change_email_handler)))
             .service(web::resource("/profile/delete")
                 .route(web::post().to(delete_profile_handler)))
+            // Two-factor authentication
+            .service(web::resource("/mfa")
+                .route(web::get().to(get_mfa_status_handler)))
+            .service(web::resource("/mfa/totp/enroll")
+                .route(web::post().to(enroll_totp_handler)))
+            .service(web::resource("/mfa/totp/verify")
+                .route(web::post().to(verify_totp_handler)))
+            .service(web::resource("/mfa/totp/disable")
+                .route(web::post().to(disable_totp_handler)))
+            // Passkeys
+            .service(web::resource("/webauthn/register/start")
+                .route(web::post().to(webauthn_register_start_handler)))
+            .service(web::resource("/webauthn/register/finish")
+                .route(web::post().to(webauthn_register_finish_handler)))
+            .service(web::resource("/webauthn/credentials")
+                .route(web::get().to(get_webauthn_credentials_handler)))
+            .service(web::resource("/webauthn/credentials/delete")
+                .route(web::post().to(delete_webauthn_credential_handler)))
+            // Sessions
+            .service(web::resource("/sessions")
+                .route(web::get().to(list_sessions_handler)))
+            .service(web::resource("/sessions/revoke")
+                .route(web::post().to(revoke_session_handler)))
+            .service(web::resource("/sessions/revokeOthers")
+                .route(web::post().to(revoke_other_sessions_handler)))
+            // Security events on the user's account
+            .service(web::resource("/audit")
+                .route(web::get().to(my_audit_events_handler)))
+            // Personal data export
+            .service(web::resource("/export")
+                .wrap(RateLimit::new(
+                    RedisStore::new(database_actor.clone()),
+                    RateLimitPolicy::new("data_export", 3, 86400).by(RateLimitBy::User),
+                ))
+                .route(web::post().to(create_data_export_handler)))
+            .service(web::resource("/export/status")
+                .route(web::get().to(data_export_status_handler)))
             // Auth ID decryption, for gateway
             .service(web::resource("/id")
                 .route(web::get().to(get_id_from_set_cookie)))
             // Admin only
             .service(web::resource("/profile/suspendUser")
+                .wrap(RequirePermission::new(Permission::UsersSuspend))
                 .route(web::get().to(suspend_user_handler)))
             .service(web::resource("/profile/unsuspendUser")
+                .wrap(RequirePermission::new(Permission::UsersSuspend))
                 .route(web::get().to(unsuspend_user_handler)))
+            .service(web::resource("/lockout/clear")
+                .wrap(RequirePermission::new(Permission::UsersUnlock))
+                .route(web::post().to(clear_lockout_handler)))
+            .service(web::resource("/admin/users/restore")
+                .wrap(RequirePermission::new(Permission::UsersRestore))
+                .route(web::post().to(admin_restore_user_handler)))
+            .service(web::resource("/admin/sessions/revoke")
+                .wrap(RequirePermission::new(Permission::UsersRevokeSessions))
+                .route(web::post().to(admin_revoke_sessions_handler)))
+            .service(web::resource("/impersonate")
+                .wrap(RequirePermission::new(Permission::UsersImpersonate))
+                .route(web::post().to(impersonate_handler)))
+            .service(web::resource("/admin/audit")
+                .wrap(RequirePermission::new(Permission::AuditRead))
+                .route(web::get().to(admin_audit_events_handler)))
+        )
+        // Admin user search, outside '/auth' but RequirePermission
+        // still needs a JWT with "users:list"
+        .service(web::resource("/admin/users")
+            .wrap(RequirePermission::new(Permission::UsersList))
+            .route(web::get().to(admin_list_users_handler))
         )
         /////////////////////////////////////
         ////////// Non Auth Routes //////////
@@ -204,28 +384,102 @@ async fn main() -> std::io::Result<()> {
         .service(web::resource("/user/get")
             .route(web::get().to(get_user_handler))
         )
+        // Service or admin credentials checked in the handler
         .service(web::resource("/user/get/by/email")
+            .wrap(RateLimit::new(
+                RedisStore::new(database_actor.clone()),
+                RateLimitPolicy::new("user_get_by_email", 60, 60).by(RateLimitBy::User),
+            ))
             .route(web::get().to(get_user_by_email_handler))
         )
         .service(web::resource("/user/create")
+            .wrap(RateLimit::new(
+                RedisStore::new(database_actor.clone()),
+                RateLimitPolicy::new("user_create", 10, 3600),
+            ))
             .route(web::post().to(create_user_handler))
         )
         .service(web::resource("/users/read/many")
+            .wrap(RateLimit::new(
+                RedisStore::new(database_actor.clone()),
+                RateLimitPolicy::new("users_read_many", 120, 60).by(RateLimitBy::User),
+            ))
             .route(web::post().to(get_users_by_ids))
         )
         .service(web::resource("/login")
             .route(web::post().to(login_handler))
         )
+        .service(web::resource("/login/mfa")
+            .wrap(RateLimit::new(
+                RedisStore::new(database_actor.clone()),
+                RateLimitPolicy::new("login_mfa", 30, 900),
+            ))
+            .route(web::post().to(login_mfa_handler))
+        )
+        .service(web::resource("/login/webauthn/start")
+            .route(web::post().to(webauthn_login_start_handler))
+        )
+        .service(web::resource("/login/webauthn/finish")
+            .route(web::post().to(webauthn_login_finish_handler))
+        )
+        .service(web::resource("/login/magic/send")
+            .route(web::post().to(send_magic_link_handler))
+        )
+        .service(web::resource("/login/magic/redeem")
+            .route(web::post().to(redeem_magic_link_handler))
+        )
+        // Owner restores their deleted account during the grace period
+        .service(web::resource("/account/restore")
+            .wrap(RateLimit::new(
+                RedisStore::new(database_actor.clone()),
+                RateLimitPolicy::new("account_restore", 10, 3600),
+            ))
+            .route(web::post().to(restore_account_handler))
+        )
         .service(web::resource("/logout")
             .route(web::delete().to(logout_handler))
         )
+        .service(web::resource("/token/refresh")
+            .route(web::post().to(refresh_token_handler))
+        )
+        .service(web::resource("/.well-known/jwks.json")
+            .route(web::get().to(jwks_handler))
+        )
+        .service(web::resource("/oauth/introspect")
+            .route(web::post().to(introspect_handler))
+        )
         .service(web::resource("/check/password")
             .route(web::post().to(check_password_handler))
         )
+        // One-time download link for a personal data export
+        .service(web::resource("/export/download")
+            .route(web::post().to(download_data_export_handler))
+        )
+        //// Email verification
+        .service(web::resource("/verify/email")
+            .route(web::post().to(verify_email_handler))
+        )
+        .service(web::resource("/verify/email/resend")
+            .wrap(RateLimit::new(
+                RedisStore::new(database_actor.clone()),
+                RateLimitPolicy::new("verify_email_resend", 10, 3600),
+            ))
+            .route(web::post().to(resend_verify_email_handler))
+        )
+        .service(web::resource("/verify/email/change/confirm")
+            .route(web::post().to(confirm_email_change_handler))
+        )
+        .service(web::resource("/verify/email/change/revert")
+            .route(web::post().to(revert_email_change_handler))
+        )
         //// Password Reset
         .service(web::scope("/forgot")
             // 1. Request password reset email
             .service(web::resource("/1/sendResetPasswordEmail")
+                .wrap(RateLimit::new(
+                    RedisStore::new(database_actor.clone()),
+                    RateLimitPolicy::new("send_reset_password_email", 5, 3600),
+                ))
                 .route(web::post().to(send_reset_password_email_handler)))
             // 2. Password reset form posts to this endpoint
             .service(web::resource("/2/resetPassword")
@@ -0,0 +1,74 @@
+use diesel::prelude::*;
+use dt::db::schema::account_deletions;
+use crate::models::email_identity;
+
+//////////////////////////////////////////////
+/// Account Deletions
+//////////////////////////////////////////////
+
+/// A soft deleted account. It can be restored by the user or an admin
+/// until `erase_after`, then it's erased, see `auth::account_deletion`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDeletion {
+    pub id: String,
+    pub user_id: String,
+    /// The email to restore the account with, None once erased
+    pub original_email: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub erase_after: chrono::NaiveDateTime,
+    pub restored_at: Option<chrono::NaiveDateTime>,
+    /// The user, or the admin who restored it
+    pub restored_by: Option<String>,
+    pub erased_at: Option<chrono::NaiveDateTime>,
+    /// `email_identity` of the original email, for looking it up
+    #[serde(skip)]
+    pub original_email_identity: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "account_deletions"]
+pub struct NewAccountDeletion {
+    pub id: String,
+    pub user_id: String,
+    pub original_email: Option<String>,
+    pub erase_after: chrono::NaiveDateTime,
+    pub original_email_identity: Option<String>,
+}
+
+impl NewAccountDeletion {
+    pub fn new(user_id: &str, original_email: &str) -> Self {
+        NewAccountDeletion {
+            id: format!("del_{}", uuid::Uuid::new_v4()),
+            user_id: user_id.to_string(),
+            original_email: Some(original_email.to_string()),
+            erase_after: chrono::Local::now().naive_utc() + account_deletion_grace_period(),
+            original_email_identity: Some(email_identity(original_email)),
+        }
+    }
+}
+
+const DEFAULT_ACCOUNT_DELETION_GRACE_DAYS: i64 = 30;
+
+/// How long deleted accounts can be restored for,
+/// set with ACCOUNT_DELETION_GRACE_DAYS (default 30)
+pub fn account_deletion_grace_period() -> chrono::Duration {
+    let days = std::env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|d| *d >= 0)
+        .unwrap_or(DEFAULT_ACCOUNT_DELETION_GRACE_DAYS);
+    chrono::Duration::days(days)
+}
+
+
+#[test]
+fn new_account_deletion_is_restorable_for_the_grace_period() {
+    let deletion = NewAccountDeletion::new("u123", "jack@black.com");
+    let erase_in = deletion.erase_after - chrono::Local::now().naive_utc();
+    assert!(deletion.id.starts_with("del_"));
+    assert_eq!(deletion.original_email, Some(String::from("jack@black.com")));
+    assert_eq!(deletion.original_email_identity, Some(String::from("jack@black.com")));
+    assert!(erase_in <= account_deletion_grace_period());
+    assert!(erase_in > account_deletion_grace_period() - chrono::Duration::minutes(1));
+}
@@ -0,0 +1,200 @@
+use diesel::prelude::*;
+use dt::db::schema::audit_events;
+
+//////////////////////////////////////////////
+/// Audit Events
+//////////////////////////////////////////////
+
+/// A security event in the append-only audit log, see `audit`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub id: String,
+    /// who did it, None if nobody was logged in (e.g. failed logins)
+    pub actor_id: Option<String>,
+    /// the account it was done to
+    pub user_id: Option<String>,
+    pub action: String,
+    pub outcome: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub payload: serde_json::Value,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Payload fields only admins see, e.g. why an admin suspended or
+/// impersonated someone, or which IP's lockout they cleared
+const ADMIN_PAYLOAD_FIELDS: &[&str] = &["reason", "ip", "impersonationId"];
+
+impl AuditEvent {
+    /// The event as shown to `user_id` at GET /auth/audit. When someone else
+    /// did it (an admin, also when impersonating them), it doesn't say who,
+    /// or where from, and the admin's payload fields are dropped.
+    pub fn for_user(mut self, user_id: &str) -> AuditEvent {
+        match &self.actor_id {
+            Some(actor_id) if actor_id != user_id => {
+                self.actor_id = None;
+                self.ip = None;
+                self.user_agent = None;
+                if let Some(payload) = self.payload.as_object_mut() {
+                    for field in ADMIN_PAYLOAD_FIELDS {
+                        payload.remove(*field);
+                    }
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub user_id: Option<String>,
+    pub action: String,
+    pub outcome: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    Logout,
+    PasswordChange,
+    PasswordReset,
+    EmailChange,
+    EmailChangeConfirm,
+    EmailChangeRevert,
+    AccountDelete,
+    AccountRestore,
+    AccountErase,
+    MfaEnroll,
+    MfaDisable,
+    PasskeyRegister,
+    PasskeyDelete,
+    SessionRevoke,
+    Suspend,
+    Unsuspend,
+    LockoutClear,
+    Impersonate,
+    DataExport,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            AuditAction::Login => "login",
+            AuditAction::Logout => "logout",
+            AuditAction::PasswordChange => "password_change",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::EmailChange => "email_change",
+            AuditAction::EmailChangeConfirm => "email_change_confirm",
+            AuditAction::EmailChangeRevert => "email_change_revert",
+            AuditAction::AccountDelete => "account_delete",
+            AuditAction::AccountRestore => "account_restore",
+            AuditAction::AccountErase => "account_erase",
+            AuditAction::MfaEnroll => "mfa_enroll",
+            AuditAction::MfaDisable => "mfa_disable",
+            AuditAction::PasskeyRegister => "passkey_register",
+            AuditAction::PasskeyDelete => "passkey_delete",
+            AuditAction::SessionRevoke => "session_revoke",
+            AuditAction::Suspend => "suspend",
+            AuditAction::Unsuspend => "unsuspend",
+            AuditAction::LockoutClear => "lockout_clear",
+            AuditAction::Impersonate => "impersonate",
+            AuditAction::DataExport => "data_export",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            AuditOutcome::Success => "SUCCESS",
+            AuditOutcome::Failure => "FAILURE",
+        }
+    }
+}
+
+/// Filters for listing audit events, all optional.
+/// Events are listed newest first, `cursor` is the `endCursor`
+/// of the previous page.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventQuery {
+    pub user_id: Option<String>,
+    pub actor_id: Option<String>,
+    pub action: Option<AuditAction>,
+    pub outcome: Option<AuditOutcome>,
+    pub ip: Option<String>,
+    /// e.g. 2020-07-16T00:00:00
+    pub created_after: Option<chrono::NaiveDateTime>,
+    pub created_before: Option<chrono::NaiveDateTime>,
+    pub cursor: Option<String>,
+    pub count: Option<i64>,
+}
+
+
+#[test]
+fn audit_action_serializes_as_str() {
+    for action in &[
+        AuditAction::Login,
+        AuditAction::PasswordReset,
+        AuditAction::EmailChangeConfirm,
+        AuditAction::LockoutClear,
+    ] {
+        assert_eq!(
+            serde_json::to_value(action).unwrap(),
+            json!(action.as_str()),
+        );
+    }
+    assert_eq!(serde_json::to_value(AuditOutcome::Failure).unwrap(), json!("FAILURE"));
+}
+
+#[test]
+fn users_dont_see_who_did_things_to_them_or_why() {
+    let event = AuditEvent {
+        id: "aud_1".to_string(),
+        actor_id: Some("uadmin".to_string()),
+        user_id: Some("u123".to_string()),
+        action: AuditAction::Suspend.as_str().to_string(),
+        outcome: AuditOutcome::Success.as_str().to_string(),
+        ip: Some("10.0.0.1".to_string()),
+        user_agent: Some("curl".to_string()),
+        payload: json!({ "suspensionId": "s1", "reason": "chargebacks", "until": null }),
+        created_at: chrono::NaiveDate::from_ymd(2020, 7, 16).and_hms(0, 0, 0),
+    };
+    let seen = event.clone().for_user("u123");
+    assert_eq!(seen.actor_id, None);
+    assert_eq!(seen.ip, None);
+    assert_eq!(seen.user_agent, None);
+    assert_eq!(seen.payload, json!({ "suspensionId": "s1", "until": null }));
+
+    // Their own events are left as they are
+    let own = AuditEvent {
+        actor_id: Some("u123".to_string()),
+        action: AuditAction::Login.as_str().to_string(),
+        payload: json!({ "method": "password", "reason": "wrong_password" }),
+        ..event
+    };
+    assert_eq!(own.clone().for_user("u123"), own);
+}
@@ -1,4 +1,4 @@
-
+use crate::models::UserRole;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionQuery {
@@ -18,6 +18,51 @@ impl ConnectionQuery {
     }
 }
 
+/// Admin user search, see `searchUsers`.
+/// All filters are optional, `search` matches part of the email or names.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsersConnectionQuery {
+    pub search: Option<String>,
+    pub role: Option<UserRole>,
+    pub is_suspended: Option<bool>,
+    pub is_deleted: Option<bool>,
+    pub email_verified: Option<bool>,
+    /// e.g. 2020-07-16T00:00:00
+    pub created_after: Option<chrono::NaiveDateTime>,
+    pub created_before: Option<chrono::NaiveDateTime>,
+    pub sort_by: Option<UserSortField>,
+    pub sort_ascending: Option<bool>,
+    pub cursor: Option<String>,
+    pub page_backwards: Option<bool>,
+    pub count: Option<i64>,
+}
+
+/// Fields users can be sorted on. Cursors are timestamps (`B64Cursor`),
+/// so only timestamp fields can be paginated.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UserSortField {
+    #[serde(rename = "createdAt")]
+    CreatedAt,
+    #[serde(rename = "updatedAt")]
+    UpdatedAt,
+}
+
+impl UserSortField {
+    pub fn column_name(&self) -> &str {
+        match *self {
+            UserSortField::CreatedAt => "created_at",
+            UserSortField::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+impl std::default::Default for UserSortField {
+    fn default() -> Self {
+        UserSortField::CreatedAt
+    }
+}
+
 #[derive(Debug, Clone, QueryId, Serialize, Deserialize)]
 pub struct Connection<T> {
     pub pageInfo: PageInfo,
@@ -28,6 +73,7 @@ impl<T> Connection<T> {
     pub fn new() -> Self {
         Connection {
             pageInfo: PageInfo {
+                startCursor: None,
                 endCursor: None,
                 isLastPage: true,
                 totalPages: None,
@@ -40,6 +86,8 @@ impl<T> Connection<T> {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageInfo {
+    /// Cursor of the first edge, to page backwards from
+    pub startCursor: Option<String>,
     pub endCursor: Option<String>,
     pub isLastPage: bool,
     pub totalPages: Option<i64>,
@@ -119,4 +167,25 @@ fn deserializes_connection_query_cursor_some() {
         Ok(cnn) => assert_eq!(cnn.cursor, Some(String::from("test_cursor"))),
         Err(e) => panic!(format!("{:?}", e)),
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn deserializes_users_connection_query() {
+
+    let test_str = r#"
+    {
+        "search": "alice",
+        "role": "PLATFORM_ADMIN",
+        "isSuspended": false,
+        "sortBy": "updatedAt",
+        "count": 5
+    }
+    "#;
+
+    let query = serde_json::from_str::<UsersConnectionQuery>(test_str).unwrap();
+    assert_eq!(query.role, Some(UserRole::PLATFORM_ADMIN));
+    assert_eq!(query.is_suspended, Some(false));
+    assert_eq!(query.sort_by, Some(UserSortField::UpdatedAt));
+    assert_eq!(query.cursor, None);
+    assert!(serde_json::from_str::<UsersConnectionQuery>(r#"{ "sortBy": "password_hash" }"#).is_err());
+}
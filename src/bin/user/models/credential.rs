@@ -0,0 +1,255 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+use std::num::NonZeroU32;
+
+use crate::models::{ LoginError, ErrJson };
+
+/////////////////////////////////////////////
+/// Password hashing
+/////////////////////////////////////////////
+/// Passwords are hashed with Argon2id and stored in PHC string format:
+///     $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+/// with a random 16 byte salt per hash. The cost is read from env:
+///     ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_PARALLELISM
+///
+/// Older accounts have bare base64 PBKDF2-HMAC-SHA256 hashes (20,000 rounds,
+/// salted with the user id). They are still accepted, and are upgraded to
+/// Argon2id on the next successful login, as are hashes with an outdated cost.
+
+const ARGON2_PREFIX: &str = "$argon2id$";
+const SALT_LEN: usize = 16;
+const HASH_LEN: u32 = 32;
+
+// OWASP recommended minimums for Argon2id
+const DEFAULT_MEMORY_KIB: u32 = 19_456;
+const DEFAULT_ITERATIONS: u32 = 2;
+const DEFAULT_PARALLELISM: u32 = 1;
+
+// Legacy PBKDF2 hashes
+static LEGACY_DIGEST_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const LEGACY_CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
+const LEGACY_PBKDF2_ITERATIONS: u32 = 20_000;
+
+lazy_static! {
+    pub static ref ARGON2_PARAMS: Argon2Params = Argon2Params::from_env();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Argon2Params {
+    pub fn from_env() -> Self {
+        dotenv::dotenv().ok();
+
+        let var = |name: &str, default: u32| std::env::var(name).ok()
+            .and_then(|s| s.parse::<u32>().ok())
+            .unwrap_or(default);
+
+        let params = Argon2Params {
+            memory_kib: var("ARGON2_MEMORY_KIB", DEFAULT_MEMORY_KIB),
+            iterations: var("ARGON2_ITERATIONS", DEFAULT_ITERATIONS),
+            parallelism: var("ARGON2_PARALLELISM", DEFAULT_PARALLELISM),
+        };
+
+        if params.is_valid() {
+            params
+        } else {
+            warn!("Invalid Argon2 params: {:?}, using defaults", params);
+            Argon2Params::default()
+        }
+    }
+
+    /// Argon2 requires at least 1 iteration, 1 lane, and 8KiB of memory per lane
+    fn is_valid(&self) -> bool {
+        self.iterations >= 1
+            && self.parallelism >= 1
+            && self.parallelism <= 0xFF_FFFF
+            && self.memory_kib >= 8 * self.parallelism
+    }
+
+    fn config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.memory_kib,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            thread_mode: argon2::ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: HASH_LEN,
+        }
+    }
+
+    /// Reads the params from a PHC string, e.g. "m=19456,t=2,p=1"
+    fn from_phc(hash: &str) -> Option<Self> {
+        let params = hash.split('$').nth(3)?;
+        let mut memory_kib = None;
+        let mut iterations = None;
+        let mut parallelism = None;
+        for param in params.split(',') {
+            let mut kv = param.splitn(2, '=');
+            let (k, v) = (kv.next()?, kv.next()?.parse::<u32>().ok()?);
+            match k {
+                "m" => memory_kib = Some(v),
+                "t" => iterations = Some(v),
+                "p" => parallelism = Some(v),
+                _ => return None,
+            }
+        }
+        Some(Argon2Params {
+            memory_kib: memory_kib?,
+            iterations: iterations?,
+            parallelism: parallelism?,
+        })
+    }
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Argon2Params {
+            memory_kib: DEFAULT_MEMORY_KIB,
+            iterations: DEFAULT_ITERATIONS,
+            parallelism: DEFAULT_PARALLELISM,
+        }
+    }
+}
+
+/// Result of checking a password against a stored hash
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordMatch {
+    /// Hash is up to date
+    Current,
+    /// Password is right, but the hash is PBKDF2 or has an outdated cost,
+    /// and should be replaced with `generate_credential`
+    NeedsRehash,
+}
+
+/// Hashes a password with Argon2id and a random salt, in PHC format
+pub fn generate_credential(password: &str) -> String {
+    hash_password(&ARGON2_PARAMS, password)
+}
+
+fn hash_password(params: &Argon2Params, password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new().fill(&mut salt)
+        .expect("Could not generate password salt");
+    // Only fails for invalid params, which `Argon2Params::from_env` rules out
+    argon2::hash_encoded(password.as_bytes(), &salt, &params.config())
+        .expect("Argon2 params are invalid")
+}
+
+/// Checks a password against a stored Argon2id or legacy PBKDF2 hash.
+/// `legacy_salt` is the user id, which salted the PBKDF2 hashes.
+pub fn verify_password(
+    password_hash: &str,
+    legacy_salt: &str,
+    attempted_password: &str,
+) -> Result<PasswordMatch, LoginError> {
+    verify_password_with(&ARGON2_PARAMS, password_hash, legacy_salt, attempted_password)
+}
+
+fn verify_password_with(
+    params: &Argon2Params,
+    password_hash: &str,
+    legacy_salt: &str,
+    attempted_password: &str,
+) -> Result<PasswordMatch, LoginError> {
+
+    if password_hash.starts_with(ARGON2_PREFIX) {
+        let matches = argon2::verify_encoded(password_hash, attempted_password.as_bytes())
+            .map_err(|_| LoginError::DecodeError(errJson!("Password could not be decoded!")))?;
+
+        if !matches {
+            return Err(LoginError::WrongPassword(errJson!("Wrong password!")))
+        }
+
+        match Argon2Params::from_phc(password_hash) {
+            Some(p) if p == *params => Ok(PasswordMatch::Current),
+            _ => Ok(PasswordMatch::NeedsRehash),
+        }
+    } else {
+        let mut decoded_password_hash = base64::decode(password_hash)
+            .map_err(|_| LoginError::DecodeError(errJson!("Password could not be decoded!")))?;
+
+        if decoded_password_hash.len() != LEGACY_CREDENTIAL_LEN {
+            return Err(LoginError::DecodeError(errJson!("Password could not be decoded!")))
+        }
+
+        pbkdf2::verify(
+            LEGACY_DIGEST_ALG,
+            NonZeroU32::new(LEGACY_PBKDF2_ITERATIONS).unwrap(),
+            legacy_salt.as_bytes(),
+            attempted_password.as_bytes(),
+            &mut decoded_password_hash,
+        ).map_err(|_| LoginError::WrongPassword(errJson!("Wrong password!")))?;
+
+        Ok(PasswordMatch::NeedsRehash)
+    }
+}
+
+
+#[cfg(test)]
+fn legacy_pbkdf2_credential(salt: &str, password: &str) -> String {
+    let mut credential = [0u8; LEGACY_CREDENTIAL_LEN];
+    pbkdf2::derive(
+        LEGACY_DIGEST_ALG,
+        NonZeroU32::new(LEGACY_PBKDF2_ITERATIONS).unwrap(),
+        salt.as_bytes(),
+        password.as_bytes(),
+        &mut credential,
+    );
+    base64::encode(&credential)
+}
+
+#[cfg(test)]
+const TEST_PARAMS: Argon2Params = Argon2Params {
+    memory_kib: 64,
+    iterations: 1,
+    parallelism: 1,
+};
+
+#[test]
+fn argon2id_hashes_are_phc_strings_with_random_salts() {
+    let hash1 = hash_password(&TEST_PARAMS, "hunter2");
+    let hash2 = hash_password(&TEST_PARAMS, "hunter2");
+
+    assert!(hash1.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+    assert_ne!(hash1, hash2);
+    assert_eq!(Argon2Params::from_phc(&hash1), Some(TEST_PARAMS));
+
+    assert_eq!(
+        verify_password_with(&TEST_PARAMS, &hash1, "u123", "hunter2").unwrap(),
+        PasswordMatch::Current
+    );
+    assert!(verify_password_with(&TEST_PARAMS, &hash1, "u123", "hunter3").is_err());
+}
+
+#[test]
+fn legacy_pbkdf2_hashes_verify_and_need_rehash() {
+    let legacy = legacy_pbkdf2_credential("u123", "hunter2");
+
+    assert_eq!(
+        verify_password_with(&TEST_PARAMS, &legacy, "u123", "hunter2").unwrap(),
+        PasswordMatch::NeedsRehash
+    );
+    // salted with the user id
+    assert!(verify_password_with(&TEST_PARAMS, &legacy, "u456", "hunter2").is_err());
+    assert!(verify_password_with(&TEST_PARAMS, &legacy, "u123", "hunter3").is_err());
+}
+
+#[test]
+fn argon2id_hashes_with_old_cost_need_rehash() {
+    let hash = hash_password(&TEST_PARAMS, "hunter2");
+    let stronger = Argon2Params { memory_kib: 128, ..TEST_PARAMS };
+
+    assert_eq!(
+        verify_password_with(&stronger, &hash, "u123", "hunter2").unwrap(),
+        PasswordMatch::NeedsRehash
+    );
+    assert!(!Argon2Params { memory_kib: 4, ..TEST_PARAMS }.is_valid());
+}
@@ -0,0 +1,102 @@
+use crate::models::{LoginError, ErrJson};
+
+//////////////////////////////////////////////
+/// Email normalization
+//////////////////////////////////////////////
+/// Emails are stored normalized: trimmed, with the domain lowercased and
+/// internationalized domains in their ASCII (punycode) form, e.g.
+///     " Jane.Doe@Bücher.Example " => "Jane.Doe@xn--bcher-kva.example"
+/// The local part keeps its case, as that's how the user typed it.
+///
+/// Accounts are identified case-insensitively, by `email_identity`.
+/// It's stored in `users.email_identity`, which has a unique index, as
+/// Postgres' lower() doesn't always agree with Rust's to_lowercase().
+
+/// Normalized form of an email, for storing it
+pub fn normalize_email(email: &str) -> Result<String, LoginError> {
+    let email = email.trim();
+    let at = email.rfind('@')
+        .ok_or(LoginError::EmailInvalid(errJson!("Email is missing an @")))?;
+    let (local_part, domain) = (&email[..at], &email[at + 1..]);
+
+    if local_part.is_empty() || domain.is_empty() {
+        return Err(LoginError::EmailInvalid(errJson!("Email is invalid")))
+    }
+
+    // UTS #46 mapping: lowercases, and converts unicode labels to punycode
+    let domain = idna::domain_to_ascii(domain)
+        .map_err(|_| LoginError::EmailInvalid(errJson!("Email domain is invalid")))?;
+
+    Ok(format!("{}@{}", local_part, domain))
+}
+
+/// Case-insensitive identity of an email, for comparing emails,
+/// lookups, and redis keys
+pub fn email_identity(email: &str) -> String {
+    normalize_email(email)
+        .unwrap_or_else(|_| email.trim().to_string())
+        .to_lowercase()
+}
+
+/// Identities shared by more than one account, with the ids of those
+/// accounts, given each account's `(user_id, email)`
+pub fn email_identity_collisions(
+    accounts: &[(String, String)]
+) -> Vec<(String, Vec<String>)> {
+    let mut by_identity: std::collections::BTreeMap<String, Vec<String>> =
+        std::collections::BTreeMap::new();
+    for (user_id, email) in accounts {
+        by_identity.entry(email_identity(email))
+            .or_insert_with(Vec::new)
+            .push(user_id.clone());
+    }
+    by_identity.into_iter()
+        .filter(|(_, user_ids)| user_ids.len() > 1)
+        .collect()
+}
+
+
+#[test]
+fn normalizes_emails_for_storage() {
+    assert_eq!(
+        normalize_email(" Jane.Doe@Example.COM ").unwrap(),
+        "Jane.Doe@example.com"
+    );
+    assert_eq!(
+        normalize_email("user@Bücher.example").unwrap(),
+        "user@xn--bcher-kva.example"
+    );
+    // only the last @ separates the domain
+    assert_eq!(
+        normalize_email("\"a@b\"@EXAMPLE.com").unwrap(),
+        "\"a@b\"@example.com"
+    );
+    assert!(normalize_email("no-at-sign").is_err());
+    assert!(normalize_email("@example.com").is_err());
+    assert!(normalize_email("user@").is_err());
+}
+
+#[test]
+fn email_identities_ignore_case_and_idna_form() {
+    assert_eq!(email_identity("Jane.Doe@EXAMPLE.com"), "jane.doe@example.com");
+    assert_eq!(
+        email_identity("user@BÜCHER.example"),
+        email_identity("USER@xn--bcher-kva.example")
+    );
+}
+
+#[test]
+fn finds_accounts_whose_emails_only_differ_by_idna_form() {
+    let accounts = vec![
+        (String::from("u1"), String::from("user@bücher.example")),
+        (String::from("u2"), String::from("jane@example.com")),
+        (String::from("u3"), String::from("USER@xn--bcher-kva.example")),
+    ];
+    assert_eq!(
+        email_identity_collisions(&accounts),
+        vec![(
+            String::from("user@xn--bcher-kva.example"),
+            vec![String::from("u1"), String::from("u3")],
+        )]
+    );
+}
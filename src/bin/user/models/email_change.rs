@@ -0,0 +1,39 @@
+use diesel::prelude::*;
+use dt::db::schema::pending_email_changes;
+
+//////////////////////////////////////////////
+/// Pending email changes
+//////////////////////////////////////////////
+
+/// A request to change a user's email. The email is only changed once the
+/// link sent to `new_email` is confirmed. The link sent to `old_email`
+/// cancels the change, or undoes it if it was already confirmed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingEmailChange {
+    pub id: String,
+    pub user_id: String,
+    pub old_email: String,
+    pub new_email: String,
+    #[serde(skip)]
+    pub confirm_token_hash: String,
+    #[serde(skip)]
+    pub revert_token_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub confirmed_at: Option<chrono::NaiveDateTime>,
+    pub reverted_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "pending_email_changes"]
+pub struct NewPendingEmailChange {
+    pub id: String,
+    pub user_id: String,
+    pub old_email: String,
+    pub new_email: String,
+    /// see `auth::hash_token`
+    pub confirm_token_hash: String,
+    pub revert_token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
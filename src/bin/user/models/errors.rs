@@ -7,6 +7,8 @@ use futures::{ future, future::Future };
 use failure::Error;
 use std::convert::From;
 
+use crate::models::{PasswordPolicyError, SuspensionNotice};
+
 #[derive(Debug, Clone, Serialize, Deserialize, Fail)]
 pub struct ErrJson {
     pub file: String,
@@ -71,7 +73,13 @@ pub enum LoginError {
     #[fail(display = "{}", _0)]
     Suspended(ErrJson),
     #[fail(display = "{}", _0)]
+    AccountSuspended(SuspensionNotice),
+    #[fail(display = "{}", _0)]
+    Forbidden(ErrJson),
+    #[fail(display = "{}", _0)]
     DuplicateUser(ErrJson),
+    #[fail(display = "{}", _0)]
+    PasswordPolicy(PasswordPolicyError),
 }
 
 impl ResponseError for LoginError {
@@ -147,15 +155,43 @@ impl ResponseError for LoginError {
                 HttpResponse::Unauthorized()
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            LoginError::AccountSuspended(notice) => {
+                HttpResponse::Unauthorized()
+                    .json(json!({
+                        "status": "SUSPENDED",
+                        "message": notice.message,
+                        "reason": notice.reason,
+                        "until": notice.until,
+                    }))
+            },
+            LoginError::Forbidden(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::Forbidden()
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
             LoginError::DuplicateUser(ejson) => {
                 warn!("{}: {}", ejson.file, ejson.message);
                 HttpResponse::BadRequest()
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            LoginError::PasswordPolicy(e) => e.error_response(),
        }
     }
 }
 
+impl From<PasswordPolicyError> for LoginError {
+    fn from(e: PasswordPolicyError) -> Self {
+        LoginError::PasswordPolicy(e)
+    }
+}
+
+/// For diesel transactions which return a LoginError
+impl From<diesel::result::Error> for LoginError {
+    fn from(e: diesel::result::Error) -> Self {
+        LoginError::DatabaseError(errJson!(e))
+    }
+}
+
 #[derive(Debug, Fail, Serialize, Deserialize)]
 pub enum RpcError {
     #[fail(display = "Error calling dt-payments: {}", _0)]
@@ -0,0 +1,34 @@
+use diesel::prelude::*;
+use dt::db::schema::impersonations;
+
+//////////////////////////////////////////////
+/// Impersonations
+//////////////////////////////////////////////
+
+/// Record of an admin impersonating a user, see `auth::impersonation`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct Impersonation {
+    pub id: String,
+    pub admin_id: String,
+    pub user_id: String,
+    pub reason: Option<String>,
+    pub jti: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "impersonations"]
+pub struct NewImpersonation {
+    pub id: String,
+    pub admin_id: String,
+    pub user_id: String,
+    pub reason: Option<String>,
+    pub jti: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub expires_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,39 @@
+use diesel::prelude::*;
+use dt::db::schema::{user_totp, mfa_recovery_codes};
+
+//////////////////////////////////////////////
+/// Two-factor authentication records
+//////////////////////////////////////////////
+
+/// A user's TOTP authenticator. Enrolment creates it with `enabled: false`,
+/// and it is enabled once the user verifies their first code.
+#[derive(Debug, Clone, PartialEq, Queryable)]
+pub struct UserTotp {
+    pub user_id: String,
+    /// base32 secret, never returned after enrolment
+    pub secret: String,
+    pub enabled: bool,
+    /// Last accepted TOTP time step, codes at or before it are rejected
+    pub last_used_step: Option<i64>,
+    pub created_at: chrono::NaiveDateTime,
+    pub enabled_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "mfa_recovery_codes"]
+pub struct NewRecoveryCode {
+    pub id: String,
+    pub user_id: String,
+    /// see `auth::hash_recovery_code`
+    pub code_hash: String,
+}
+
+impl NewRecoveryCode {
+    pub fn new(user_id: &str, code_hash: String) -> Self {
+        NewRecoveryCode {
+            id: format!("mrc_{}", uuid::Uuid::new_v4()),
+            user_id: user_id.to_string(),
+            code_hash: code_hash,
+        }
+    }
+}
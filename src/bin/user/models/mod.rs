@@ -1,23 +1,43 @@
 
+pub mod account_deletion;
+pub mod audit_event;
 pub mod auth;
 pub mod connection;
+pub mod credential;
 pub mod customer_stripe;
+pub mod email_address;
+pub mod email_change;
 pub mod errors;
 pub mod generate_user_id;
+pub mod impersonation;
 pub mod lens;
+pub mod mfa;
 pub mod paginate_cursor;
 pub mod paginate_page;
+pub mod password_policy;
+pub mod suspension;
 pub mod update_profile;
 pub mod user;
 pub mod validation;
+pub mod webauthn;
 
+pub use account_deletion::*;
+pub use audit_event::*;
 pub use auth::*;
 pub use connection::*;
+pub use credential::*;
 pub use customer_stripe::*;
+pub use email_address::*;
+pub use email_change::*;
 pub use errors::*;
 pub use generate_user_id::*;
+pub use impersonation::*;
+pub use mfa::*;
 pub use paginate_cursor::*;
 pub use paginate_page::*;
+pub use password_policy::*;
+pub use suspension::*;
 pub use update_profile::*;
 pub use user::*;
-pub use validation::*;
\ No newline at end of file
+pub use validation::*;
+pub use webauthn::*;
\ No newline at end of file
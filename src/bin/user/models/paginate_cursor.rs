@@ -48,6 +48,7 @@ impl<T> PaginateCursor for T {
             cursor: None, // Cannot do dynamic queries yet with QueryFragments
             // cursor_where clause handled in match clause
             pageBackwards: pageBackwards.or(Some(false)).unwrap(),
+            tiebreakField: None,
         }
     }
 }
@@ -63,6 +64,8 @@ pub struct PaginatedCursor<T> {
     ascendingOrDescending: String, // 'ASC' or 'DESC'
     cursor: Option<String>,
     pageBackwards: bool,
+    // Unique field to order rows with the same orderField by
+    tiebreakField: Option<String>,
 }
 
 impl <T> PaginatedCursor<T> {
@@ -73,6 +76,15 @@ impl <T> PaginatedCursor<T> {
         }
     }
 
+    /// Orders rows with the same orderField by a unique field too,
+    /// e.g. "id", so cursors on (orderField, tiebreakField) are exact
+    pub fn tiebreak_by(self, field: &str) -> Self {
+        PaginatedCursor {
+            tiebreakField: Some(field.to_string()),
+            ..self
+        }
+    }
+
     pub fn load_and_count_pages<U>(
         self,
         conn: &PgConnection
@@ -158,6 +170,12 @@ impl<T> QueryFragment<Pg> for PaginatedCursor<T>
         out.push_identifier(&self.orderField)?;
         out.push_sql(" ");
         out.push_sql(&self.ascendingOrDescending);
+        if let Some(tiebreakField) = &self.tiebreakField {
+            out.push_sql(", ");
+            out.push_identifier(tiebreakField)?;
+            out.push_sql(" ");
+            out.push_sql(&self.ascendingOrDescending);
+        }
 
         out.push_sql(" LIMIT ");
         // Set count + 1 (overfetch to detect page end)
@@ -177,18 +195,25 @@ pub struct PageDirection {
 pub struct B64Cursor {
     pub name: String,
     pub value: chrono::NaiveDateTime,
+    /// Row id, to break ties between rows with the same `value`.
+    /// Encoded after a ',', which timestamps don't have.
+    pub id: Option<String>,
 }
 impl B64Cursor {
-    fn new<S: ToString>(name: S, value: S) -> Self {
+    pub fn new<S: ToString>(name: S, value: S) -> Self {
         Self {
             name: name.to_string(),
             value: chrono::NaiveDateTime::from_str(&value.to_string()).unwrap_or(
                 chrono::NaiveDateTime::from_timestamp(chrono::Utc::now().timestamp(), 0)
             ),
+            id: None,
         }
     }
-    fn to_b64_string(&self) -> String {
-        base64::encode(&format!("{}:{}", self.name, self.value))
+    pub fn to_b64_string(&self) -> String {
+        match &self.id {
+            None => base64::encode(&format!("{}:{}", self.name, self.value)),
+            Some(id) => base64::encode(&format!("{}:{},{}", self.name, self.value, id)),
+        }
     }
 }
 
@@ -213,6 +238,11 @@ pub fn decode_datetime_cursor(
 
     let cursorName = String::from(*cursor.iter().nth(0).expect("B64Cursor.name missing!"));
     let cursorStr = String::from(*cursor.iter().nth(1).expect("B64Cursor.value missing!"));
+    // an optional id after the first ','
+    let (cursorStr, cursorId) = match cursorStr.find(",") {
+        None => (cursorStr, None),
+        Some(i) => (cursorStr[..i].to_string(), Some(cursorStr[i + 1..].to_string())),
+    };
     let cursorValue = chrono::NaiveDateTime::parse_from_str(
         &cursorStr,
         pick_datetime_format(&cursorStr),
@@ -223,6 +253,7 @@ pub fn decode_datetime_cursor(
         Ok(v) => Ok(B64Cursor {
             name: cursorName,
             value: v,
+            id: cursorId,
         })
     }
 }
@@ -245,3 +276,17 @@ pub fn get_page_direction(
 }
 
 
+#[test]
+fn cursors_round_trip_with_and_without_ids() {
+    let value = chrono::NaiveDate::from_ymd(2020, 7, 16).and_hms_milli(9, 30, 5, 250);
+    let cursor = B64Cursor { name: String::from("created_at"), value, id: None };
+    let decoded = decode_datetime_cursor(&cursor.to_b64_string()).unwrap();
+    assert_eq!(decoded.name, "created_at");
+    assert_eq!(decoded.value, value);
+    assert_eq!(decoded.id, None);
+
+    let cursor = B64Cursor { id: Some(String::from("u_a1-B2")), ..cursor };
+    let decoded = decode_datetime_cursor(&cursor.to_b64_string()).unwrap();
+    assert_eq!(decoded.value, value);
+    assert_eq!(decoded.id, Some(String::from("u_a1-B2")));
+}
@@ -0,0 +1,459 @@
+use actix_web::{
+    http::StatusCode,
+    error::ResponseError,
+    HttpResponse,
+};
+use ring::digest;
+
+//////////////////////////////////////////////
+/// Password policy
+//////////////////////////////////////////////
+/// New passwords (signup, password change and reset) must:
+///     - be at least PASSWORD_MIN_LENGTH characters (default 10),
+///       and at most 128 so hashing stays cheap
+///     - have an estimated strength score of at least PASSWORD_MIN_SCORE
+///       (0 to 4, default 2), see `estimate_strength`
+///     - not be in the breached password list in PASSWORD_BLOCKLIST_FILE
+///
+/// Existing passwords are not checked at login.
+///
+/// The blocklist is a binary file of sorted, big-endian 8 byte prefixes of the
+/// SHA-1 of each password, built with scripts/build_password_blocklist.py.
+/// 8 bytes per password keeps large lists (e.g. Pwned Passwords) in memory,
+/// and false positives are ~n / 2^64.
+
+const DEFAULT_MIN_LENGTH: usize = 10;
+const DEFAULT_MIN_SCORE: u8 = 2;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+
+lazy_static! {
+    pub static ref PASSWORD_POLICY: PasswordPolicy = PasswordPolicy::from_env()
+        .unwrap_or_else(|e| panic!("Error loading password policy: {}", e));
+}
+
+/// Passwords which are guessed first, checked with l33t substitutions undone.
+/// Longer lists go in the blocklist file.
+const COMMON_WORDS: &[&str] = &[
+    "password", "passw0rd", "qwerty", "letmein", "welcome", "admin", "login",
+    "dragon", "monkey", "master", "shadow", "sunshine", "princess", "football",
+    "baseball", "iloveyou", "trustno1", "superman", "batman", "hello",
+    "freedom", "whatever", "secret", "access", "starwars", "computer",
+    "michael", "jordan", "pokemon", "charlie", "summer", "winter", "spring",
+    "autumn", "love", "god", "abc", "degen", "tracker",
+];
+
+/// Keyboard rows and alphabets for spotting sequences like "asdf" and "4321"
+const SEQUENCES: &[&str] = &[
+    "abcdefghijklmnopqrstuvwxyz",
+    "0123456789",
+    "qwertyuiop",
+    "asdfghjkl",
+    "zxcvbnm",
+    "1234567890",
+];
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_score: u8,
+    pub blocklist: Option<PasswordBlocklist>,
+}
+
+impl PasswordPolicy {
+
+    pub fn from_env() -> Result<Self, failure::Error> {
+        dotenv::dotenv().ok();
+
+        let min_length = match std::env::var("PASSWORD_MIN_LENGTH").ok() {
+            Some(s) => s.parse::<usize>()?,
+            None => DEFAULT_MIN_LENGTH,
+        };
+        let min_score = match std::env::var("PASSWORD_MIN_SCORE").ok() {
+            Some(s) => s.parse::<u8>()?.min(4),
+            None => DEFAULT_MIN_SCORE,
+        };
+        let blocklist = match std::env::var("PASSWORD_BLOCKLIST_FILE").ok() {
+            Some(path) => {
+                let blocklist = PasswordBlocklist::from_bytes(&std::fs::read(&path)?)?;
+                info!("Loaded {} breached passwords from {}", blocklist.len(), path);
+                Some(blocklist)
+            },
+            None => {
+                warn!("PASSWORD_BLOCKLIST_FILE not set, breached passwords are not checked");
+                None
+            }
+        };
+
+        Ok(PasswordPolicy {
+            min_length: min_length,
+            min_score: min_score,
+            blocklist: blocklist,
+        })
+    }
+
+    /// `user_inputs` are the user's email, names, user id...
+    /// which make a password easier to guess.
+    pub fn check(
+        &self,
+        password: &str,
+        user_inputs: &[&str],
+    ) -> Result<(), PasswordPolicyError> {
+
+        let strength = estimate_strength(password, user_inputs);
+        let length = password.chars().count();
+        let mut violations = vec![];
+
+        if length < self.min_length {
+            violations.push(PasswordViolation::new(
+                PasswordViolationCode::TooShort,
+                &format!("Password must be at least {} characters", self.min_length),
+            ));
+        }
+        if length > PASSWORD_MAX_LENGTH {
+            violations.push(PasswordViolation::new(
+                PasswordViolationCode::TooLong,
+                &format!("Password must be at most {} characters", PASSWORD_MAX_LENGTH),
+            ));
+        }
+        if strength.score < self.min_score {
+            let message = if strength.contains_user_input {
+                "Password is too easy to guess, avoid using your name or email"
+            } else {
+                "Password is too easy to guess, try a longer passphrase"
+            };
+            violations.push(PasswordViolation::new(PasswordViolationCode::TooWeak, message));
+        }
+        if self.blocklist.as_ref().map(|b| b.contains(password)).unwrap_or(false) {
+            violations.push(PasswordViolation::new(
+                PasswordViolationCode::Breached,
+                "Password has appeared in a data breach, please choose another",
+            ));
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordPolicyError {
+                violations: violations,
+                score: strength.score,
+                min_score: self.min_score,
+            })
+        }
+    }
+}
+
+/// Checks a new password against `PASSWORD_POLICY`
+pub fn check_password_policy(
+    password: &str,
+    user_inputs: &[&str],
+) -> Result<(), PasswordPolicyError> {
+    PASSWORD_POLICY.check(password, user_inputs)
+}
+
+
+//////////////////////////////////////////////
+/// Breached password blocklist
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct PasswordBlocklist {
+    prefixes: Vec<u64>,
+}
+
+impl PasswordBlocklist {
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, failure::Error> {
+        if bytes.len() % 8 != 0 {
+            return Err(format_err!("Blocklist length is not a multiple of 8 bytes"))
+        }
+        let prefixes = bytes.chunks(8)
+            .map(|chunk| {
+                let mut prefix = [0u8; 8];
+                prefix.copy_from_slice(chunk);
+                u64::from_be_bytes(prefix)
+            })
+            .collect::<Vec<u64>>();
+
+        if prefixes.windows(2).any(|w| w[0] > w[1]) {
+            return Err(format_err!("Blocklist is not sorted"))
+        }
+        Ok(PasswordBlocklist { prefixes: prefixes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.prefixes.binary_search(&sha1_prefix(password)).is_ok()
+    }
+}
+
+/// First 8 bytes of the SHA-1 of a password, as a big-endian integer
+pub fn sha1_prefix(password: &str) -> u64 {
+    let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&hash.as_ref()[..8]);
+    u64::from_be_bytes(prefix)
+}
+
+
+//////////////////////////////////////////////
+/// Strength estimation
+//////////////////////////////////////////////
+/// A simplified zxcvbn: the password is split into guessable patterns
+/// (the user's own details, common words, sequences and repeats), each
+/// costing only a few bits, and characters outside any pattern cost
+/// log2 of the character set size. The total entropy gives a 0-4 score.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordStrength {
+    pub entropy_bits: f64,
+    pub score: u8,
+    pub contains_user_input: bool,
+}
+
+pub fn estimate_strength(password: &str, user_inputs: &[&str]) -> PasswordStrength {
+
+    let lower = password.to_lowercase().chars().collect::<Vec<char>>();
+    let unleeted = lower.iter().map(|c| unleet(*c)).collect::<Vec<char>>();
+    let user_words = user_input_words(user_inputs);
+    let charset_bits = (charset_size(password) as f64).log2();
+
+    let mut entropy_bits = 0.0;
+    let mut contains_user_input = false;
+    let mut i = 0;
+
+    while i < lower.len() {
+        let user_word = longest_word_match(&unleeted, i, &user_words);
+        let common_word = longest_word_match(
+            &unleeted,
+            i,
+            &COMMON_WORDS.iter().map(|w| w.to_string()).collect::<Vec<String>>(),
+        );
+        let sequence = sequence_len(&lower, i);
+        let repeat = repeat_len(&lower, i);
+
+        // Take the longest pattern starting here, preferring the cheapest
+        let (len, bits) = [
+            (user_word, 1.0 + (user_words.len().max(1) as f64).log2()),
+            (common_word, (COMMON_WORDS.len() as f64).log2()),
+            (sequence, (SEQUENCES.len() as f64 * 2.0).log2() + (sequence as f64).log2()),
+            (repeat, charset_bits + (repeat as f64).log2()),
+        ].iter()
+            .cloned()
+            .filter(|(len, _)| *len >= 3)
+            .fold((0, 0.0), |best, (len, bits)| {
+                if len > best.0 || (len == best.0 && bits < best.1) {
+                    (len, bits)
+                } else {
+                    best
+                }
+            });
+
+        if len > 0 {
+            if len == user_word {
+                contains_user_input = true;
+            }
+            entropy_bits += bits;
+            i += len;
+        } else {
+            entropy_bits += charset_bits;
+            i += 1;
+        }
+    }
+
+    PasswordStrength {
+        entropy_bits: entropy_bits,
+        score: score_from_entropy(entropy_bits),
+        contains_user_input: contains_user_input,
+    }
+}
+
+fn score_from_entropy(bits: f64) -> u8 {
+    match bits {
+        b if b < 28.0 => 0,
+        b if b < 36.0 => 1,
+        b if b < 60.0 => 2,
+        b if b < 80.0 => 3,
+        _ => 4,
+    }
+}
+
+fn charset_size(password: &str) -> usize {
+    let mut size = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) { size += 26 }
+    if password.chars().any(|c| c.is_ascii_uppercase()) { size += 26 }
+    if password.chars().any(|c| c.is_ascii_digit()) { size += 10 }
+    if password.chars().any(|c| c.is_ascii_punctuation() || c == ' ') { size += 33 }
+    if password.chars().any(|c| !c.is_ascii()) { size += 100 }
+    size.max(1)
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '8' => 'b',
+        '(' => 'c',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' | '+' => 't',
+        '2' => 'z',
+        c => c,
+    }
+}
+
+/// Words of 3+ characters from the user's details, e.g.
+/// "jane.doe@example.com" => ["jane.doe@example.com", "jane", "doe", "example"]
+fn user_input_words(user_inputs: &[&str]) -> Vec<String> {
+    let mut words = vec![];
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        words.push(input.chars().map(unleet).collect::<String>());
+        words.extend(
+            input.split(|c: char| !c.is_alphanumeric())
+                .filter(|w| w.chars().count() >= 3)
+                .filter(|w| *w != "com")
+                .map(|w| w.chars().map(unleet).collect::<String>())
+        );
+    }
+    words.retain(|w| w.chars().count() >= 3);
+    words.sort();
+    words.dedup();
+    words
+}
+
+fn longest_word_match(password: &[char], start: usize, words: &[String]) -> usize {
+    words.iter()
+        .map(|w| w.chars().collect::<Vec<char>>())
+        .filter(|w| password[start..].starts_with(w))
+        .map(|w| w.len())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Length of a run like "abcd", "4321" or "qwer" starting at `start`
+fn sequence_len(password: &[char], start: usize) -> usize {
+    SEQUENCES.iter()
+        .flat_map(|seq| {
+            let forward = seq.chars().collect::<Vec<char>>();
+            let backward = seq.chars().rev().collect::<Vec<char>>();
+            vec![forward, backward]
+        })
+        .map(|seq| {
+            match seq.iter().position(|c| *c == password[start]) {
+                None => 0,
+                Some(pos) => seq[pos..].iter()
+                    .zip(password[start..].iter())
+                    .take_while(|(a, b)| a == b)
+                    .count(),
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Length of a run of the same character starting at `start`
+fn repeat_len(password: &[char], start: usize) -> usize {
+    password[start..].iter()
+        .take_while(|c| **c == password[start])
+        .count()
+}
+
+
+//////////////////////////////////////////////
+/// Errors
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PasswordViolationCode {
+    TooShort,
+    TooLong,
+    TooWeak,
+    Breached,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordViolation {
+    pub code: PasswordViolationCode,
+    pub message: String,
+}
+
+impl PasswordViolation {
+    fn new(code: PasswordViolationCode, message: &str) -> Self {
+        PasswordViolation { code: code, message: String::from(message) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Fail)]
+#[serde(rename_all = "camelCase")]
+#[fail(display = "Password does not meet the password policy: {:?}", violations)]
+pub struct PasswordPolicyError {
+    pub violations: Vec<PasswordViolation>,
+    pub score: u8,
+    pub min_score: u8,
+}
+
+impl ResponseError for PasswordPolicyError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::BAD_REQUEST)
+            .json(json!({
+                "status": "PASSWORD_POLICY",
+                "message": "Password does not meet the password policy",
+                "violations": self.violations,
+                "score": self.score,
+                "minScore": self.min_score,
+            }))
+    }
+}
+
+
+#[test]
+fn scores_guessable_passwords_low() {
+    assert_eq!(estimate_strength("", &[]).score, 0);
+    assert_eq!(estimate_strength("password123", &[]).score, 0);
+    assert_eq!(estimate_strength("qwertyuiop", &[]).score, 0);
+    assert_eq!(estimate_strength("aaaaaaaaaaaa", &[]).score, 0);
+    assert_eq!(estimate_strength("P@ssw0rd!", &[]).score, 0);
+    assert!(estimate_strength("correct horse battery staple", &[]).score >= 3);
+    assert!(estimate_strength("vq7#Lm2!xR", &[]).score >= 3);
+}
+
+#[test]
+fn penalizes_passwords_from_user_details() {
+    let inputs = ["severus.snape@hogwarts.com", "Severus", "Snape", "u8f2kq9d1"];
+    let strength = estimate_strength("SeverusSnape1", &inputs);
+    assert!(strength.contains_user_input);
+    assert!(strength.score < estimate_strength("SeverusSnape1", &[]).score);
+    assert!(estimate_strength("hogwart$u8f2kq9d1", &inputs).score < 2);
+}
+
+#[test]
+fn checks_blocklist_and_length() {
+    let mut prefixes = vec![sha1_prefix("hunter2hunter2"), sha1_prefix("Tr0ub4dor&3xyz")];
+    prefixes.sort();
+    let bytes = prefixes.iter()
+        .flat_map(|p| p.to_be_bytes().to_vec())
+        .collect::<Vec<u8>>();
+
+    let policy = PasswordPolicy {
+        min_length: 10,
+        min_score: 2,
+        blocklist: Some(PasswordBlocklist::from_bytes(&bytes).unwrap()),
+    };
+
+    let codes = |password: &str| policy.check(password, &[])
+        .err()
+        .map(|e| e.violations.iter().map(|v| v.code).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    assert_eq!(codes("Tr0ub4dor&3xyz"), vec![PasswordViolationCode::Breached]);
+    assert_eq!(codes("kH4#p"), vec![PasswordViolationCode::TooShort, PasswordViolationCode::TooWeak]);
+    assert_eq!(codes(&"x9#Kq2!m".repeat(20)), vec![PasswordViolationCode::TooLong]);
+    assert!(codes("correct horse battery staple").is_empty());
+    assert!(PasswordBlocklist::from_bytes(&bytes[..9]).is_err());
+}
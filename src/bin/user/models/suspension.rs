@@ -0,0 +1,127 @@
+use diesel::prelude::*;
+use dt::db::schema::suspensions;
+
+//////////////////////////////////////////////
+/// Suspensions
+//////////////////////////////////////////////
+
+/// A suspension of a user's account by an admin.
+/// Active until `lifted_at` is set, by an admin or when `until` passes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct Suspension {
+    pub id: String,
+    pub user_id: String,
+    pub reason: String,
+    /// Admins only, not shown to the user
+    pub note: Option<String>,
+    pub admin_id: String,
+    pub created_at: chrono::NaiveDateTime,
+    /// None for suspensions which only an admin can lift
+    pub until: Option<chrono::NaiveDateTime>,
+    pub lifted_at: Option<chrono::NaiveDateTime>,
+    /// None if lifted automatically
+    pub lifted_by: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "suspensions"]
+pub struct NewSuspension {
+    pub id: String,
+    pub user_id: String,
+    pub reason: String,
+    pub note: Option<String>,
+    pub admin_id: String,
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SuspensionReason {
+    Spam,
+    Fraud,
+    Abuse,
+    Chargeback,
+    TermsViolation,
+    Other,
+}
+
+impl SuspensionReason {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            SuspensionReason::Spam => "SPAM",
+            SuspensionReason::Fraud => "FRAUD",
+            SuspensionReason::Abuse => "ABUSE",
+            SuspensionReason::Chargeback => "CHARGEBACK",
+            SuspensionReason::TermsViolation => "TERMS_VIOLATION",
+            SuspensionReason::Other => "OTHER",
+        }
+    }
+}
+
+impl From<String> for SuspensionReason {
+    fn from(s: String) -> Self {
+        match s.to_uppercase().as_str() {
+            "SPAM" => SuspensionReason::Spam,
+            "FRAUD" => SuspensionReason::Fraud,
+            "ABUSE" => SuspensionReason::Abuse,
+            "CHARGEBACK" => SuspensionReason::Chargeback,
+            "TERMS_VIOLATION" => SuspensionReason::TermsViolation,
+            _ => SuspensionReason::Other,
+        }
+    }
+}
+
+/// What a suspended user is told when they login or use their JWT
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Fail)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspensionNotice {
+    pub reason: SuspensionReason,
+    pub until: Option<chrono::NaiveDateTime>,
+    pub message: String,
+}
+
+impl std::fmt::Display for SuspensionNotice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl SuspensionNotice {
+    /// For users suspended before suspensions were recorded
+    pub fn unknown() -> Self {
+        SuspensionNotice {
+            reason: SuspensionReason::Other,
+            until: None,
+            message: String::from("User is suspended"),
+        }
+    }
+}
+
+impl From<&Suspension> for SuspensionNotice {
+    fn from(suspension: &Suspension) -> Self {
+        SuspensionNotice {
+            reason: SuspensionReason::from(suspension.reason.clone()),
+            until: suspension.until,
+            message: match suspension.until {
+                Some(until) => format!("User is suspended until {} UTC", until.format("%Y-%m-%d %H:%M")),
+                None => String::from("User is suspended"),
+            },
+        }
+    }
+}
+
+
+#[test]
+fn suspension_reason_round_trips() {
+    for reason in &[
+        SuspensionReason::Spam,
+        SuspensionReason::Chargeback,
+        SuspensionReason::TermsViolation,
+        SuspensionReason::Other,
+    ] {
+        assert_eq!(SuspensionReason::from(reason.as_str().to_string()), *reason);
+        assert_eq!(serde_json::to_value(reason).unwrap(), json!(reason.as_str()));
+    }
+    assert_eq!(SuspensionReason::from(String::from("not a reason")), SuspensionReason::Other);
+}
@@ -51,8 +51,8 @@ impl UpdateUserProfile {
     }
 
     pub fn update_password(&mut self, password: String) {
-        // credential = hash(salt-id + password)
-        let new_credential = crate::models::user::generate_credential(&self.id, &password);
+        // argon2id PHC string, see models::credential
+        let new_credential = crate::models::credential::generate_credential(&password);
         self.password_hash = Some(new_credential);
     }
 
@@ -3,9 +3,6 @@ use actix_web::{
     HttpResponse,
 };
 use failure::Error;
-use ring::rand::SystemRandom;
-use ring::{digest, pbkdf2};
-use std::num::NonZeroU32;
 // validation
 use validator::{Validate, ValidationError};
 use crate::models::validate_unoffensive_name;
@@ -23,14 +20,17 @@ use dt::db;
 
 use crate::db::queries::users_raw::{insert_user_profile, get_user_profile_by_id};
 
+use crate::db::queries::users_raw::set_new_password;
+
 use crate::models::{ LoginError, ErrJson };
 use crate::models::auth::UserRole;
+use crate::models::email_identity;
 use crate::models::generate_user_id::generate_nano_user_id;
-
-// Constants for hashing passwords
-static DIGEST_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
-const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
-const PBKDF2_ITERATIONS: u32 = 20_000;
+use crate::models::credential::{
+    generate_credential,
+    verify_password,
+    PasswordMatch,
+};
 
 // Type aliases for IDs
 pub type UserId = String;
@@ -48,6 +48,8 @@ pub struct User {
     pub first_name: Option<String>,
     #[validate(custom = "validate_unoffensive_name")]
     pub last_name: Option<String>,
+    /// Never sent in responses
+    #[serde(skip_serializing, default)]
     pub password_hash: String,
     pub email_verified: bool,
     #[serde(deserialize_with = "from_datetimestr_to_option_naivedatetime")]
@@ -57,6 +59,10 @@ pub struct User {
     pub is_suspended: bool,
     pub is_deleted: bool,
     pub user_role: Option<UserRole>,
+    /// Lowercased `email`, which accounts are looked up by.
+    /// None for deleted accounts, see models::email_address
+    #[serde(skip_serializing, default)]
+    pub email_identity: Option<String>,
 }
 
 impl User {
@@ -66,14 +72,13 @@ impl User {
         first_name: Option<String>,
         last_name: Option<String>,
     ) -> Self {
-        // Uuid as random salt
         // let user_id = format!("user_{}", uuid::Uuid::new_v4());
         let user_id = format!("u{}", generate_nano_user_id());
-        let salt = user_id.clone();
-        let password_hash = generate_credential(&salt, &password);
+        let password_hash = generate_credential(&password);
+        let identity = email_identity(&email);
 
         User {
-            id: salt,
+            id: user_id,
             email: email,
             first_name: first_name,
             last_name: last_name,
@@ -84,12 +89,22 @@ impl User {
             is_suspended: false,
             is_deleted: false,
             user_role: Some(UserRole::USER),
+            email_identity: Some(identity),
         }
     }
 
     pub fn generate_new_password_hash(&self, password: &str) -> String {
-        // salt: user_id
-        generate_credential(&self.id, password)
+        generate_credential(password)
+    }
+
+    /// Details which shouldn't be in the user's password, see `check_password_policy`
+    pub fn password_inputs(&self) -> Vec<&str> {
+        vec![
+            self.email.as_str(),
+            self.first_name.as_deref().unwrap_or(""),
+            self.last_name.as_deref().unwrap_or(""),
+            self.id.as_str(),
+        ]
     }
 
     pub fn store_user_profile(
@@ -118,45 +133,35 @@ impl User {
         conn: &diesel::PgConnection,
         attempted_password: String
     ) -> Result<User, LoginError> {
-        // Check attempted_password matches decoded password_hash
-        match base64::decode(&self.password_hash) {
-            Err(_) => Err(LoginError::DecodeError(errJson!("Password could not be decoded!"))),
-            Ok(mut decoded_password_hash) => {
-                // See if salt + attempted_password recreates current password_hash
-                let salt = &self.id;
-                let authentication = pbkdf2::verify(
-                    DIGEST_ALG,
-                    NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
-                    salt.as_bytes(),
-                    attempted_password.as_bytes(),
-                    &mut decoded_password_hash,
-                ).map_err(|_| LoginError::WrongPassword(errJson!("Wrong password!")));
-
-                // If credentials match, return user profile
-                match authentication {
-                    Err(e) => Err(e),
-                    Ok(_) => get_user_profile_by_id(conn, &self.id),
+        // legacy PBKDF2 hashes are salted with the user id
+        let password_match = verify_password(
+            &self.password_hash,
+            &self.id,
+            &attempted_password,
+        )?;
+
+        // If credentials match, return user profile
+        match password_match {
+            PasswordMatch::Current => get_user_profile_by_id(conn, &self.id),
+            PasswordMatch::NeedsRehash => {
+                // Upgrade to Argon2id (or the current cost) while we have
+                // the plaintext password. Login still succeeds if this fails.
+                let new_password_hash = generate_credential(&attempted_password);
+                match set_new_password(conn, &self.id, &new_password_hash) {
+                    Ok(user) => {
+                        debug!("rehashed password for user: {}", self.id);
+                        Ok(user)
+                    },
+                    Err(e) => {
+                        warn!("could not rehash password for user {}: {:?}", self.id, e);
+                        get_user_profile_by_id(conn, &self.id)
+                    }
                 }
             }
         }
     }
 }
 
-pub fn generate_credential(salt: &str, password: &str) -> String {
-    // Store hash(password + salt) into credential
-    let mut credential: [u8; CREDENTIAL_LEN] = [0u8; CREDENTIAL_LEN];
-
-    pbkdf2::derive(
-        DIGEST_ALG,          // digest_alg: &'static Algorithm,
-        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(), // NonZeroU32
-        salt.as_bytes(),     // salt: &[u8]
-        password.as_bytes(), // secret: &[u8]
-        &mut credential,     // out: &mut [u8]
-    );
-    // Encode bytearray credential as base64 string
-    base64::encode(&credential)
-}
-
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -0,0 +1,37 @@
+use diesel::prelude::*;
+use dt::db::schema::webauthn_credentials;
+use dt::utils::dates::from_datetimestr_to_naivedatetime;
+use dt::utils::dates::from_datetimestr_to_option_naivedatetime;
+
+//////////////////////////////////////////////
+/// WebAuthn credentials (passkeys)
+//////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnCredential {
+    pub id: String,
+    pub user_id: String,
+    /// base64url credential ID
+    pub credential_id: String,
+    /// COSE_Key encoded public key
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: Option<String>,
+    #[serde(deserialize_with = "from_datetimestr_to_naivedatetime")]
+    pub created_at: chrono::NaiveDateTime,
+    #[serde(deserialize_with = "from_datetimestr_to_option_naivedatetime")]
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Insertable)]
+#[table_name = "webauthn_credentials"]
+pub struct NewWebauthnCredential {
+    pub id: String,
+    pub user_id: String,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: Option<String>,
+}
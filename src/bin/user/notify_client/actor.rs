@@ -13,6 +13,10 @@ use crate::rpc::{
     rpc_notify_user_created,
     rpc_send_welcome_email,
     rpc_send_password_reset_email,
+    rpc_send_magic_link_email,
+    rpc_send_verify_email,
+    rpc_send_email_change_emails,
+    rpc_send_password_changed_email,
 };
 use crate::notify_client::{
     NotifyActixError,
@@ -59,6 +63,27 @@ pub enum NotifyMessage {
         String, // resetId,
         chrono::NaiveDateTime, // expiresAt,
     ),
+    SendPasswordChangedEmail(
+        String, // email,
+    ),
+    SendMagicLinkEmail(
+        String, // email,
+        String, // loginToken,
+        String, // loginCode,
+        chrono::NaiveDateTime, // expiresAt,
+    ),
+    SendVerifyEmail(
+        String, // email,
+        String, // verifyToken,
+        chrono::NaiveDateTime, // expiresAt,
+    ),
+    SendEmailChangeEmails(
+        String, // oldEmail,
+        String, // newEmail,
+        String, // confirmToken,
+        String, // revertToken,
+        chrono::NaiveDateTime, // expiresAt,
+    ),
 }
 
 impl Message for NotifyMessage {
@@ -116,6 +141,68 @@ impl Handler<NotifyMessage> for NotifyActor {
                     ).await
                 }.into_actor(self))
             },
+            NotifyMessage::SendPasswordChangedEmail(email) => {
+                Box::pin(async move {
+                    // Tell the notify service to let the user know
+                    // their password was changed
+                    rpc_send_password_changed_email(
+                        &ref_client,
+                        &email
+                    ).await
+                }.into_actor(self))
+            },
+            NotifyMessage::SendMagicLinkEmail(
+                email,
+                login_token,
+                login_code,
+                expires_at,
+            ) => {
+                Box::pin(async move {
+                    // Tell the notify service to send a passwordless login email
+                    rpc_send_magic_link_email(
+                        &ref_client,
+                        &email,
+                        &login_token,
+                        &login_code,
+                        &expires_at
+                    ).await
+                }.into_actor(self))
+            },
+            NotifyMessage::SendVerifyEmail(
+                email,
+                verify_token,
+                expires_at,
+            ) => {
+                Box::pin(async move {
+                    // Tell the notify service to send an email verification link
+                    rpc_send_verify_email(
+                        &ref_client,
+                        &email,
+                        &verify_token,
+                        &expires_at
+                    ).await
+                }.into_actor(self))
+            },
+            NotifyMessage::SendEmailChangeEmails(
+                old_email,
+                new_email,
+                confirm_token,
+                revert_token,
+                expires_at,
+            ) => {
+                Box::pin(async move {
+                    // Tell the notify service to send the confirmation link
+                    // to the new email, and the revert link to the old email
+                    rpc_send_email_change_emails(
+                        &ref_client,
+                        &old_email,
+                        &new_email,
+                        &confirm_token,
+                        &revert_token,
+                        &expires_at
+                    ).await
+                }.into_actor(self))
+            },
         }
     }
 }
@@ -16,6 +16,14 @@ pub enum NotifyActixError {
     WelcomeEmail(ErrJson),
     #[fail(display = "{}", _0)]
     PasswordResetEmail(ErrJson),
+    #[fail(display = "{}", _0)]
+    PasswordChangedEmail(ErrJson),
+    #[fail(display = "{}", _0)]
+    MagicLinkEmail(ErrJson),
+    #[fail(display = "{}", _0)]
+    VerifyEmail(ErrJson),
+    #[fail(display = "{}", _0)]
+    EmailChangeEmail(ErrJson),
 }
 
 impl ResponseError for NotifyActixError {
@@ -36,6 +44,26 @@ impl ResponseError for NotifyActixError {
                 HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
                     .json(json!({ "file": ejson.file, "message": ejson.message }))
             },
+            NotifyActixError::PasswordChangedEmail(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            NotifyActixError::MagicLinkEmail(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            NotifyActixError::VerifyEmail(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
+            NotifyActixError::EmailChangeEmail(ejson) => {
+                warn!("{}: {}", ejson.file, ejson.message);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                    .json(json!({ "file": ejson.file, "message": ejson.message }))
+            },
        }
     }
 }
@@ -0,0 +1,277 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    error::ResponseError,
+    Error,
+    HttpResponse,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::auth::{Claims, connection_ip, decode_claims, request_jwt};
+use crate::rate_limit::{RateLimitHit, RateLimitStore};
+
+//////////////////////////////////////////////
+/// RateLimit Middleware
+//////////////////////////////////////////////
+/// Limits how often a route can be called per client IP, or per user:
+///     web::resource("/user/create")
+///         .wrap(RateLimit::new(
+///             RedisStore::new(database_actor.clone()),
+///             RateLimitPolicy::new("user_create", 10, 3600),
+///         ))
+///
+/// Limits can be overridden with env vars named after the policy,
+/// as "{requests}/{window seconds}", e.g. RATE_LIMIT_USER_CREATE=20/3600
+///
+/// Responses carry the RateLimit-Limit, RateLimit-Remaining and RateLimit-Reset
+/// (seconds) headers. Requests over the limit get a 429 with Retry-After.
+/// If the store is unavailable requests are let through.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitBy {
+    /// Client IP
+    Ip,
+    /// User id from the JWT, or the client IP when not logged in
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitPolicy {
+    pub name: String,
+    pub limit: u64,
+    pub window_seconds: u64,
+    pub by: RateLimitBy,
+}
+
+impl RateLimitPolicy {
+    /// Limit per client IP, unless overridden in RATE_LIMIT_{NAME}
+    pub fn new(name: &str, limit: u64, window_seconds: u64) -> Self {
+        dotenv::dotenv().ok();
+        let env_var = format!("RATE_LIMIT_{}", name.to_uppercase());
+        let (limit, window_seconds) = std::env::var(&env_var).ok()
+            .and_then(|s| parse_rate_limit(&s))
+            .unwrap_or((limit, window_seconds));
+
+        RateLimitPolicy {
+            name: String::from(name),
+            limit: limit,
+            window_seconds: window_seconds,
+            by: RateLimitBy::Ip,
+        }
+    }
+
+    pub fn by(mut self, by: RateLimitBy) -> Self {
+        self.by = by;
+        self
+    }
+
+    fn key(&self, req: &ServiceRequest) -> String {
+        let user_id = match self.by {
+            RateLimitBy::Ip => None,
+            RateLimitBy::User => request_jwt(req)
+                .and_then(|jwt| decode_claims::<Claims>(&jwt).ok())
+                .map(|claims| claims.sub),
+        };
+        match user_id {
+            Some(user_id) => format!("rate_limit:{}:user:{}", self.name, user_id),
+            None => format!(
+                "rate_limit:{}:ip:{}",
+                self.name,
+                connection_ip(req.peer_addr(), req.headers()).unwrap_or_else(|| String::from("unknown"))
+            ),
+        }
+    }
+}
+
+/// Parses "{requests}/{window seconds}", e.g. "10/60"
+pub fn parse_rate_limit(s: &str) -> Option<(u64, u64)> {
+    let mut parts = s.trim().splitn(2, '/');
+    let limit = parts.next()?.trim().parse::<u64>().ok()?;
+    let window_seconds = parts.next()?.trim().parse::<u64>().ok()?;
+    if window_seconds == 0 {
+        return None
+    }
+    Some((limit, window_seconds))
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, hit: &RateLimitHit) {
+    let reset_seconds = (hit.reset_ms + 999) / 1000;
+    headers.insert(HeaderName::from_static("ratelimit-limit"), HeaderValue::from(hit.limit));
+    headers.insert(HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(hit.remaining));
+    headers.insert(HeaderName::from_static("ratelimit-reset"), HeaderValue::from(reset_seconds));
+}
+
+pub struct RateLimit {
+    store: Rc<dyn RateLimitStore>,
+    policy: Rc<RateLimitPolicy>,
+}
+
+impl RateLimit {
+    pub fn new<T: RateLimitStore + 'static>(store: T, policy: RateLimitPolicy) -> Self {
+        RateLimit {
+            store: Rc::new(store),
+            policy: Rc::new(policy),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            store: self.store.clone(),
+            policy: self.policy.clone(),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    store: Rc<dyn RateLimitStore>,
+    policy: Rc<RateLimitPolicy>,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let hit = self.store.hit(
+            self.policy.key(&req),
+            self.policy.limit,
+            self.policy.window_seconds * 1000,
+            chrono::Utc::now().timestamp_millis() as u64,
+        );
+
+        Box::pin(async move {
+            let hit = match hit.await {
+                Ok(hit) => Some(hit),
+                Err(e) => {
+                    warn!("rate limit store error, allowing request: {}", e);
+                    None
+                }
+            };
+
+            if let Some(hit) = hit {
+                if !hit.allowed {
+                    return Ok(req.error_response(RateLimitError::Exceeded(hit)))
+                }
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+            if let Some(hit) = hit {
+                insert_rate_limit_headers(res.headers_mut(), &hit);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum RateLimitError {
+    #[fail(display = "{{\"status\":\"Rate limit exceeded\"}}")]
+    Exceeded(RateLimitHit),
+    #[fail(display = "{{\"status\":\"Rate limit store error: {}\"}}", _0)]
+    Store(String),
+}
+
+impl ResponseError for RateLimitError {
+    fn error_response(&self) -> HttpResponse {
+       match self {
+            RateLimitError::Exceeded(hit) => {
+                let mut res = HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                    .header("Retry-After", ((hit.reset_ms + 999) / 1000).to_string())
+                    .json(json!({
+                        "status": "RATE_LIMITED",
+                        "message": "Too many requests, try again later.",
+                    }));
+                insert_rate_limit_headers(res.headers_mut(), hit);
+                res
+            },
+            RateLimitError::Store(e) => {
+                warn!("rate limit store error: {}", e);
+                HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                .json(json!({
+                    "status": "ERROR",
+                    "message": "Something went wrong with rate limiting."
+                }))
+            },
+       }
+    }
+}
+
+
+#[test]
+fn parses_rate_limits_from_env_format() {
+    assert_eq!(parse_rate_limit("10/60"), Some((10, 60)));
+    assert_eq!(parse_rate_limit(" 5 / 3600 "), Some((5, 3600)));
+    assert_eq!(parse_rate_limit("10"), None);
+    assert_eq!(parse_rate_limit("10/0"), None);
+    assert_eq!(parse_rate_limit("ten/60"), None);
+}
+
+#[actix_rt::test]
+async fn rate_limit_middleware_rejects_requests_over_the_limit() {
+    use actix_web::{test, web, App};
+    use crate::rate_limit::MemoryStore;
+
+    let mut app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(
+                MemoryStore::new(),
+                RateLimitPolicy::new("test_route", 2, 60),
+            ))
+            .route("/", web::get().to(|| async { HttpResponse::Ok().finish() }))
+    ).await;
+
+    let request = |ip: &str| test::TestRequest::get()
+        .uri("/")
+        .peer_addr(format!("{}:4000", ip).parse().unwrap())
+        .to_request();
+
+    let res = test::call_service(&mut app, request("10.0.0.1")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("ratelimit-limit").unwrap(), "2");
+    assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), "1");
+    assert_eq!(res.headers().get("ratelimit-reset").unwrap(), "60");
+
+    let res = test::call_service(&mut app, request("10.0.0.1")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), "0");
+
+    let res = test::call_service(&mut app, request("10.0.0.1")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(res.headers().get("retry-after").is_some());
+
+    // other clients have their own limit
+    let res = test::call_service(&mut app, request("10.0.0.2")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
@@ -0,0 +1,5 @@
+pub mod middleware;
+pub mod store;
+
+pub use middleware::*;
+pub use store::*;
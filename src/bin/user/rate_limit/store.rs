@@ -0,0 +1,219 @@
+use actix::{Addr, Handler, SyncContext, Message};
+use futures::future::LocalBoxFuture;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use crate::db::DatabaseActor;
+use crate::rate_limit::RateLimitError;
+
+//////////////////////////////////////////////
+/// Rate limit stores
+//////////////////////////////////////////////
+/// Stores count requests in a sliding window log: the time of each allowed
+/// request in the last `window_ms` is kept, and a request is allowed if there
+/// are fewer than `limit` of them. Rejected requests are not counted.
+///
+/// `RedisStore` shares the counts between workers and service instances.
+/// `MemoryStore` keeps them in the worker, for tests and local development.
+
+/// Result of counting a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitHit {
+    pub allowed: bool,
+    pub limit: u64,
+    /// requests left in the window, after this one
+    pub remaining: u64,
+    /// milliseconds until the oldest request leaves the window
+    pub reset_ms: u64,
+}
+
+pub trait RateLimitStore {
+    fn hit(
+        &self,
+        key: String,
+        limit: u64,
+        window_ms: u64,
+        now_ms: u64,
+    ) -> LocalBoxFuture<'static, Result<RateLimitHit, RateLimitError>>;
+}
+
+
+/// Sliding window log in a redis sorted set, scored by request time.
+/// Runs on DatabaseActor's sync redis client.
+pub struct RedisStore {
+    database_actor: Addr<DatabaseActor>,
+}
+
+impl RedisStore {
+    pub fn new(database_actor: Addr<DatabaseActor>) -> Self {
+        RedisStore { database_actor: database_actor }
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    fn hit(
+        &self,
+        key: String,
+        limit: u64,
+        window_ms: u64,
+        now_ms: u64,
+    ) -> LocalBoxFuture<'static, Result<RateLimitHit, RateLimitError>> {
+        let database_actor = self.database_actor.clone();
+        Box::pin(async move {
+            database_actor
+                .send(SlidingWindowHit {
+                    key: key,
+                    limit: limit,
+                    window_ms: window_ms,
+                    now_ms: now_ms,
+                })
+                .await
+                .map_err(|e| RateLimitError::Store(e.to_string()))?
+        })
+    }
+}
+
+// KEYS[1]: key, ARGV: now_ms, window_ms, limit, unique member for this request.
+// Returns { allowed, count, reset_ms }
+const SLIDING_WINDOW_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local limit = tonumber(ARGV[3])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+local allowed = 0
+if count < limit then
+    redis.call('ZADD', KEYS[1], now, ARGV[4])
+    count = count + 1
+    allowed = 1
+end
+redis.call('PEXPIRE', KEYS[1], window)
+local reset = window
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+if oldest[2] then
+    reset = tonumber(oldest[2]) + window - now
+end
+return { allowed, count, reset }
+";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SlidingWindowHit {
+    pub key: String,
+    pub limit: u64,
+    pub window_ms: u64,
+    pub now_ms: u64,
+}
+
+impl Message for SlidingWindowHit {
+    type Result = Result<RateLimitHit, RateLimitError>;
+}
+
+impl Handler<SlidingWindowHit> for DatabaseActor {
+    type Result = Result<RateLimitHit, RateLimitError>;
+
+    fn handle(
+        &mut self,
+        msg: SlidingWindowHit,
+        _ctx: &mut SyncContext<Self>
+    ) -> Result<RateLimitHit, RateLimitError> {
+
+        let mut conn = self.redis_sync_client.clone().get_connection()
+            .map_err(|e| RateLimitError::Store(e.to_string()))?;
+
+        let (allowed, count, reset_ms): (i64, u64, u64) = redis::Script::new(SLIDING_WINDOW_SCRIPT)
+            .key(&msg.key)
+            .arg(msg.now_ms)
+            .arg(msg.window_ms)
+            .arg(msg.limit)
+            .arg(uuid::Uuid::new_v4().to_string())
+            .invoke(&mut conn)
+            .map_err(|e| RateLimitError::Store(e.to_string()))?;
+
+        Ok(RateLimitHit {
+            allowed: allowed == 1,
+            limit: msg.limit,
+            remaining: msg.limit.saturating_sub(count),
+            reset_ms: reset_ms,
+        })
+    }
+}
+
+
+/// In-process sliding window log. Counts are per worker thread
+/// and lost on restart, so only use this in tests.
+pub struct MemoryStore {
+    hits: RefCell<HashMap<String, VecDeque<u64>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore { hits: RefCell::new(HashMap::new()) }
+    }
+
+    pub fn hit_now(
+        &self,
+        key: &str,
+        limit: u64,
+        window_ms: u64,
+        now_ms: u64,
+    ) -> RateLimitHit {
+        let mut hits = self.hits.borrow_mut();
+        let log = hits.entry(key.to_string()).or_insert_with(VecDeque::new);
+
+        while log.front().map(|t| *t + window_ms <= now_ms).unwrap_or(false) {
+            log.pop_front();
+        }
+
+        let allowed = (log.len() as u64) < limit;
+        if allowed {
+            log.push_back(now_ms);
+        }
+
+        RateLimitHit {
+            allowed: allowed,
+            limit: limit,
+            remaining: limit.saturating_sub(log.len() as u64),
+            reset_ms: log.front().map(|t| *t + window_ms - now_ms).unwrap_or(window_ms),
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn hit(
+        &self,
+        key: String,
+        limit: u64,
+        window_ms: u64,
+        now_ms: u64,
+    ) -> LocalBoxFuture<'static, Result<RateLimitHit, RateLimitError>> {
+        let hit = self.hit_now(&key, limit, window_ms, now_ms);
+        Box::pin(async move { Ok(hit) })
+    }
+}
+
+
+#[test]
+fn memory_store_slides_the_window() {
+    let store = MemoryStore::new();
+    let window = 1000;
+
+    let first = store.hit_now("k", 2, window, 0);
+    assert!(first.allowed);
+    assert_eq!(first.remaining, 1);
+    assert!(store.hit_now("k", 2, window, 400).allowed);
+
+    let rejected = store.hit_now("k", 2, window, 500);
+    assert!(!rejected.allowed);
+    assert_eq!(rejected.remaining, 0);
+    // the request at t=0 leaves the window at t=1000
+    assert_eq!(rejected.reset_ms, 500);
+
+    // rejected requests are not counted
+    let after_first_expires = store.hit_now("k", 2, window, 1000);
+    assert!(after_first_expires.allowed);
+    assert_eq!(after_first_expires.reset_ms, 400);
+    assert!(!store.hit_now("k", 2, window, 1001).allowed);
+
+    // keys are counted separately
+    assert!(store.hit_now("other", 2, window, 1001).allowed);
+}
@@ -30,6 +30,11 @@ pub enum RedisCommand {
     Set(String, String),
     Get(String),
     Del(String),
+    /// GET then DEL the key atomically, for single use values.
+    /// Errors if the key does not exist.
+    GetDel(String),
+    /// INCR a counter and (re)set its TTL, returns the new count
+    Incr(Incr),
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Setex {
@@ -38,6 +43,12 @@ pub struct Setex {
     pub value: String,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Incr {
+    pub key: String,
+    pub ttl: i32, // TTL in seconds
+}
+
 impl Message for RedisCommand {
     type Result = Result<String, RedisActixError>;
 }
@@ -76,6 +87,22 @@ pub fn exec_redis_command(
                 .arg(value)
                 .query(conn)
         },
+        RedisCommand::GetDel(key) => {
+            redis::pipe()
+                .atomic()
+                .cmd("GET").arg(&key)
+                .cmd("DEL").arg(&key).ignore()
+                .query::<(String,)>(conn)
+                .map(|(value,)| value)
+        },
+        RedisCommand::Incr(incr) => {
+            redis::pipe()
+                .atomic()
+                .cmd("INCR").arg(&incr.key)
+                .cmd("EXPIRE").arg(&incr.key).arg(incr.ttl).ignore()
+                .query::<(i64,)>(conn)
+                .map(|(count,)| count.to_string())
+        },
         RedisCommand::Setex(setex) => {
             redis::cmd("SETEX")
                 .arg(setex.key)
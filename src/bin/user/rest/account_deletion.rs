@@ -0,0 +1,172 @@
+use actix_web::{
+    web::{Json, Query},
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use chrono::Local;
+
+use crate::auth::{
+    AuthInfo,
+    MfaError,
+    check_login_lockout,
+    record_login_failure,
+    clear_account_lockout,
+    verify_totp,
+    hash_recovery_code,
+};
+use crate::db::{
+    GetPool,
+    checkPasswordForUserId,
+    getRestorableAccountDeletion,
+    getRestorableAccountDeletionByEmail,
+    restoreUser,
+    getTotp,
+    useTotpStep,
+    useRecoveryCode,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    email_identity,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Restoring deleted accounts during their grace period,
+/// see `auth::account_deletion`
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreAccountForm {
+    /// The account's email before it was deleted
+    pub email: String,
+    pub password: String,
+    /// For users with 2FA enabled, a code from their authenticator app
+    pub code: Option<String>,
+    /// or one of their one-time recovery codes
+    pub recovery_code: Option<String>,
+}
+
+// POST /account/restore
+// The owner re-verifies with their password (and 2FA), then logs in as usual
+pub async fn restore_account_handler(
+    req: HttpRequest,
+    json: Json<RestoreAccountForm>,
+) -> Result<HttpResponse, Error> {
+
+    let form = json.into_inner();
+
+    // Shares the login lockout, so this can't be used to guess passwords
+    check_login_lockout(&req, &form.email).await?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // Same error for unknown emails and wrong passwords
+    let restorable = getRestorableAccountDeletionByEmail(&conn, &form.email)?
+        .and_then(|deletion| {
+            checkPasswordForUserId(&conn, deletion.user_id.clone(), form.password.clone())
+                .ok()
+                .map(|user| (deletion, user))
+        });
+
+    let (deletion, user) = match restorable {
+        Some(restorable) => restorable,
+        None => {
+            audit(&req, AuditAction::AccountRestore)
+                .failed()
+                .payload(json!({ "email": email_identity(&form.email), "reason": "wrong_password" }))
+                .record();
+            record_login_failure(&req, &form.email).await?;
+            return Err(Error::from(LoginError::WrongPassword(
+                errJson!("Wrong email or password, or the account can't be restored"))))
+        }
+    };
+
+    if let Some(totp) = getTotp(&conn, &user.id)?.filter(|totp| totp.enabled) {
+        let verified = match (form.code, form.recovery_code) {
+            (Some(code), _) => match verify_totp(&totp.secret, &code, Local::now().timestamp()) {
+                // Reject codes which were already used
+                Some(step) => useTotpStep(&conn, &user.id, step)?,
+                None => false,
+            },
+            (None, Some(recovery_code)) => {
+                useRecoveryCode(&conn, &user.id, &hash_recovery_code(&recovery_code))?
+            },
+            (None, None) => {
+                return Err(Error::from(LoginError::BadRequest(
+                    errJson!("2FA is enabled, code or recovery_code required"))))
+            }
+        };
+        if !verified {
+            audit(&req, AuditAction::AccountRestore)
+                .actor(&user.id)
+                .user(&user.id)
+                .failed()
+                .payload(json!({ "deletionId": deletion.id, "reason": "invalid_code" }))
+                .record();
+            record_login_failure(&req, &form.email).await?;
+            return Err(Error::from(MfaError::InvalidCode))
+        }
+    }
+
+    let restored_user = restoreUser(&conn, &deletion, &user.id)?;
+    clear_account_lockout(&req, &form.email);
+
+    audit(&req, AuditAction::AccountRestore)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "deletionId": deletion.id }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Account restored, please login",
+            "user": restored_user,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminRestoreUserQuery {
+    pub user_id: String,
+}
+
+// POST /auth/admin/users/restore?user_id=
+// Permission "users:restore" required for this route
+pub async fn admin_restore_user_handler(
+    req: HttpRequest,
+    query: Query<AdminRestoreUserQuery>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let query = query.into_inner();
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let deletion = getRestorableAccountDeletion(&conn, &query.user_id)?
+        .ok_or(Error::from(LoginError::BadRequest(
+            errJson!("User isn't deleted, or was already erased"))))?;
+
+    let restored_user = restoreUser(&conn, &deletion, &authInfo.user_id)?;
+
+    info!("user {} restored by admin {}", restored_user.id, authInfo.user_id);
+    audit(&req, AuditAction::AccountRestore)
+        .by(&authInfo)
+        .user(&restored_user.id)
+        .payload(json!({ "deletionId": deletion.id }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Account restored",
+            "user": restored_user,
+        })))
+}
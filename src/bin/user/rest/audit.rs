@@ -0,0 +1,128 @@
+use actix_web::{
+    web::Query,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::auth::AuthInfo;
+use crate::db::{
+    GetPool,
+    getAuditEvents,
+};
+use crate::models::{
+    AuditEvent,
+    AuditEventQuery,
+    B64Cursor,
+    Connection,
+    Edge,
+    PageInfo,
+    PaginateError,
+    ErrJson,
+    decode_datetime_cursor,
+};
+use crate::AppState;
+
+/// Searching the security audit log, see `audit`
+
+const DEFAULT_AUDIT_PAGE_SIZE: i64 = 25;
+const MAX_AUDIT_PAGE_SIZE: i64 = 100;
+const AUDIT_CURSOR_NAME: &str = "created_at";
+
+// GET /auth/admin/audit?userId=&actorId=&action=&outcome=&ip=&createdAfter=&createdBefore=&cursor=&count=
+// Permission "audit:read" required for this route
+pub async fn admin_audit_events_handler(
+    req: HttpRequest,
+    query: Query<AuditEventQuery>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    debug!("audit log searched by {}: {:?}", authInfo.user_id, query);
+    let connection = audit_events_connection(&req, query.into_inner()).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(connection))
+}
+
+// GET /auth/audit?action=&outcome=&cursor=&count=
+// The logged in user's own security events, e.g. logins from new devices
+pub async fn my_audit_events_handler(
+    req: HttpRequest,
+    query: Query<AuditEventQuery>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let query = AuditEventQuery {
+        user_id: Some(authInfo.user_id.clone()),
+        // Users can't search by who did what to other accounts
+        actor_id: None,
+        ..query.into_inner()
+    };
+    let mut connection = audit_events_connection(&req, query).await?;
+    // Admins' ip, user agent and notes aren't shown to the user
+    connection.edges = connection.edges.into_iter()
+        .map(|edge| Edge {
+            node: edge.node.for_user(&authInfo.user_id),
+            ..edge
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(connection))
+}
+
+async fn audit_events_connection(
+    req: &HttpRequest,
+    query: AuditEventQuery,
+) -> Result<Connection<AuditEvent>, Error> {
+
+    let count = query.count
+        .unwrap_or(DEFAULT_AUDIT_PAGE_SIZE)
+        .max(1)
+        .min(MAX_AUDIT_PAGE_SIZE);
+
+    let before = match &query.cursor {
+        None => None,
+        Some(cursor) => {
+            let cursor = decode_datetime_cursor(cursor)?;
+            match (cursor.name == AUDIT_CURSOR_NAME, cursor.id) {
+                (true, Some(id)) => Some((cursor.value, id)),
+                _ => return Err(Error::from(PaginateError::InvalidCursor(
+                    errJson!("Not an audit log cursor")))),
+            }
+        }
+    };
+
+    let conn = AppState::databaseActor(req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // Overfetch by 1 to see if there is a next page
+    let mut events = getAuditEvents(&conn, &query, before, count + 1)?;
+    let is_last_page = events.len() as i64 <= count;
+    events.truncate(count as usize);
+
+    let edges = events.into_iter()
+        .map(|event| Edge {
+            cursor: Some(B64Cursor {
+                name: AUDIT_CURSOR_NAME.to_string(),
+                value: event.created_at,
+                id: Some(event.id.clone()),
+            }.to_b64_string()),
+            node: event,
+        })
+        .collect::<Vec<Edge<AuditEvent>>>();
+
+    Ok(Connection {
+        pageInfo: PageInfo {
+            startCursor: edges.first().and_then(|e| e.cursor.clone()),
+            endCursor: edges.last().and_then(|e| e.cursor.clone()),
+            isLastPage: is_last_page,
+            totalPages: None,
+        },
+        edges: edges,
+        totalCount: None,
+    })
+}
@@ -0,0 +1,124 @@
+use actix_web::{
+    http::header,
+    web::Json,
+    web::Query,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::auth::AuthInfo;
+use crate::data_export::{
+    CreateDataExport,
+    GetDataExport,
+    RedeemDataExport,
+    spawn_data_export,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Personal data exports, see `data_export`
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateDataExportQuery {
+    /// Also ask the payment service for the user's payment data
+    pub include_payments: Option<bool>,
+}
+
+// POST /auth/export?include_payments=true
+pub async fn create_data_export_handler(
+    req: HttpRequest,
+    query: Query<CreateDataExportQuery>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    // The download token is as good as the user's data
+    authInfo.forbid_impersonation()?;
+
+    let (record, token) = AppState::databaseActor(&req)
+                .send(CreateDataExport {
+                    user_id: authInfo.user_id.clone(),
+                    include_payments: query.include_payments.unwrap_or(false),
+                })
+                .await??;
+
+    spawn_data_export(
+        AppState::databaseActor(&req).clone(),
+        AppState::httpClient(&req).clone(),
+        record.clone(),
+    );
+
+    audit(&req, AuditAction::DataExport)
+        .by(&authInfo)
+        .payload(json!({ "exportId": record.id, "includePayments": record.include_payments }))
+        .record();
+
+    Ok(HttpResponse::Accepted()
+        .content_type("application_json")
+        .json(json!({
+            "exportId": record.id,
+            "status": record.status,
+            "downloadToken": token,
+            "expiresAt": record.expires_at,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataExportStatusQuery {
+    pub export_id: String,
+}
+
+// GET /auth/export/status?export_id=exp_123
+pub async fn data_export_status_handler(
+    req: HttpRequest,
+    query: Query<DataExportStatusQuery>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let record = AppState::databaseActor(&req)
+                .send(GetDataExport {
+                    user_id: authInfo.user_id.clone(),
+                    export_id: query.into_inner().export_id,
+                })
+                .await??;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(record.status_json()))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadDataExportBody {
+    pub token: String,
+}
+
+// POST /export/download
+// The token is the credential. It's taken in the body rather than the
+// url, so it's never written to access logs.
+pub async fn download_data_export_handler(
+    req: HttpRequest,
+    json: Json<DownloadDataExportBody>,
+) -> Result<HttpResponse, Error> {
+
+    let (record, archive) = AppState::databaseActor(&req)
+                .send(RedeemDataExport(json.into_inner().token))
+                .await??;
+
+    audit(&req, AuditAction::DataExport)
+        .actor(&record.user_id)
+        .user(&record.user_id)
+        .payload(json!({ "exportId": record.id, "downloaded": true }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.json\"", record.id),
+        )
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(archive))
+}
@@ -0,0 +1,216 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use chrono::{Local, Duration};
+
+use crate::db::{
+    GetPool,
+    getUser,
+    checkPasswordForUserId,
+    insertPendingEmailChange,
+    getPendingEmailChangeByConfirmHash,
+    getPendingEmailChangeByRevertHash,
+    confirmEmailChange,
+    revertEmailChange,
+};
+use crate::auth::{
+    AuthInfo,
+    RevokeUserTokens,
+    hash_token,
+    check_login_lockout,
+    record_login_failure,
+    clear_account_lockout,
+};
+use crate::email::{
+    EmailVerifyError,
+    generate_email_change_token,
+    EMAIL_CHANGE_CONFIRM_TTL_HOURS,
+    EMAIL_CHANGE_REVERT_TTL_DAYS,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    NewPendingEmailChange,
+    normalize_email,
+    email_identity,
+};
+use crate::notify_client::NotifyMessage;
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Email changes, see `email::email_change`.
+/// Emails aren't changed by /auth/profile/update.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeEmailForm {
+    pub current_password: String,
+    pub new_email: String,
+}
+
+// POST /auth/profile/changeEmail
+pub async fn change_email_handler(
+    req: HttpRequest,
+    json: Json<ChangeEmailForm>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let form = json.into_inner();
+    let new_email = normalize_email(&form.new_email)?;
+
+    if !validator::validate_email(&new_email) {
+        return Err(Error::from(LoginError::EmailInvalid(errJson!("New email is invalid"))))
+    }
+
+    // Wrong passwords count towards the login lockout
+    check_login_lockout(&req, &authInfo.email).await?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user = match checkPasswordForUserId(&conn, authInfo.user_id.clone(), form.current_password) {
+        Ok(user) => user,
+        Err(e) => {
+            audit(&req, AuditAction::EmailChange)
+                .by(&authInfo)
+                .failed()
+                .payload(json!({ "reason": "wrong_password" }))
+                .record();
+            record_login_failure(&req, &authInfo.email).await?;
+            return Err(Error::from(e))
+        }
+    };
+    clear_account_lockout(&req, &authInfo.email);
+
+    if email_identity(&new_email) == email_identity(&user.email) {
+        return Err(Error::from(LoginError::BadRequest(errJson!("New email is the same as the current email"))))
+    }
+    if getUser(&conn, Some(&new_email), None).is_ok() {
+        return Err(Error::from(LoginError::DuplicateUser(errJson!("Email is already in use"))))
+    }
+
+    let confirm_token = generate_email_change_token()?;
+    let revert_token = generate_email_change_token()?;
+    let expires_at = Local::now().naive_utc() + Duration::hours(EMAIL_CHANGE_CONFIRM_TTL_HOURS);
+
+    let change = insertPendingEmailChange(&conn, NewPendingEmailChange {
+        id: format!("pec_{}", uuid::Uuid::new_v4()),
+        user_id: user.id.clone(),
+        old_email: user.email.clone(),
+        new_email: new_email.clone(),
+        confirm_token_hash: hash_token(&confirm_token),
+        revert_token_hash: hash_token(&revert_token),
+        expires_at: expires_at,
+    })?;
+
+    AppState::notifyActor(&req)
+        .do_send(NotifyMessage::SendEmailChangeEmails(
+            change.old_email.clone(),
+            change.new_email.clone(),
+            confirm_token,
+            revert_token,
+            expires_at,
+        ));
+
+    audit(&req, AuditAction::EmailChange)
+        .by(&authInfo)
+        .payload(json!({ "oldEmail": change.old_email, "newEmail": change.new_email }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "A confirmation link has been sent to the new email.",
+            "pendingEmailChange": change,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailChangeTokenForm {
+    pub token: String,
+}
+
+// POST /verify/email/change/confirm
+// Token from the link sent to the new email
+pub async fn confirm_email_change_handler(
+    req: HttpRequest,
+    json: Json<EmailChangeTokenForm>,
+) -> Result<HttpResponse, Error> {
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let change = getPendingEmailChangeByConfirmHash(&conn, &hash_token(&json.token))?
+        .filter(|c| c.confirmed_at.is_none() && c.reverted_at.is_none())
+        .filter(|c| c.expires_at > Local::now().naive_utc())
+        .ok_or(EmailVerifyError::VerificationError(
+            errJson!("Email change link is invalid or has expired")
+        ))?;
+
+    let user = confirmEmailChange(&conn, &change)?;
+    info!("email changed for user: {}", user.id);
+    audit(&req, AuditAction::EmailChangeConfirm)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "oldEmail": change.old_email, "newEmail": change.new_email }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Email changed",
+            "email": user.email,
+            "emailVerified": user.email_verified,
+        })))
+}
+
+// POST /verify/email/change/revert
+// Token from the link sent to the old email
+pub async fn revert_email_change_handler(
+    req: HttpRequest,
+    json: Json<EmailChangeTokenForm>,
+) -> Result<HttpResponse, Error> {
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let change = getPendingEmailChangeByRevertHash(&conn, &hash_token(&json.token))?
+        .filter(|c| c.reverted_at.is_none())
+        .filter(|c| {
+            c.created_at + Duration::days(EMAIL_CHANGE_REVERT_TTL_DAYS) > Local::now().naive_utc()
+        })
+        .ok_or(EmailVerifyError::VerificationError(
+            errJson!("Email change link is invalid or has expired")
+        ))?;
+
+    let user = revertEmailChange(&conn, &change)?;
+    info!("email change reverted for user: {}", user.id);
+    audit(&req, AuditAction::EmailChangeRevert)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "oldEmail": change.old_email, "newEmail": change.new_email }))
+        .record();
+
+    // Whoever asked for the change knew the password,
+    // so sign out every session
+    AppState::databaseActor(&req)
+        .send(RevokeUserTokens(user.id.clone()))
+        .await??;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Email change cancelled. If you didn't ask for it, please reset your password.",
+            "email": user.email,
+        })))
+}
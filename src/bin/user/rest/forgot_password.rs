@@ -1,123 +1,132 @@
 
-use actix::{Addr, SyncArbiter, Arbiter};
+use actix::Addr;
 use actix_web::{
-    http,
-    http::{header, Method},
-    web, web::Data, web::Query, web::Json,
-    error, Error,
-    HttpRequest, HttpResponse, HttpMessage,
-    Responder,
+    web, web::Json,
+    Error,
+    HttpRequest, HttpResponse,
 };
-use actix_identity::{Identity};
 
-use chrono::{Local, Utc, Duration};
-use redis::RedisResult;
-use std::path::PathBuf;
-use std::io::Read;
-
-use crate::endpoints::Endpoint;
-use crate::models::auth::{
-    ResetPasswordForm,
-    RequestResetPasswordForm,
-};
-use crate::models::{
-    User,
-    ErrJson,
-    UpdateUserProfile,
-    errors::LoginError,
-};
+use crate::models::auth::RequestResetPasswordForm;
+use crate::models::User;
 use crate::email::password_reset::{
-    PasswordReset,
-    PasswordResetResponse,
+    CreatePasswordReset,
+    RedeemPasswordReset,
+    PasswordResetLink,
 };
-use crate::email::PasswordResetError;
 use crate::db::{
+    DatabaseActor,
     GetPool,
-    GetPoolError,
-    loginUser,
     getUser,
 };
-
-use crate::redis_client::{
-    RedisCommand, Setex,
-};
 use crate::notify_client::{
+    NotifyActor,
     NotifyMessage
 };
-use crate::email::SendgridStatus;
+use crate::auth::clear_account_lockout;
+use crate::audit::audit;
+use crate::models::AuditAction;
 use crate::AppState;
 
 
 
 // REST /forgot/1/sendResetPasswordEmail
-// no password required, send email to reset pw
+// no password required, send email to reset pw.
+// Responds the same whether or not the account exists, and the reset
+// is done in the background so response times don't give it away either.
 pub async fn send_reset_password_email_handler(
     req: HttpRequest,
     data: Json<RequestResetPasswordForm>
 ) -> Result<HttpResponse, Error> {
 
-    let request_reset_pw = data.into_inner();
-    debug!("body: {:?}", &request_reset_pw);
-    let pw_reset = PasswordReset::from(&request_reset_pw);
-    debug!("pw_reset args: {:?}", &pw_reset);
-
-    // cache password request
-    let _redis_res = AppState::redisActor(&req)
-                .send(RedisCommand::Setex(
-                    Setex {
-                        key: pw_reset.reset_id.clone(),
-                        ttl: 3600, // 1hr
-                        value: pw_reset.email.clone(),
-                    }
-                ))
-                .await?;
-
-
-    // then send out a password reset email
-    let res = AppState::notifyActor(&req)
-                .send(NotifyMessage::SendPasswordResetEmail(
-                    pw_reset.email.clone(),
-                    pw_reset.reset_id.clone(),
-                    pw_reset.expires_at.clone(),
-                ))
-                .await??;
+    let email = data.into_inner().email;
+    let database_actor = AppState::databaseActor(&req).clone();
+    let notify_actor = AppState::notifyActor(&req).clone();
 
-    // debug!("pw_reset args: {:?}", &pw_reset);
+    actix_rt::spawn(async move {
+        if let Err(e) = send_reset_password_email(database_actor, notify_actor, email).await {
+            warn!("password reset email not sent: {:?}", e);
+        }
+    });
 
     Ok(HttpResponse::Ok()
     .content_type("application_json")
     .json(
         json!({
-            "reset_id": pw_reset.reset_id,
-            "email_sent_to": pw_reset.email,
-            "status": res
+            "status": "If an account exists for that email, a password reset link has been sent."
         })
     ))
 }
 
+async fn send_reset_password_email(
+    database_actor: Addr<DatabaseActor>,
+    notify_actor: Addr<NotifyActor>,
+    email: String,
+) -> Result<(), Error> {
+
+    let conn = database_actor
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user: User = match getUser(&conn, Some(&email), None) {
+        Ok(user) if !user.is_deleted && !user.is_suspended => user,
+        _ => {
+            debug!("no active account for password reset");
+            return Ok(())
+        }
+    };
+
+    // store a hash of a new reset token, replacing any previous one
+    let link: PasswordResetLink = database_actor
+                .send(CreatePasswordReset {
+                    user_id: user.id.clone(),
+                    email: user.email.clone(),
+                })
+                .await??;
+
+    // then send out a password reset email
+    notify_actor
+        .send(NotifyMessage::SendPasswordResetEmail(
+            user.email,
+            link.reset_id,
+            link.expires_at,
+        ))
+        .await??;
+
+    Ok(())
+}
+
 
 // POST /forgot/2/resetPassword
 pub async fn reset_password_handler(
     req: HttpRequest,
-    json: web::Json<PasswordReset>,
+    json: web::Json<RedeemPasswordReset>,
 ) -> Result<HttpResponse, Error> {
 
-    debug!("form: \n{:?}\n", &json);
-    let pw_reset: PasswordReset = json.into_inner();
+    // RedeemPasswordReset is a actix message that triggers
+    // the DatabaseActor actor to check and burn the reset token,
+    // generate a new password_hash, then save to DB.
+    let user: User = AppState::databaseActor(&req)
+                .send(json.into_inner())
+                .await??;
 
-    // pw_reset: PasswordReset is a actix message that triggers
-    // the DatabaseActor actor to generate a new password_hash
-    // based on new password then save to DB.
+    debug!("password reset for user: {:?}", &user.id);
+    audit(&req, AuditAction::PasswordReset)
+        .actor(&user.id)
+        .user(&user.id)
+        .record();
 
-    let res = AppState::databaseActor(&req)
-                .send(pw_reset)
-                .await?;
+    // Resetting the password proves ownership of the account
+    clear_account_lockout(&req, &user.email);
 
-    debug!("pw_reset result: {:?}", &res);
+    AppState::notifyActor(&req)
+        .do_send(NotifyMessage::SendPasswordChangedEmail(user.email.clone()));
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
-        .json(res))
+        .json(json!({
+            "status": "Password reset",
+            "email": user.email,
+        })))
 }
 
 
@@ -0,0 +1,107 @@
+use actix_web::{
+    web::Query,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::auth::{
+    AuthInfo,
+    SessionClient,
+    PERMISSIONS,
+    encode_claims,
+    impersonation_claims,
+};
+use crate::db::{
+    GetPool,
+    getUser,
+    insertImpersonation,
+};
+use crate::models::{
+    LoginError,
+    ErrJson,
+    UserRole,
+    NewImpersonation,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Admin impersonation, see `auth::impersonation`
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImpersonateQuery {
+    pub user_id: String,
+    /// e.g. a support ticket, kept in the audit trail
+    pub reason: Option<String>,
+}
+
+// POST /auth/impersonate?user_id=user_id
+// Permission "users:impersonate" required for this route
+pub async fn impersonate_handler(
+    req: HttpRequest,
+    query: Query<ImpersonateQuery>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let query = query.into_inner();
+
+    if authInfo.is_impersonation() {
+        return Err(Error::from(LoginError::Forbidden(
+            errJson!("Can't impersonate while impersonating a user"))))
+    }
+    if query.user_id == authInfo.user_id {
+        return Err(Error::from(LoginError::BadRequest(
+            errJson!("Can't impersonate yourself"))))
+    }
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user = getUser(&conn, None, Some(&query.user_id))
+        .map_err(Error::from)?;
+
+    if user.is_suspended || user.is_deleted {
+        return Err(Error::from(LoginError::BadRequest(
+            errJson!("User is suspended or deleted"))))
+    }
+    // Otherwise an admin could act with another admin's permissions
+    let role = user.user_role.clone().unwrap_or(UserRole::USER);
+    if !PERMISSIONS.permissions(&role).is_empty() {
+        return Err(Error::from(LoginError::Forbidden(
+            errJson!("Can't impersonate users with admin permissions"))))
+    }
+
+    let claims = impersonation_claims(&user, &authInfo.user_id);
+    let client = SessionClient::from_request(&req);
+
+    // Record the impersonation before handing out the token
+    let impersonation = insertImpersonation(&conn, NewImpersonation {
+        id: format!("imp_{}", uuid::Uuid::new_v4()),
+        admin_id: authInfo.user_id.clone(),
+        user_id: user.id.clone(),
+        reason: query.reason,
+        jti: claims.jti.clone(),
+        ip: client.ip,
+        user_agent: client.user_agent,
+        expires_at: chrono::NaiveDateTime::from_timestamp(claims.exp, 0),
+    })?;
+
+    let jwt = encode_claims(&claims)?;
+
+    warn!("user {} impersonated by admin {}: {}", user.id, authInfo.user_id, impersonation.id);
+    audit(&req, AuditAction::Impersonate)
+        .by(&authInfo)
+        .user(&user.id)
+        .payload(json!({ "impersonationId": impersonation.id, "reason": impersonation.reason }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "jwt": jwt,
+            "expiresIn": claims.remaining_lifetime(),
+            "impersonation": impersonation,
+        })))
+}
@@ -0,0 +1,73 @@
+use actix_web::{
+    http::header,
+    web::Form,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use actix_identity::{Identity};
+
+use crate::auth::{
+    ServiceAuth,
+    verify_jwt,
+};
+
+
+/// RFC 7662 introspection request.
+/// `token` is an access JWT. If it is missing, the "degen-auth" identity
+/// cookie forwarded by the caller is introspected instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntrospectionRequest {
+    pub token: Option<String>,
+    pub token_type_hint: Option<String>,
+}
+
+// POST /oauth/introspect
+// Service credentials required for this route (HTTP Basic)
+pub async fn introspect_handler(
+    req: HttpRequest,
+    service: ServiceAuth,
+    id: Identity,
+    form: Option<Form<IntrospectionRequest>>,
+) -> Result<HttpResponse, Error> {
+
+    let token = form
+        .and_then(|f| f.into_inner().token)
+        .or(id.identity());
+
+    let inactive = HttpResponse::Ok()
+        .header(header::CACHE_CONTROL, "no-store")
+        .json(json!({ "active": false }));
+
+    let jwt = match token {
+        None => return Ok(inactive),
+        Some(jwt) => jwt,
+    };
+
+    // Invalid, expired, revoked tokens, or suspended and deleted users
+    // are all just inactive to the caller.
+    match verify_jwt(&req, &jwt).await {
+        Err(e) => {
+            debug!("introspection by {}: inactive token: {}", service.client_id, e);
+            Ok(inactive)
+        },
+        Ok((claims, _user)) => {
+            Ok(HttpResponse::Ok()
+                .header(header::CACHE_CONTROL, "no-store")
+                .json(json!({
+                    "active": true,
+                    "sub": claims.sub,
+                    "aud": claims.aud,
+                    "iss": claims.iss,
+                    "exp": claims.exp,
+                    "iat": claims.iat,
+                    "jti": claims.jti,
+                    "sid": claims.sid,
+                    "act": claims.act,
+                    "scope": claims.scope,
+                    "username": claims.email,
+                    "token_type": "Bearer",
+                })))
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use actix_web::{
+    http::header,
+    HttpRequest,
+    HttpResponse,
+};
+use crate::auth::JWT_KEYS;
+
+// GET /.well-known/jwks.json
+// Public keys for the gateway and other services to verify JWTs offline
+pub fn jwks_handler(_req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        // let verifiers cache keys, but pick up rotated keys within minutes
+        .header(header::CACHE_CONTROL, "public, max-age=300")
+        .json(JWT_KEYS.jwks())
+}
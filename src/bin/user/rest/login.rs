@@ -6,6 +6,9 @@ use actix_web::{
     Error,
 };
 use actix_identity::{Identity};
+use chrono::Local;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
 use crate::auth::{
     AuthInfo,
 };
@@ -14,6 +17,10 @@ use crate::db::{
     loginUser,
     getUser,
     checkPasswordForUserId,
+    getTotp,
+    useTotpStep,
+    useRecoveryCode,
+    checkSuspension,
 };
 use crate::db::{
     GetPool, GetPoolError
@@ -23,7 +30,31 @@ use crate::redis_client::{
 };
 use crate::auth::{
     CheckJwt, CheckJwtError,
+    Claims,
     decode_token,
+    decode_claims,
+    revoked_jti_key,
+    request_jwt,
+    issue_token_pair,
+    refresh_cookie,
+    removal_refresh_cookie,
+    current_refresh_token,
+    RevokeRefreshToken,
+    RevokeSession,
+    TokenPair,
+    // Brute-force protection
+    check_login_lockout,
+    record_login_failure,
+    clear_account_lockout,
+    // Two-factor login
+    MFA_PENDING_SCOPE,
+    MFA_MAX_ATTEMPTS,
+    MfaError,
+    CountMfaAttempt,
+    create_mfa_pending_token,
+    revoke_mfa_pending_token,
+    verify_totp,
+    hash_recovery_code,
 };
 use crate::models::auth::{
     LoginEmail,
@@ -36,16 +67,26 @@ use crate::models::{
     LoginError,
     ErrJson,
     bad_request,
+    email_identity,
 };
+use crate::email::{
+    VerifiedEmailAction,
+    check_email_verified,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
 use crate::AppState;
 
 /// 1. Login with JWT.
-/// Requires password and email to login, then creates a JsonWebToken
-/// for the client to use in their request headers so they don't need
-/// to re-login every time. This JWT expires in 30days.
+/// Requires password and email to login, then creates a short-lived JsonWebToken
+/// for the client to use in their request headers, and a refresh token
+/// which the client exchanges at /token/refresh for new JWTs, so they don't need
+/// to re-login every time. Refresh tokens expire in 30days.
 /// JWT authentication is only for users to read profile info,
 /// and non-critical updates.
 /// 2. Delete Profile and password change require password login to re-authenticate.
+/// 3. Users with 2FA enabled get an "mfa_pending" token after the password check,
+/// and exchange it with a TOTP or recovery code at /login/mfa for the JWT.
 
 ////////////////////////////
 //// REST API Login Handlers
@@ -60,59 +101,266 @@ pub async fn login_handler(
 
     let login = data.into_inner();
 
+    // 429 if there were too many wrong passwords for this email or IP
+    if let Err(e) = check_login_lockout(&req, &login.email).await {
+        audit(&req, AuditAction::Login)
+            .failed()
+            .payload(json!({ "method": "password", "email": email_identity(&login.email), "reason": "locked_out" }))
+            .record();
+        return Err(e)
+    }
+
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
     // requires password
-    let user: User = loginUser(
-        &conn,
-        login.email,
-        login.password
-    ).map_err(Error::from)?;
+    let user: User = match loginUser(&conn, login.email.clone(), login.password) {
+        Ok(user) => user,
+        Err(e) => {
+            // No actor, as whoever tried isn't known. The user id is set
+            // when the email belongs to an account, so it's in their log.
+            let mut event = audit(&req, AuditAction::Login)
+                .failed()
+                .payload(json!({ "method": "password", "email": email_identity(&login.email), "reason": "wrong_password" }));
+            if let Ok(account) = getUser(&conn, Some(&login.email), None) {
+                event = event.user(&account.id);
+            }
+            event.record();
+            record_login_failure(&req, &login.email).await?;
+            return Err(Error::from(e))
+        }
+    };
+
+    // Tells suspended users why, and until when
+    let user_id = user.id.clone();
+    let user = match checkSuspension(&conn, user) {
+        Ok(user) => user,
+        Err(e) => {
+            audit(&req, AuditAction::Login)
+                .actor(&user_id)
+                .user(&user_id)
+                .failed()
+                .payload(json!({ "method": "password", "reason": "suspended" }))
+                .record();
+            let _ = destroy_and_blacklist_jwt(req, id);
+            return Err(Error::from(e))
+        }
+    };
 
-    if user.is_suspended {
+    if user.is_deleted {
+        audit(&req, AuditAction::Login)
+            .actor(&user.id)
+            .user(&user.id)
+            .failed()
+            .payload(json!({ "method": "password", "reason": "deleted" }))
+            .record();
         let _ = destroy_and_blacklist_jwt(req, id);
         return Err(
-            LoginError::Suspended(ErrJson::new("User is suspended"))
+            LoginError::Suspended(ErrJson::new("User is deleted"))
         ).map_err(Error::from)
     }
 
+    check_email_verified(&user, VerifiedEmailAction::Login)?;
+
+    // Users with 2FA enabled need to enter a code at /login/mfa
+    // before they are given a session
+    // The lockout is only cleared once login is complete, so failed
+    // 2FA codes keep counting towards it
+    if let Some(mfa_response) = mfa_required_response(&conn, &user)? {
+        return Ok(mfa_response)
+    }
+    clear_account_lockout(&req, &login.email);
+
+    // Sets the JWT as HttpOnly cookie to pass to the client
+    let tokens = issue_token_pair(&req, &id, &user).await?;
+    debug!("login created jwt: {:?}", &tokens.jwt);
+
+    audit(&req, AuditAction::Login)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "method": "password" }))
+        .record();
+
+    Ok(login_response(user, tokens))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaLoginForm {
+    pub mfa_token: String,
+    /// 6 digit code from the user's authenticator app
+    pub code: Option<String>,
+    /// or one of their one-time recovery codes
+    pub recovery_code: Option<String>,
+}
+
+// POST /login/mfa
+// Second step of login for users with 2FA enabled
+pub async fn login_mfa_handler(
+    req: HttpRequest,
+    id: Identity,
+    data: Json<MfaLoginForm>
+) -> Result<HttpResponse, Error> {
+
+    let form = data.into_inner();
+
+    let claims = decode_claims::<Claims>(&form.mfa_token)
+        .ok()
+        .filter(|claims| claims.has_scope(MFA_PENDING_SCOPE))
+        .ok_or(Error::from(MfaError::InvalidMfaToken))?;
+
+    // Wrong codes count as failed logins, so new mfa_pending tokens
+    // don't give more guesses
+    check_login_lockout(&req, &claims.email).await?;
+
+    // mfa_pending tokens are revoked once used
+    let claims = AppState::databaseActor(&req)
+                .send(CheckJwt(claims))
+                .await?
+                .map_err(|_| Error::from(MfaError::InvalidMfaToken))?;
+
+    let attempts = AppState::databaseActor(&req)
+                .send(CountMfaAttempt(claims.clone()))
+                .await??;
+
+    if attempts > MFA_MAX_ATTEMPTS {
+        revoke_mfa_pending_token(&req, &claims);
+        return Err(Error::from(MfaError::TooManyAttempts))
+    }
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user: User = getUser(&conn, None, Some(&claims.sub))
+        .map_err(Error::from)?;
+
+    let user = match checkSuspension(&conn, user) {
+        Ok(user) => user,
+        Err(e) => {
+            revoke_mfa_pending_token(&req, &claims);
+            return Err(Error::from(e))
+        }
+    };
+
     if user.is_deleted {
-        let _ = destroy_and_blacklist_jwt(req, id);
+        revoke_mfa_pending_token(&req, &claims);
         return Err(
             LoginError::Suspended(ErrJson::new("User is deleted"))
         ).map_err(Error::from)
     }
 
-    let jwt = crate::auth::create_token(
-        user.email.clone(),
-        user.id.clone(),
-        user.user_role.clone(),
-    ).map_err(Error::from)?;
+    let method = match form.code {
+        Some(_) => "totp",
+        None => "recovery_code",
+    };
 
-    debug!("login created jwt: {:?}", &jwt);
-    // Set JWT as HttpOnly cookie to pass to the client
-    id.remember(jwt.clone());
+    let verified = match (form.code, form.recovery_code) {
+        (Some(code), _) => {
+            let totp = getTotp(&conn, &user.id)?
+                .filter(|totp| totp.enabled)
+                .ok_or(Error::from(MfaError::NotEnrolled))?;
 
-    Ok(HttpResponse::Ok()
+            match verify_totp(&totp.secret, &code, Local::now().timestamp()) {
+                // Reject codes which were already used
+                Some(step) => useTotpStep(&conn, &user.id, step)?,
+                None => false,
+            }
+        },
+        (None, Some(recovery_code)) => {
+            useRecoveryCode(&conn, &user.id, &hash_recovery_code(&recovery_code))?
+        },
+        (None, None) => {
+            return Err(Error::from(LoginError::BadRequest(
+                errJson!("code or recovery_code required"))))
+        }
+    };
+
+    if !verified {
+        audit(&req, AuditAction::Login)
+            .actor(&user.id)
+            .user(&user.id)
+            .failed()
+            .payload(json!({ "method": method, "reason": "invalid_code" }))
+            .record();
+        record_login_failure(&req, &user.email).await?;
+        return Err(Error::from(MfaError::InvalidCode))
+    }
+
+    revoke_mfa_pending_token(&req, &claims);
+    clear_account_lockout(&req, &user.email);
+
+    // Sets the JWT as HttpOnly cookie to pass to the client
+    let tokens = issue_token_pair(&req, &id, &user).await?;
+
+    audit(&req, AuditAction::Login)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "method": method }))
+        .record();
+
+    Ok(login_response(user, tokens))
+}
+
+/// For users with 2FA enabled, the "mfa_pending" response to send
+/// instead of a session. None if the user can be logged in directly.
+pub fn mfa_required_response(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    user: &User,
+) -> Result<Option<HttpResponse>, Error> {
+
+    let totp_enabled = getTotp(conn, &user.id)?
+        .map(|totp| totp.enabled)
+        .unwrap_or(false);
+
+    if !totp_enabled {
+        return Ok(None)
+    }
+
+    let mfa_token = create_mfa_pending_token(user)
+        .map_err(Error::from)?;
+
+    Ok(Some(HttpResponse::Ok()
         .content_type("application_json")
         .json(json!({
-            "user": user,
-            "jwt": jwt,
-        })
+            "mfaRequired": true,
+            "mfaToken": mfa_token,
+            "mfaMethods": ["totp", "recovery_code"],
+        }))
     ))
 }
 
+/// JWT, refresh token and user returned by every login method
+pub fn login_response(user: User, tokens: TokenPair) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application_json")
+        .cookie(refresh_cookie(&tokens.refresh_token))
+        .json(json!({
+            "user": user,
+            "jwt": tokens.jwt,
+            "refreshToken": tokens.refresh_token,
+            "expiresIn": tokens.expires_in,
+        }))
+}
+
 // DELETE /logout
 pub fn logout_handler(
     req: HttpRequest,
     id: Identity
 ) -> HttpResponse {
 
+    if let Some(claims) = request_jwt(&req).and_then(|jwt| decode_claims::<Claims>(&jwt).ok()) {
+        audit(&req, AuditAction::Logout)
+            .by(&AuthInfo::from(claims))
+            .record();
+    }
+
     let _ = destroy_and_blacklist_jwt(req, id);
 
-    HttpResponse::Ok().json(json!({
+    HttpResponse::Ok()
+    .cookie(removal_refresh_cookie())
+    .json(json!({
         "status": "logged out successfully.",
     }))
 }
@@ -121,16 +369,32 @@ pub fn destroy_and_blacklist_jwt(
     req: HttpRequest,
     id: Identity
 ) -> () {
-    if let Some(jwt) = id.identity() {
-        // Revoke JWT in redis
-        AppState::from(&req).redis_actor
-        .do_send(RedisCommand::Setex(
-            Setex {
-                key: jwt,
-                ttl: 86_400 * 30, // JWT lifetimes are 1 day, do for 30 days
-                value: String::from("REVOKED")
+    if let Some(jwt) = request_jwt(&req) {
+        // Revoke JWT ID in redis until the token expires.
+        // Expired or invalid tokens don't need revoking.
+        if let Ok(claims) = decode_claims::<Claims>(&jwt) {
+            AppState::from(&req).redis_actor
+            .do_send(RedisCommand::Setex(
+                Setex {
+                    key: revoked_jti_key(&claims.jti),
+                    ttl: std::cmp::max(1, claims.remaining_lifetime()) as i32,
+                    value: String::from("REVOKED")
+                }
+            ));
+            // End the session too, for clients without the refresh cookie
+            if let Some(session_id) = claims.sid {
+                AppState::databaseActor(&req)
+                    .do_send(RevokeSession {
+                        user_id: claims.sub,
+                        session_id: session_id,
+                    });
             }
-        ));
+        }
+    }
+    // Revoke the refresh token family of this session
+    if let Some(refresh_token) = current_refresh_token(&req) {
+        AppState::databaseActor(&req)
+            .do_send(RevokeRefreshToken(refresh_token));
     }
     // remove JWT as HttpOnly session cookie
     id.forget();
@@ -144,27 +408,24 @@ pub struct PasswordCheckBody {
 // POST /check/password
 pub async fn check_password_handler(
     req: HttpRequest,
-    id: Identity,
-    json: Json<PasswordCheckBody>
+    json: Json<PasswordCheckBody>,
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     let json = json.into_inner();
 
-    let jwt = id.identity()
-        .ok_or(Error::from(noJwtError!()))?;
-
-    let authInfo: AuthInfo = decode_token(&jwt)
-        .map_err(Error::from)?;
+    check_login_lockout(&req, &authInfo.email).await?;
 
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
     // requires password
-    let _user: User = checkPasswordForUserId(&conn,
-        authInfo.user_id,
-        json.password,
-    ).map_err(Error::from)?;
+    if let Err(e) = checkPasswordForUserId(&conn, authInfo.user_id, json.password) {
+        record_login_failure(&req, &authInfo.email).await?;
+        return Err(Error::from(e))
+    }
+    clear_account_lockout(&req, &authInfo.email);
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
@@ -172,4 +433,56 @@ pub async fn check_password_handler(
             "password_matches": true,
         })
     ))
-}
\ No newline at end of file
+}
+
+#[actix_rt::test]
+async fn locked_out_accounts_cant_guess_codes_with_new_mfa_tokens() {
+    // Needs postgres, redis and the JWT keys, like db::tests
+    use actix::{Actor, SyncArbiter};
+    use actix_web::{test, http::StatusCode, dev::Payload, FromRequest};
+    use crate::auth::{ACCOUNT_LOCKOUT, clear_ip_lockout};
+    use crate::db::DatabaseActor;
+    use crate::notify_client::NotifyActor;
+    use crate::redis_client::RedisActor;
+
+    let database_actor = SyncArbiter::start(1, || DatabaseActor::new(
+        dt::db::create_postgres_pool("DATABASE_URL", 1)
+    ));
+    let redis_actor = RedisActor::new().start();
+    let notify_actor = NotifyActor::new().start();
+    let request = || test::TestRequest::post()
+        .uri("/login/mfa")
+        .peer_addr("203.0.113.9:4000".parse().unwrap())
+        .app_data(AppState {
+            database_actor: database_actor.clone(),
+            http_client: AppState::create_client(),
+            redis_actor: redis_actor.clone(),
+            notify_actor: notify_actor.clone(),
+        })
+        .to_http_request();
+
+    let user = User::new(
+        String::from("mfa.lockout@hogwarts.com"),
+        String::from("password"),
+        None,
+        None,
+    );
+    // Wrong codes on earlier mfa_pending tokens
+    for _ in 0..ACCOUNT_LOCKOUT.lockout_after {
+        record_login_failure(&request(), &user.email).await.unwrap();
+    }
+
+    // A new token from logging in again with the password doesn't help
+    let form = MfaLoginForm {
+        mfa_token: create_mfa_pending_token(&user).unwrap(),
+        code: Some(String::from("123456")),
+        recovery_code: None,
+    };
+    let req = request();
+    let id = Identity::from_request(&req, &mut Payload::None).await.unwrap();
+    let err = login_mfa_handler(req, id, Json(form)).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    clear_account_lockout(&request(), &user.email);
+    clear_ip_lockout(&request(), "203.0.113.9");
+}
@@ -0,0 +1,186 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use actix_identity::{Identity};
+use chrono::{Local, Duration};
+
+use crate::db::{
+    GetPool,
+    getUser,
+    setEmailVerified,
+    checkSuspension,
+};
+use crate::auth::{
+    issue_token_pair,
+    client_ip,
+    // Passwordless login
+    MagicLinkError,
+    CountMagicLinkRequest,
+    CreateMagicLogin,
+    RedeemMagicLogin,
+    MAGIC_LINK_TTL_SECONDS,
+};
+use crate::models::{
+    User,
+    LoginError,
+    ErrJson,
+};
+use crate::notify_client::NotifyMessage;
+use crate::rest::{login_response, mfa_required_response};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Passwordless login:
+/// 1. /login/magic/send emails a single-use login link and 6 digit code
+/// 2. /login/magic/redeem exchanges the link token (or email + code) for the
+///    same JWT and refresh token as /login. Users with 2FA enabled get an
+///    "mfa_pending" token instead, and finish at /login/mfa.
+///
+/// See `auth::magic_link`.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLinkSendForm {
+    pub email: String,
+}
+
+// POST /login/magic/send
+pub async fn send_magic_link_handler(
+    req: HttpRequest,
+    json: Json<MagicLinkSendForm>,
+) -> Result<HttpResponse, Error> {
+
+    let form = json.into_inner();
+
+    // Counted before looking up the user, so unknown emails
+    // are rate limited the same way
+    let allowed = AppState::databaseActor(&req)
+                .send(CountMagicLinkRequest {
+                    email: form.email.clone(),
+                    ip: client_ip(&req),
+                })
+                .await??;
+
+    if !allowed {
+        return Err(Error::from(MagicLinkError::RateLimited))
+    }
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    match getUser(&conn, Some(&form.email), None) {
+        Ok(user) if !user.is_suspended && !user.is_deleted => {
+
+            let magic_login = AppState::databaseActor(&req)
+                        .send(CreateMagicLogin {
+                            user_id: user.id.clone(),
+                            email: user.email.clone(),
+                        })
+                        .await??;
+
+            // Not awaited, so the response time doesn't show
+            // whether an email was sent
+            AppState::notifyActor(&req)
+                .do_send(NotifyMessage::SendMagicLinkEmail(
+                    user.email.clone(),
+                    magic_login.token,
+                    magic_login.code,
+                    Local::now().naive_utc() + Duration::seconds(MAGIC_LINK_TTL_SECONDS),
+                ));
+        },
+        _ => {
+            debug!("magic link not sent, no active account for: {}", form.email);
+        },
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "If an account exists for this email, a login link has been sent.",
+            "expiresIn": MAGIC_LINK_TTL_SECONDS,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLinkRedeemForm {
+    /// token from the login link
+    pub token: Option<String>,
+    /// or the email and 6 digit code
+    pub email: Option<String>,
+    pub code: Option<String>,
+}
+
+// POST /login/magic/redeem
+pub async fn redeem_magic_link_handler(
+    req: HttpRequest,
+    id: Identity,
+    json: Json<MagicLinkRedeemForm>,
+) -> Result<HttpResponse, Error> {
+
+    let form = json.into_inner();
+
+    let redeem = match (form.token, form.email, form.code) {
+        (Some(token), _, _) => RedeemMagicLogin::Token(token),
+        (None, Some(email), Some(code)) => RedeemMagicLogin::Code {
+            email: email,
+            code: code,
+        },
+        _ => {
+            return Err(Error::from(LoginError::BadRequest(
+                errJson!("token, or email and code required"))))
+        }
+    };
+
+    let record = AppState::databaseActor(&req)
+                .send(redeem)
+                .await??;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user: User = getUser(&conn, None, Some(&record.user_id))
+        .map_err(|_| Error::from(MagicLinkError::InvalidToken))?;
+
+    // Sent before an email change, it doesn't prove access to the
+    // current email, so can't verify it or log in
+    if !record.matches_user(&user) {
+        return Err(Error::from(MagicLinkError::InvalidToken))
+    }
+
+    let user = checkSuspension(&conn, user)?;
+
+    if user.is_deleted {
+        return Err(
+            LoginError::Suspended(ErrJson::new("User is deleted"))
+        ).map_err(Error::from)
+    }
+
+    // The link was sent to their email, so this also verifies it
+    let user = if user.email_verified {
+        user
+    } else {
+        setEmailVerified(&conn, user.email.clone(), true)?
+    };
+
+    // A login link only proves access to the email account,
+    // so it doesn't skip 2FA
+    if let Some(mfa_response) = mfa_required_response(&conn, &user)? {
+        return Ok(mfa_response)
+    }
+
+    let tokens = issue_token_pair(&req, &id, &user).await?;
+
+    audit(&req, AuditAction::Login)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "method": "magic_link" }))
+        .record();
+
+    Ok(login_response(user, tokens))
+}
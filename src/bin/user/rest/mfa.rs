@@ -0,0 +1,203 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use chrono::Local;
+
+use crate::db::{
+    GetPool,
+    checkPasswordForUserId,
+    getTotp,
+    insertPendingTotp,
+    enableTotp,
+    deleteTotp,
+    countUnusedRecoveryCodes,
+};
+use crate::auth::{
+    AuthInfo,
+    MfaError,
+    generate_totp_secret,
+    generate_recovery_codes,
+    hash_recovery_code,
+    otpauth_uri,
+    totp_issuer,
+    verify_totp,
+};
+use crate::models::User;
+use crate::email::{
+    VerifiedEmailAction,
+    require_verified_email,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Two-factor authentication (TOTP) enrolment:
+/// 1. /auth/mfa/totp/enroll returns a new secret and otpauth:// URI for
+///    the user's authenticator app (usually shown as a QR code).
+/// 2. /auth/mfa/totp/verify checks the first code from the app, enables 2FA
+///    and returns one-time recovery codes. They are only shown this once.
+/// 3. /auth/mfa/totp/disable requires the user's password.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpCodeForm {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisableTotpForm {
+    pub password: String,
+}
+
+// GET /auth/mfa
+pub async fn get_mfa_status_handler(
+    req: HttpRequest,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let totp_enabled = getTotp(&conn, &authInfo.user_id)?
+        .map(|totp| totp.enabled)
+        .unwrap_or(false);
+
+    let recovery_codes_remaining = match totp_enabled {
+        true => countUnusedRecoveryCodes(&conn, &authInfo.user_id)?,
+        false => 0,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "totpEnabled": totp_enabled,
+            "recoveryCodesRemaining": recovery_codes_remaining,
+        })))
+}
+
+// POST /auth/mfa/totp/enroll
+pub async fn enroll_totp_handler(
+    req: HttpRequest,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    require_verified_email(&conn, &authInfo.user_id, VerifiedEmailAction::MfaEnroll)?;
+
+    if let Some(totp) = getTotp(&conn, &authInfo.user_id)? {
+        if totp.enabled {
+            return Err(Error::from(MfaError::AlreadyEnabled))
+        }
+    }
+
+    let secret = generate_totp_secret()
+        .ok_or(Error::from(MfaError::Internal(String::from("Could not generate TOTP secret"))))?;
+
+    let totp = insertPendingTotp(&conn, &authInfo.user_id, &secret)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .header("Cache-Control", "no-store")
+        .json(json!({
+            "secret": totp.secret,
+            "otpauthUri": otpauth_uri(&totp_issuer(), &authInfo.email, &totp.secret),
+        })))
+}
+
+// POST /auth/mfa/totp/verify
+pub async fn verify_totp_handler(
+    req: HttpRequest,
+    json: Json<TotpCodeForm>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let form = json.into_inner();
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let totp = getTotp(&conn, &authInfo.user_id)?
+        .ok_or(Error::from(MfaError::NotEnrolled))?;
+
+    if totp.enabled {
+        return Err(Error::from(MfaError::AlreadyEnabled))
+    }
+
+    let step = verify_totp(&totp.secret, &form.code, Local::now().timestamp())
+        .ok_or(Error::from(MfaError::InvalidCode))?;
+
+    let recovery_codes = generate_recovery_codes()
+        .ok_or(Error::from(MfaError::Internal(String::from("Could not generate recovery codes"))))?;
+
+    enableTotp(
+        &conn,
+        &authInfo.user_id,
+        step,
+        recovery_codes.iter().map(|c| hash_recovery_code(c)).collect(),
+    )?;
+
+    info!("2FA enabled for user: {}", authInfo.user_id);
+    audit(&req, AuditAction::MfaEnroll)
+        .by(&authInfo)
+        .payload(json!({ "method": "totp" }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .header("Cache-Control", "no-store")
+        .json(json!({
+            "totpEnabled": true,
+            "recoveryCodes": recovery_codes,
+        })))
+}
+
+// POST /auth/mfa/totp/disable
+pub async fn disable_totp_handler(
+    req: HttpRequest,
+    json: Json<DisableTotpForm>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let form = json.into_inner();
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    // requires password
+    if let Err(e) = checkPasswordForUserId(&conn, authInfo.user_id.clone(), form.password) {
+        audit(&req, AuditAction::MfaDisable)
+            .by(&authInfo)
+            .failed()
+            .payload(json!({ "method": "totp", "reason": "wrong_password" }))
+            .record();
+        return Err(Error::from(e))
+    }
+
+    deleteTotp(&conn, &authInfo.user_id)?;
+
+    info!("2FA disabled for user: {}", authInfo.user_id);
+    audit(&req, AuditAction::MfaDisable)
+        .by(&authInfo)
+        .payload(json!({ "method": "totp" }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "totpEnabled": false,
+        })))
+}
@@ -1,14 +1,40 @@
+pub mod account_deletion;
 pub mod login;
+pub mod magic_link;
 pub mod forgot_password;
 pub mod profile;
 pub mod registration;
 pub mod health;
+pub mod token;
+pub mod jwks;
+pub mod introspect;
+pub mod mfa;
+pub mod webauthn;
+pub mod verify_email;
+pub mod email_change;
+pub mod sessions;
+pub mod impersonate;
+pub mod audit;
+pub mod data_export;
 
+pub use account_deletion::*;
 pub use login::*;
+pub use magic_link::*;
 pub use forgot_password::*;
 pub use profile::*;
 pub use registration::*;
 pub use health::*;
+pub use token::*;
+pub use jwks::*;
+pub use introspect::*;
+pub use mfa::*;
+pub use webauthn::*;
+pub use verify_email::*;
+pub use email_change::*;
+pub use sessions::*;
+pub use impersonate::*;
+pub use audit::*;
+pub use data_export::*;
 
 ///////////////////////////////////////
 
@@ -15,10 +15,13 @@ use crate::db::{
     getUser,
     updateUser,
     deleteUser,
+    getRestorableAccountDeletion,
     setEmailVerified,
-    setSuspended,
+    suspendUser,
+    unsuspendUser,
     setNewPassword,
     getUsersByIds,
+    searchUsers,
 };
 use crate::db::{
     GetPool, GetPoolError,
@@ -28,9 +31,22 @@ use crate::redis_client::{
 };
 use crate::auth::{
     AuthInfo,
+    Permission,
+    ServiceAuth,
     // CheckJwt Actor Message
     CheckJwt, CheckJwtError,
+    RevokeUserTokens,
+    RevokeSessions,
+    Claims,
     decode_token,
+    decode_claims,
+    verify_jwt,
+    issue_token_pair,
+    refresh_cookie,
+    current_refresh_token,
+    RevokeRefreshToken,
+    clear_account_lockout,
+    clear_ip_lockout,
 };
 use crate::models::auth::{
     LoginEmail,
@@ -47,7 +63,19 @@ use crate::models::{
     LoginError,
     ErrJson,
     AuthError,
+    email_identity,
+    UsersConnectionQuery,
+    NewSuspension,
+    SuspensionReason,
 };
+use crate::email::{
+    VerifiedEmailAction,
+    require_verified_email,
+};
+use crate::notify_client::NotifyMessage;
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::data_export::DeleteDataExports;
 use crate::AppState;
 use crate::rpc;
 use crate::rest::destroy_and_blacklist_jwt;
@@ -69,22 +97,34 @@ pub async fn get_user_handler(
                 .await??;
 
     let user = getUser(&conn, None, Some(&user_id))
-        .map_err(Error::from);
+        .map_err(Error::from)?;
 
     // filter public fields with UserPublic
     Ok(HttpResponse::Ok()
         .content_type("application_json")
-        .json(user?))
+        .json(UserPublic::from(user)))
 
 }
 
 
 // GET /user/get/by/email?user_email=email@domain
+// Service credentials, or a JWT with the users:read_private permission required,
+// otherwise this could be used to find out who has an account.
 pub async fn get_user_by_email_handler(
     req: HttpRequest,
     query: Query<QueryUserEmail>,
+    auth_info: Option<AuthInfo>,
 ) -> Result<HttpResponse, Error> {
 
+    if ServiceAuth::from_request_headers(&req).is_err() {
+        match auth_info {
+            Some(a) => a.require_permission(Permission::UsersReadPrivate)?,
+            None => return Err(Error::from(
+                LoginError::Unauthorized(errJson!("Service or admin credentials required"))
+            )),
+        }
+    }
+
     // Retrieves user profiles by email
     let user_email = query.user_email.clone();
 
@@ -93,12 +133,12 @@ pub async fn get_user_by_email_handler(
                 .await??;
 
     let user = getUser(&conn, Some(&user_email), None)
-        .map_err(Error::from);
+        .map_err(Error::from)?;
 
     // filter public fields with UserPublic
     Ok(HttpResponse::Ok()
         .content_type("application_json")
-        .json(user?))
+        .json(UserPublic::from(user)))
 
 }
 
@@ -107,60 +147,22 @@ pub async fn get_user_by_email_handler(
 // JWT required for this route
 pub async fn get_profile_handler(
     req: HttpRequest,
-    id: Identity,
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     debug!("Incoming request: {:?}", req);
-    // Notes: async fetches DBPool and redis JWT check at the same time.
-    // If JWT is not in blacklist, proceed with getUser DB call.
-    let jwt = id.identity()
-        .ok_or(Error::from(noJwtError!()))?;
-
-    let authInfo: AuthInfo = decode_token(&jwt)
-        .map_err(Error::from)?;
-
-    // Retreive DB pool thread asynchronously
+    // AuthInfo extractor has checked the JWT is not in the blacklist,
+    // and the user is not suspended or deleted.
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
-
-    // Check if JWT exists in blacklist, return early if so.
-    let res = AppState::databaseActor(&req)
-                .send(CheckJwt(jwt))
-                .await?;
-
-    match res {
-        Err(e) => Err(Error::from(e)),
-        Ok(_) => {
-
-            let user = match getUser(&conn, None, Some(&authInfo.user_id)) {
-                Err(e) => return Err(Error::from(e)),
-                Ok(u) => u,
-            };
-
-            if user.is_suspended {
-                let _ = destroy_and_blacklist_jwt(req, id);
-                return Err(
-                    LoginError::Suspended(ErrJson::new("User is suspended"))
-                ).map_err(Error::from)
-            }
-
-            if user.is_deleted {
-                let _ = destroy_and_blacklist_jwt(req, id);
-                return Err(
-                    LoginError::Suspended(ErrJson::new("User is deleted"))
-                ).map_err(Error::from)
-            }
-
-            Ok(HttpResponse::Ok()
-            .content_type("application_json")
-            .json(
-                // Return HttpResponse with user data
-                getUser(&conn, None, Some(&authInfo.user_id))?
-            ))
-        }
-    }
+    Ok(HttpResponse::Ok()
+    .content_type("application_json")
+    .json(
+        // Return HttpResponse with user data
+        getUser(&conn, None, Some(&authInfo.user_id))?
+    ))
 }
 
 
@@ -174,110 +176,87 @@ pub struct UsersByIdsBody {
 pub async fn get_users_by_ids(
     req: HttpRequest,
     json: Json<UsersByIdsBody>,
-    id: Identity,
+    auth_info: Option<AuthInfo>,
 ) -> Result<HttpResponse, Error> {
 
     // Retrieves public user profiles by storeIds
     let body = json.into_inner();
 
-    let auth_info = match id.identity() {
-        None => None,
-        Some(jwt) => match decode_token::<AuthInfo>(&jwt) {
-            Err(e) => return Err(Error::from(e)),
-            Ok(auth_info) => match auth_info.user_role {
-                UserRole::PLATFORM_ADMIN => Some(auth_info),
-                _ => None
-            }
-        },
-    };
-
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    match auth_info {
-        Some(a) => match a.user_role {
-
-            UserRole::PLATFORM_ADMIN => {
-                let users: Vec<User> = getUsersByIds(&conn, body.user_ids)
-                    .map_err(Error::from)?;
-
-                Ok(HttpResponse::Ok()
-                .content_type("application_json")
-                .json(json!({
-                    "userRole": a.user_role,
-                    "userType": "UserPrivate",
-                    "users": users,
-                })))
-            },
-            _ => {
-                let users: Vec<UserPublic> = getUsersByIds(&conn, body.user_ids)
-                    .map_err(Error::from)?
-                    .into_iter()
-                    .map(UserPublic::from)
-                    .collect::<Vec<UserPublic>>();
-
-                Ok(HttpResponse::Ok()
-                .content_type("application_json")
-                .json(json!({
-                    "userRole": a.user_role,
-                    "userType": "UserPublic",
-                    "users": users,
-                })))
-            }
-        },
-        None => {
-            let users: Vec<UserPublic> = getUsersByIds(&conn, body.user_ids)
-                .map_err(Error::from)?
-                .into_iter()
-                .map(UserPublic::from)
-                .collect::<Vec<UserPublic>>();
-
-            Ok(HttpResponse::Ok()
-            .content_type("application_json")
-            .json(json!({
-                "userRole": "",
-                "userType": "UserPublic",
-                "users": users,
-            })))
-        }
-    }
-}
+    let user_role = auth_info.as_ref()
+        .map(|a| json!(a.user_role))
+        .unwrap_or(json!(""));
 
+    let can_read_private = auth_info.as_ref()
+        .map(|a| a.has_permission(Permission::UsersReadPrivate))
+        .unwrap_or(false);
 
+    if can_read_private {
+        let users: Vec<User> = getUsersByIds(&conn, body.user_ids)
+            .map_err(Error::from)?;
 
-// GET /auth/id
-// JWT required for this route
-pub async fn get_id_from_set_cookie(
+        Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "userRole": user_role,
+            "userType": "UserPrivate",
+            "users": users,
+        })))
+    } else {
+        let users: Vec<UserPublic> = getUsersByIds(&conn, body.user_ids)
+            .map_err(Error::from)?
+            .into_iter()
+            .map(UserPublic::from)
+            .collect::<Vec<UserPublic>>();
+
+        Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "userRole": user_role,
+            "userType": "UserPublic",
+            "users": users,
+        })))
+    }
+}
+
+// GET /admin/users?search=&role=&isSuspended=&isDeleted=&emailVerified=
+//      &createdAfter=&createdBefore=&sortBy=&sortAscending=&cursor=&pageBackwards=&count=
+// Permission "users:list" required for this route
+pub async fn admin_list_users_handler(
     req: HttpRequest,
-    id: Identity,
+    query: Query<UsersConnectionQuery>,
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
-    // debug!("Incoming request: {:?}", req);
-    let jwt = match id.identity() {
-        None => return Err(Error::from(noJwtError!())),
-        Some(jwt) => jwt,
-    };
-    let auth_info: AuthInfo = decode_token(&jwt)
+
+    debug!("users searched by {}: {:?}", authInfo.user_id, query);
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let connection = searchUsers(&conn, &query.into_inner())
         .map_err(Error::from)?;
-    // Check if JWT exists in blacklist, return early with error if so.
-    let _check_jwt = AppState::databaseActor(&req)
-                .send(CheckJwt(jwt))
-                .await?;
 
-    // let conn = AppState::databaseActor(&req)
-    //             .send(GetPool::Postgres)
-    //             .await??;
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(connection))
+}
+
 
-    // // lookup user cartId and storeId, deprecate storeId and cartId in cookie
-    // let user: User = getUser(&conn, None, Some(&auth_info.user_id))
-    //     .map_err(Error::from)?;
 
+// GET /auth/id
+// JWT required for this route
+pub async fn get_id_from_set_cookie(
+    auth_info: AuthInfo,
+) -> Result<HttpResponse, Error> {
+    // AuthInfo extractor returns early with error if the JWT is revoked,
+    // or the user is suspended or deleted.
     Ok(HttpResponse::Ok()
         .content_type("application_json")
-        .json(
-            // AuthInfo::from(user)
-            auth_info
-        )) // authInfo serializes as camelCase
+        .json(auth_info)) // authInfo serializes as camelCase
 }
 
 
@@ -285,47 +264,54 @@ pub async fn get_id_from_set_cookie(
 pub async fn update_profile_handler(
     req: HttpRequest,
     data: Json<UpdateUserProfile>,
-    id: Identity
+    id: Identity,
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     // debug!("Incoming request: {:?}", req);
+    // This issues a new session, which would be a full login as the user
+    authInfo.forbid_impersonation()?;
+
     let profile = data.into_inner();
     info!("profile: {:?}", profile);
 
-    let authInfo: AuthInfo = match id.identity() {
-        None => return Err(Error::from(noJwtError!())),
-        Some(jwt) => match decode_token(&jwt) {
-            Err(e) => return Err(Error::from(e)),
-            Ok(auth_info) => auth_info,
-        },
-    };
     info!("authInfo: {:?}", authInfo);
 
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
+    require_verified_email(&conn, &authInfo.user_id, VerifiedEmailAction::ProfileUpdate)?;
+
+    // Email changes need to be confirmed, see /auth/profile/changeEmail
+    if let Some(email) = &profile.email {
+        let current_email = getUser(&conn, None, Some(&authInfo.user_id))?.email;
+        if email_identity(email) != email_identity(&current_email) {
+            return Err(Error::from(LoginError::BadRequest(
+                errJson!("Use /auth/profile/changeEmail to change your email")
+            )))
+        }
+    }
+
     let updated_user: User = updateUser(
         &conn,
         authInfo.user_id,
-        profile.email,
         profile.first_name,
         profile.last_name,
     ).map_err(Error::from)?;
     debug!("updated_user: {:?}", updated_user);
 
-    let jwt = crate::auth::create_token(
-        updated_user.email.clone(),
-        updated_user.id.clone(),
-        updated_user.user_role.clone(),
-    ).map_err(Error::from)?;
-
-    // debug!("updated jwt: {:?}", &jwt);
+    // Replace the old session's refresh tokens with a new pair
+    if let Some(refresh_token) = current_refresh_token(&req) {
+        AppState::databaseActor(&req)
+            .do_send(RevokeRefreshToken(refresh_token));
+    }
     // Set JWT as HttpOnly cookie to pass to the client
-    id.remember(jwt);
+    let tokens = issue_token_pair(&req, &id, &updated_user).await?;
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
+        .cookie(refresh_cookie(&tokens.refresh_token))
         .json(updated_user))
 }
 
@@ -339,36 +325,57 @@ pub struct ChangePassword {
 pub async fn change_password_handler(
     req: HttpRequest,
     data: Json<ChangePassword>,
-    id: Identity
+    id: Identity,
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     // debug!("Incoming request: {:?}", req);
     let password_reset = data.into_inner();
     // debug!("password_reset request: {:?}", password_reset);
 
-    let authInfo: AuthInfo = match id.identity() {
-        None => return Err(Error::from(noJwtError!())),
-        Some(jwt) => match decode_token(&jwt) {
-            Err(e) => return Err(Error::from(e)),
-            Ok(auth_info) => auth_info,
-        },
-    };
     info!("authInfo: {:?}", authInfo);
+    authInfo.forbid_impersonation()?;
 
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    let updated_user: User = setNewPassword(
+    require_verified_email(&conn, &authInfo.user_id, VerifiedEmailAction::PasswordChange)?;
+
+    let updated_user: User = match setNewPassword(
         &conn,
         &authInfo.user_id,
         &password_reset.current_password,
         &password_reset.new_password,
-    ).map_err(Error::from)?;
+    ) {
+        Ok(user) => user,
+        Err(e) => {
+            audit(&req, AuditAction::PasswordChange)
+                .by(&authInfo)
+                .failed()
+                .record();
+            return Err(Error::from(e))
+        }
+    };
     // debug!("updated user password: {:?}", updated_user);
 
+    // Sign out every session issued with the old password,
+    // then keep this session logged in with a new token pair.
+    AppState::databaseActor(&req)
+        .send(RevokeUserTokens(updated_user.id.clone()))
+        .await??;
+    let tokens = issue_token_pair(&req, &id, &updated_user).await?;
+
+    AppState::notifyActor(&req)
+        .do_send(NotifyMessage::SendPasswordChangedEmail(updated_user.email.clone()));
+
+    audit(&req, AuditAction::PasswordChange)
+        .by(&authInfo)
+        .record();
+
     Ok(HttpResponse::Ok()
         .content_type("application_json")
+        .cookie(refresh_cookie(&tokens.refresh_token))
         .json(updated_user))
 }
 
@@ -377,31 +384,55 @@ pub async fn change_password_handler(
 pub async fn delete_profile_handler(
     req: HttpRequest,
     json: Json<DeleteUserForm>,
-    id: Identity
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     // debug!("Incoming request: {:?}", req);
 
     let password = json.into_inner().password;
-    let authInfo: AuthInfo = match id.identity() {
-        None => return Err(Error::from(noJwtError!())),
-        Some(jwt) => match decode_token(&jwt) {
-            Err(e) => return Err(Error::from(e)),
-            Ok(auth_info) => auth_info,
-        },
-    };
     info!("authInfo: {:?}", authInfo);
+    authInfo.forbid_impersonation()?;
 
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
 
-    let res = deleteUser(
-        &conn,
-        &authInfo.user_id,
-        password,
-    )?;
+    let res = match deleteUser(&conn, &authInfo.user_id, password) {
+        Ok(res) => res,
+        Err(e) => {
+            audit(&req, AuditAction::AccountDelete)
+                .by(&authInfo)
+                .failed()
+                .record();
+            return Err(Error::from(e))
+        }
+    };
+
+    // Signed out everywhere, the account can only be restored at /account/restore
+    AppState::databaseActor(&req)
+        .send(RevokeUserTokens(authInfo.user_id.clone()))
+        .await??;
+    AppState::databaseActor(&req)
+        .send(RevokeSessions {
+            user_id: authInfo.user_id.clone(),
+            keep: None,
+        })
+        .await??;
+    // Exports are a copy of the user's data. They expire in a day anyway,
+    // so the deletion still goes ahead if this fails.
+    if let Err(e) = AppState::databaseActor(&req)
+        .send(DeleteDataExports(authInfo.user_id.clone()))
+        .await? {
+        warn!("data exports not deleted for user {}: {}", authInfo.user_id, e);
+    }
+
+    let deletion = getRestorableAccountDeletion(&conn, &authInfo.user_id)?;
+
+    audit(&req, AuditAction::AccountDelete)
+        .by(&authInfo)
+        .payload(json!({ "deletionId": deletion.as_ref().map(|d| &d.id) }))
+        .record();
 
     // // Ask shopping service to delete things owned by this user
     // let user_delete_res = rpc::rpc_delete_user_shopping(
@@ -411,33 +442,48 @@ pub async fn delete_profile_handler(
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
-        .json(res))
+        .json(json!({
+            "status": res,
+            // Restorable until then
+            "eraseAfter": deletion.map(|d| d.erase_after),
+        })))
 }
 
 
-// POST /auth/profile/suspendUser?user_id=
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspendUserQuery {
+    pub user_id: String,
+    /// Shown to the user, defaults to OTHER
+    pub reason: Option<SuspensionReason>,
+    /// For admins only
+    pub note: Option<String>,
+    /// Lifted automatically at this time, e.g. 2020-08-01T00:00:00 (UTC).
+    /// Otherwise until an admin lifts it.
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+// POST /auth/profile/suspendUser?user_id=&reason=&note=&until=
+// Permission "users:suspend" required for this route
 pub async fn suspend_user_handler(
     req: HttpRequest,
-    query: Query<QueryUserId>,
-    id: Identity
+    query: Query<SuspendUserQuery>,
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     // debug!("Incoming request: {:?}", req);
-    let user_id = query.user_id.clone();
+    let query = query.into_inner();
 
-    let authInfo: AuthInfo = match id.identity() {
-        None => return Err(Error::from(noJwtError!())),
-        Some(jwt) => match decode_token(&jwt) {
-            Err(e) => return Err(Error::from(e)),
-            Ok(auth_info) => auth_info,
-        },
-    };
     info!("authInfo: {:?}", authInfo);
 
-    if authInfo.user_role != UserRole::PLATFORM_ADMIN {
-        return Err(Error::from(
-                LoginError::CredentialsError(
-                    errJson!("Not an admin, can't suspend a user"))))
+    if query.user_id == authInfo.user_id {
+        return Err(Error::from(LoginError::BadRequest(
+            errJson!("Can't suspend yourself"))))
+    }
+    if let Some(until) = query.until {
+        if until <= chrono::Local::now().naive_utc() {
+            return Err(Error::from(LoginError::BadRequest(
+                errJson!("until must be in the future"))))
+        }
     }
 
     // send message to DB actor to retrieve pool.
@@ -446,51 +492,156 @@ pub async fn suspend_user_handler(
                 .send(GetPool::Postgres)
                 .await??;
 
-    let user = setSuspended(&conn, user_id, true)
-        .map_err(Error::from)?;
+    let (user, suspension) = suspendUser(&conn, NewSuspension {
+        id: format!("sus_{}", uuid::Uuid::new_v4()),
+        user_id: query.user_id,
+        reason: query.reason.unwrap_or(SuspensionReason::Other).as_str().to_string(),
+        note: query.note,
+        admin_id: authInfo.user_id.clone(),
+        until: query.until,
+    }).map_err(Error::from)?;
+
+    // Sign the user out of every session now: revoke their tokens,
+    // and end their sessions so refresh tokens stop working too
+    AppState::databaseActor(&req)
+        .send(RevokeUserTokens(user.id.clone()))
+        .await??;
+    AppState::databaseActor(&req)
+        .send(RevokeSessions {
+            user_id: user.id.clone(),
+            keep: None,
+        })
+        .await??;
 
     debug!("user suspended: {:?}", user.email);
+    audit(&req, AuditAction::Suspend)
+        .by(&authInfo)
+        .user(&user.id)
+        .payload(json!({
+            "suspensionId": suspension.id,
+            "reason": suspension.reason,
+            "until": suspension.until,
+        }))
+        .record();
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
-        .json(user))
+        .json(json!({
+            "user": user,
+            "suspension": suspension,
+        })))
 }
 
 // POST /auth/profile/unsuspendUser?user_id=
+// Permission "users:suspend" required for this route
 pub async fn unsuspend_user_handler(
     req: HttpRequest,
     query: Query<QueryUserId>,
-    id: Identity
+    authInfo: AuthInfo,
 ) -> Result<HttpResponse, Error> {
 
     // debug!("Incoming request: {:?}", req);
     let user_id = query.user_id.clone();
 
-    let authInfo: AuthInfo = match id.identity() {
-        None => return Err(Error::from(noJwtError!())),
-        Some(jwt) => match decode_token(&jwt) {
-            Err(e) => return Err(Error::from(e)),
-            Ok(auth_info) => auth_info,
-        },
-    };
     info!("authInfo: {:?}", authInfo);
 
-    if authInfo.user_role != UserRole::PLATFORM_ADMIN {
-        return Err(Error::from(LoginError::CredentialsError(
-                    errJson!("Not an admin, can't unsuspend a user"))))
-    }
-
     let conn = AppState::databaseActor(&req)
                 .send(GetPool::Postgres)
                 .await??;
 
-    let user = setSuspended(&conn, user_id, false)
+    let user = unsuspendUser(&conn, &user_id, &authInfo.user_id)
         .map_err(Error::from)?;
 
-    debug!("user suspended: {:?}", user.email);
+    debug!("user unsuspended: {:?}", user.email);
+    audit(&req, AuditAction::Unsuspend)
+        .by(&authInfo)
+        .user(&user.id)
+        .record();
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
         .json(user))
 }
 
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearLockoutBody {
+    pub user_id: Option<String>,
+    /// also clear the lockout of a client IP
+    pub ip: Option<String>,
+}
+
+// POST /auth/lockout/clear
+// Permission "users:unlock" required for this route
+pub async fn clear_lockout_handler(
+    req: HttpRequest,
+    json: Json<ClearLockoutBody>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    if body.user_id.is_none() && body.ip.is_none() {
+        return Err(Error::from(LoginError::BadRequest(
+            errJson!("userId or ip required"))))
+    }
+
+    if let Some(user_id) = &body.user_id {
+        let conn = AppState::databaseActor(&req)
+                    .send(GetPool::Postgres)
+                    .await??;
+
+        let user = getUser(&conn, None, Some(user_id))
+            .map_err(Error::from)?;
+
+        clear_account_lockout(&req, &user.email);
+    }
+
+    if let Some(ip) = &body.ip {
+        clear_ip_lockout(&req, ip);
+    }
+
+    info!("login lockout cleared by {}: {:?}", authInfo.user_id, body);
+    let mut recorder = audit(&req, AuditAction::LockoutClear)
+        .actor(&authInfo.user_id)
+        .payload(json!({ "ip": body.ip }));
+    if let Some(user_id) = &body.user_id {
+        recorder = recorder.user(user_id);
+    }
+    recorder.record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "userId": body.user_id,
+            "ip": body.ip,
+            "cleared": true,
+        })))
+}
+
+
+#[actix_rt::test]
+async fn impersonation_tokens_cant_update_profiles() {
+    use actix_web::{test, http::StatusCode, dev::Payload, FromRequest};
+
+    let authInfo = AuthInfo {
+        user_id: String::from("u123"),
+        email: String::from("jack@black.com"),
+        user_role: UserRole::USER,
+        session_id: None,
+        impersonator_id: Some(String::from("uadmin")),
+    };
+    let req = test::TestRequest::post()
+        .uri("/auth/profile/update")
+        .to_http_request();
+    let id = Identity::from_request(&req, &mut Payload::None).await.unwrap();
+    let profile: UpdateUserProfile = serde_json::from_value(json!({
+        "id": "u123",
+        "firstName": "Jack",
+    })).unwrap();
+
+    // rejected before the database is used, or a session is issued
+    let err = update_profile_handler(req, Json(profile), id, authInfo).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+}
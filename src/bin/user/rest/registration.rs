@@ -13,7 +13,10 @@ use actix_identity::{Identity};
 
 use redis::RedisResult;
 
-use crate::email::EmailVerifyError;
+use crate::email::{
+    EmailVerifyError,
+    VerifiedEmailAction,
+};
 use crate::AppState;
 use crate::db::{
     createUser,
@@ -43,6 +46,11 @@ use crate::rpc::{
 use crate::notify_client::{
     NotifyMessage
 };
+use crate::auth::{
+    issue_token_pair,
+    refresh_cookie,
+};
+use crate::rest::send_verify_email;
 
 
 // POST /user/create
@@ -100,14 +108,22 @@ pub async fn create_user_handler_dev(
 
     debug!("new user created in db: {:?}", &user);
 
-    let jwt = crate::auth::create_token(
-        user.email.clone(),
-        user.id.clone(),
-        user.user_role.clone(),
-    )?;
+    // Email a link to verify the new user's email
+    send_verify_email(&req, &user).await?;
+
+    // Users can't login until they verify their email
+    // when EMAIL_VERIFICATION_BLOCKS includes "login"
+    if VerifiedEmailAction::Login.is_blocked() {
+        return Ok(HttpResponse::Ok()
+            .content_type("application_json")
+            .json(json!({
+                "user": user,
+                "emailVerificationRequired": true,
+            })))
+    }
 
     // Set JWT as HttpOnly cookie to pass to the client
-    id.remember(jwt);
+    let tokens = issue_token_pair(&req, &id, &user).await?;
 
     // // tell notify service to send welcome email
     // let sendgrid_response = AppState::notifyActor(&req)
@@ -116,8 +132,12 @@ pub async fn create_user_handler_dev(
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
+        .cookie(refresh_cookie(&tokens.refresh_token))
         .json(json!({
             "user": user,
+            "jwt": tokens.jwt,
+            "refreshToken": tokens.refresh_token,
+            "expiresIn": tokens.expires_in,
             // "sendgridResponse": sendgrid_response,
             "sendgridResponse": json!({
                 "verified": {
@@ -152,17 +172,6 @@ pub async fn create_user_handler_prod(
         .send(GetPool::Postgres)
         .await??;
 
-    // 2. Set verify_email email in redis cache
-    // let verify_email = VerifyEmail::from(&userForm.clone());
-    // let _redis_future = AppState::redisActor(&req)
-    //                     .do_send(RedisCommand::Setex(
-    //                         Setex {
-    //                             key: verify_email.email.clone(),
-    //                             ttl: 172_800, // 48hrs
-    //                             value: verify_email.email.clone(),
-    //                         }
-    //                     ));
-
     // // await all 2 futures to return concurrently, then send response
     // let (
     //     conn,
@@ -180,14 +189,22 @@ pub async fn create_user_handler_prod(
     )?;
     debug!("new user created in db: {:?}", &user);
 
-    let jwt = crate::auth::create_token(
-        user.email.clone(),
-        user.id.clone(),
-        user.user_role.clone(),
-    )?;
+    // 2. Email a link to verify the new user's email
+    send_verify_email(&req, &user).await?;
+
+    // Users can't login until they verify their email
+    // when EMAIL_VERIFICATION_BLOCKS includes "login"
+    if VerifiedEmailAction::Login.is_blocked() {
+        return Ok(HttpResponse::Ok()
+            .content_type("application_json")
+            .json(json!({
+                "user": user,
+                "emailVerificationRequired": true,
+            })))
+    }
 
     // Set JWT as HttpOnly cookie to pass to the client
-    id.remember(jwt);
+    let tokens = issue_token_pair(&req, &id, &user).await?;
 
     // Try tell the notify service about the new user
     // don't wait for it to return with do_send
@@ -201,8 +218,12 @@ pub async fn create_user_handler_prod(
 
     Ok(HttpResponse::Ok()
         .content_type("application_json")
+        .cookie(refresh_cookie(&tokens.refresh_token))
         .json(json!({
             "user": user,
+            "jwt": tokens.jwt,
+            "refreshToken": tokens.refresh_token,
+            "expiresIn": tokens.expires_in,
             // "sendgridResponse": sendgrid_response,
             "sendgridResponse": json!({
                 "verified": {
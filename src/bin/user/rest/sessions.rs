@@ -0,0 +1,192 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+
+use crate::auth::{
+    AuthInfo,
+    ListSessions,
+    RevokeSession,
+    RevokeSessions,
+    RevokeUserTokens,
+    SessionRecord,
+};
+use crate::db::{
+    GetPool,
+    getUser,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Where a user is logged in, see `auth::session`.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    pub session: SessionRecord,
+    /// the session making this request
+    pub current: bool,
+}
+
+// GET /auth/sessions
+pub async fn list_sessions_handler(
+    req: HttpRequest,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let sessions = AppState::databaseActor(&req)
+                .send(ListSessions(authInfo.user_id.clone()))
+                .await??
+                .into_iter()
+                .map(|session| SessionResponse {
+                    current: Some(&session.id) == authInfo.session_id.as_ref(),
+                    session: session,
+                })
+                .collect::<Vec<SessionResponse>>();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "sessions": sessions,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeSessionBody {
+    pub session_id: String,
+}
+
+// POST /auth/sessions/revoke
+// Signs out one of the user's sessions, e.g. a lost phone
+pub async fn revoke_session_handler(
+    req: HttpRequest,
+    json: Json<RevokeSessionBody>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let body = json.into_inner();
+
+    AppState::databaseActor(&req)
+        .send(RevokeSession {
+            user_id: authInfo.user_id.clone(),
+            session_id: body.session_id.clone(),
+        })
+        .await??;
+
+    audit(&req, AuditAction::SessionRevoke)
+        .by(&authInfo)
+        .payload(json!({ "sessionId": body.session_id }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Session revoked",
+            "sessionId": body.session_id,
+            "current": Some(&body.session_id) == authInfo.session_id.as_ref(),
+        })))
+}
+
+// POST /auth/sessions/revokeOthers
+// Signs out every session except the one making this request
+pub async fn revoke_other_sessions_handler(
+    req: HttpRequest,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let revoked = AppState::databaseActor(&req)
+                .send(RevokeSessions {
+                    user_id: authInfo.user_id.clone(),
+                    keep: authInfo.session_id.clone(),
+                })
+                .await??;
+
+    audit(&req, AuditAction::SessionRevoke)
+        .by(&authInfo)
+        .payload(json!({ "kept": authInfo.session_id, "revoked": revoked }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Other sessions revoked",
+            "revoked": revoked,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminRevokeSessionsBody {
+    pub user_id: String,
+    /// Revoke only this session, otherwise all of the user's sessions
+    pub session_id: Option<String>,
+}
+
+// POST /auth/admin/sessions/revoke
+// Permission "users:revoke_sessions" required for this route
+pub async fn admin_revoke_sessions_handler(
+    req: HttpRequest,
+    json: Json<AdminRevokeSessionsBody>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user = getUser(&conn, None, Some(&body.user_id))
+        .map_err(Error::from)?;
+
+    let revoked = match &body.session_id {
+        Some(session_id) => {
+            AppState::databaseActor(&req)
+                .send(RevokeSession {
+                    user_id: user.id.clone(),
+                    session_id: session_id.clone(),
+                })
+                .await??;
+            1
+        },
+        None => {
+            let revoked = AppState::databaseActor(&req)
+                .send(RevokeSessions {
+                    user_id: user.id.clone(),
+                    keep: None,
+                })
+                .await??;
+            // Also ends sessions started before they were recorded
+            AppState::databaseActor(&req)
+                .send(RevokeUserTokens(user.id.clone()))
+                .await??;
+            revoked
+        },
+    };
+
+    info!("sessions revoked by {}: {:?}", authInfo.user_id, body);
+    audit(&req, AuditAction::SessionRevoke)
+        .by(&authInfo)
+        .user(&user.id)
+        .payload(json!({ "sessionId": body.session_id, "revoked": revoked }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Sessions revoked",
+            "userId": user.id,
+            "revoked": revoked,
+        })))
+}
@@ -0,0 +1,116 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use actix_identity::{Identity};
+
+use crate::db::{
+    getUser,
+    GetPool,
+    checkSuspension,
+};
+use crate::auth::{
+    RotateRefreshToken,
+    RefreshTokenError,
+    RefreshTokenRecord,
+    RevokeSession,
+    SessionClient,
+    current_refresh_token,
+    refresh_cookie,
+    access_token_ttl,
+};
+use crate::models::{
+    User,
+    LoginError,
+    ErrJson,
+};
+use crate::rest::destroy_and_blacklist_jwt;
+use crate::AppState;
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshTokenBody {
+    pub refresh_token: Option<String>,
+}
+
+// POST /token/refresh
+// Rotates the refresh token (body or cookie), and issues a new access JWT
+pub async fn refresh_token_handler(
+    req: HttpRequest,
+    id: Identity,
+    body: Option<Json<RefreshTokenBody>>,
+) -> Result<HttpResponse, Error> {
+
+    // Mobile clients send the refresh token in the body,
+    // browsers send it in the HttpOnly cookie.
+    let refresh_token = body
+        .and_then(|b| b.into_inner().refresh_token)
+        .or(current_refresh_token(&req))
+        .ok_or(Error::from(RefreshTokenError::Invalid))?;
+
+    let (record, new_refresh_token) = AppState::databaseActor(&req)
+                .send(RotateRefreshToken {
+                    refresh_token: refresh_token,
+                    client: SessionClient::from_request(&req),
+                })
+                .await??;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user: User = getUser(&conn, None, Some(&record.user_id))
+        .map_err(Error::from)?;
+
+    // The presented token may have come from the body rather than the cookie,
+    // so end its family here: destroy_and_blacklist_jwt only ends the cookie's
+    let user = match checkSuspension(&conn, user) {
+        Ok(user) => user,
+        Err(e) => {
+            revoke_family(&req, &record);
+            let _ = destroy_and_blacklist_jwt(req, id);
+            return Err(Error::from(e))
+        }
+    };
+
+    if user.is_deleted {
+        revoke_family(&req, &record);
+        let _ = destroy_and_blacklist_jwt(req, id);
+        return Err(
+            LoginError::Suspended(ErrJson::new("User is deleted"))
+        ).map_err(Error::from)
+    }
+
+    let jwt = crate::auth::create_token(
+        user.email.clone(),
+        user.id.clone(),
+        user.user_role.clone(),
+        record.family_id.clone(),
+    ).map_err(Error::from)?;
+
+    // Set JWT as HttpOnly cookie to pass to the client
+    id.remember(jwt.clone());
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .cookie(refresh_cookie(&new_refresh_token))
+        .json(json!({
+            "jwt": jwt,
+            "refreshToken": new_refresh_token,
+            "expiresIn": access_token_ttl().num_seconds(),
+        })
+    ))
+}
+
+/// Ends the session the refresh token belongs to, including the token
+/// it was just rotated into
+fn revoke_family(req: &HttpRequest, record: &RefreshTokenRecord) {
+    AppState::databaseActor(req)
+        .do_send(RevokeSession {
+            user_id: record.user_id.clone(),
+            session_id: record.family_id.clone(),
+        });
+}
@@ -0,0 +1,149 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use chrono::{Local, Duration};
+
+use crate::db::{
+    GetPool,
+    getUser,
+    setEmailVerified,
+};
+use crate::email::{
+    EmailVerifyError,
+    CreateEmailVerification,
+    RedeemEmailVerification,
+    resend_count_key,
+    EMAIL_VERIFY_TTL_HOURS,
+    MAX_RESENDS_PER_EMAIL,
+    RESEND_WINDOW_SECONDS,
+};
+use crate::models::User;
+use crate::notify_client::NotifyMessage;
+use crate::redis_client::{RedisCommand, Incr};
+use crate::AppState;
+
+/// Email verification:
+/// 1. /user/create emails the new user a verification link
+/// 2. /verify/email marks the email as verified, with the token from the link
+/// 3. /verify/email/resend sends a new link, if the first one expired or got lost
+///
+/// See `email::verify_email`.
+
+/// Emails a verification link to the user.
+/// Sending isn't awaited, so a slow notify service doesn't hold up the response.
+pub async fn send_verify_email(req: &HttpRequest, user: &User) -> Result<(), Error> {
+    let token = AppState::databaseActor(req)
+                .send(CreateEmailVerification {
+                    user_id: user.id.clone(),
+                    email: user.email.clone(),
+                })
+                .await??;
+    AppState::notifyActor(req)
+        .do_send(NotifyMessage::SendVerifyEmail(
+            user.email.clone(),
+            token,
+            Local::now().naive_utc() + Duration::hours(EMAIL_VERIFY_TTL_HOURS),
+        ));
+    Ok(())
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyEmailForm {
+    pub token: String,
+}
+
+// POST /verify/email
+pub async fn verify_email_handler(
+    req: HttpRequest,
+    json: Json<VerifyEmailForm>,
+) -> Result<HttpResponse, Error> {
+
+    let record = AppState::databaseActor(&req)
+                .send(RedeemEmailVerification(json.into_inner().token))
+                .await??;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user: User = getUser(&conn, None, Some(&record.user_id))
+        .map_err(|_| EmailVerifyError::VerificationError(
+            errJson!("Email verification link is invalid or has expired")
+        ))?;
+
+    if !record.matches_user(&user) {
+        return Err(Error::from(EmailVerifyError::VerificationError(
+            errJson!("Email verification link is for a different email address")
+        )))
+    }
+
+    let user = if user.email_verified {
+        user
+    } else {
+        setEmailVerified(&conn, user.email.clone(), true)?
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "Email verified",
+            "email": user.email,
+            "emailVerified": user.email_verified,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResendVerifyEmailForm {
+    pub email: String,
+}
+
+// POST /verify/email/resend
+// Rate limited per IP by the RateLimit middleware, and per email here
+pub async fn resend_verify_email_handler(
+    req: HttpRequest,
+    json: Json<ResendVerifyEmailForm>,
+) -> Result<HttpResponse, Error> {
+
+    let form = json.into_inner();
+
+    // Counted before looking up the user, so unknown emails
+    // are throttled the same way
+    let resends = AppState::redisActor(&req)
+                .send(RedisCommand::Incr(Incr {
+                    key: resend_count_key(&form.email),
+                    ttl: RESEND_WINDOW_SECONDS as i32,
+                }))
+                .await??
+                .parse::<i64>()
+                .unwrap_or(0);
+
+    if resends <= MAX_RESENDS_PER_EMAIL {
+        let conn = AppState::databaseActor(&req)
+                    .send(GetPool::Postgres)
+                    .await??;
+
+        match getUser(&conn, Some(&form.email), None) {
+            Ok(user) if !user.email_verified && !user.is_suspended && !user.is_deleted => {
+                send_verify_email(&req, &user).await?;
+            },
+            _ => {
+                debug!("verification email not sent for: {}", form.email);
+            },
+        }
+    } else {
+        debug!("verification email resend throttled for: {}", form.email);
+    }
+
+    // Same response either way, so this doesn't show
+    // which emails have accounts
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "status": "If this email has an unverified account, a verification link has been sent.",
+        })))
+}
@@ -0,0 +1,404 @@
+use actix_web::{
+    web::Json,
+    Error,
+    HttpRequest,
+    HttpResponse,
+};
+use actix_identity::{Identity};
+
+use crate::db::{
+    GetPool,
+    getUser,
+    getWebauthnCredentials,
+    getWebauthnCredentialById,
+    insertWebauthnCredential,
+    updateWebauthnSignCount,
+    deleteWebauthnCredential,
+    checkSuspension,
+};
+use crate::auth::{
+    AuthInfo,
+    issue_token_pair,
+    // WebAuthn
+    RelyingParty,
+    ChallengeKind,
+    WebauthnChallenge,
+    WebauthnError,
+    RegisterCredential,
+    AssertionCredential,
+    CHALLENGE_TTL_SECONDS,
+    webauthn_challenge_key,
+    creation_options,
+    request_options,
+    fake_credential_ids,
+    verify_registration,
+    verify_assertion,
+    b64url,
+    from_b64url,
+};
+use crate::models::{
+    User,
+    LoginError,
+    ErrJson,
+    WebauthnCredential,
+    NewWebauthnCredential,
+};
+use crate::redis_client::{
+    RedisCommand, Setex,
+};
+use crate::rest::login_response;
+use crate::email::{
+    VerifiedEmailAction,
+    check_email_verified,
+    require_verified_email,
+};
+use crate::audit::audit;
+use crate::models::AuditAction;
+use crate::AppState;
+
+/// Passkey registration (logged in):
+/// 1. /auth/webauthn/register/start returns options for navigator.credentials.create()
+/// 2. /auth/webauthn/register/finish verifies and stores the new credential
+///
+/// Passkey login:
+/// 1. /login/webauthn/start returns options for navigator.credentials.get()
+/// 2. /login/webauthn/finish verifies the assertion, and logs the user in
+///    with the same JWT and refresh token as /login
+///
+/// See `auth::webauthn` for the protocol.
+
+async fn store_challenge(
+    req: &HttpRequest,
+    challenge: &WebauthnChallenge,
+) -> Result<String, Error> {
+
+    let challenge_id = uuid::Uuid::new_v4().to_string();
+    let value = serde_json::to_string(challenge)
+        .map_err(|e| WebauthnError::Internal(e.to_string()))?;
+
+    AppState::redisActor(req)
+        .send(RedisCommand::Setex(
+            Setex {
+                key: webauthn_challenge_key(&challenge_id),
+                ttl: CHALLENGE_TTL_SECONDS,
+                value: value,
+            }
+        ))
+        .await??;
+
+    Ok(challenge_id)
+}
+
+/// Challenges are deleted as they are read, so each can only be used once
+async fn take_challenge(
+    req: &HttpRequest,
+    challenge_id: &str,
+) -> Result<WebauthnChallenge, Error> {
+
+    let value = AppState::redisActor(req)
+        .send(RedisCommand::GetDel(webauthn_challenge_key(challenge_id)))
+        .await?
+        .map_err(|_| WebauthnError::ChallengeExpired)?;
+
+    serde_json::from_str::<WebauthnChallenge>(&value)
+        .map_err(|_| Error::from(WebauthnError::ChallengeExpired))
+}
+
+
+// POST /auth/webauthn/register/start
+pub async fn webauthn_register_start_handler(
+    req: HttpRequest,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    require_verified_email(&conn, &authInfo.user_id, VerifiedEmailAction::MfaEnroll)?;
+
+    // Stop the authenticator registering a second credential for this user
+    let existing_ids = getWebauthnCredentials(&conn, &authInfo.user_id)?
+        .into_iter()
+        .map(|c| c.credential_id)
+        .collect::<Vec<String>>();
+
+    let challenge = WebauthnChallenge::new(
+        ChallengeKind::Register,
+        Some(authInfo.user_id.clone()),
+    )?;
+    let challenge_id = store_challenge(&req, &challenge).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "challengeId": challenge_id,
+            "publicKey": creation_options(
+                &RelyingParty::from_env(),
+                &challenge,
+                &authInfo.user_id,
+                &authInfo.email,
+                existing_ids,
+            ),
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnRegisterFinishBody {
+    pub challenge_id: String,
+    /// user's label for the passkey, e.g. "iPhone"
+    pub name: Option<String>,
+    pub credential: RegisterCredential,
+}
+
+// POST /auth/webauthn/register/finish
+pub async fn webauthn_register_finish_handler(
+    req: HttpRequest,
+    json: Json<WebauthnRegisterFinishBody>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let body = json.into_inner();
+    let challenge = take_challenge(&req, &body.challenge_id).await?;
+
+    if challenge.user_id.as_ref() != Some(&authInfo.user_id) {
+        return Err(Error::from(WebauthnError::ChallengeExpired))
+    }
+
+    let verified = verify_registration(
+        &RelyingParty::from_env(),
+        &challenge,
+        &body.credential,
+    )?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let credential = insertWebauthnCredential(&conn, NewWebauthnCredential {
+        id: format!("wac_{}", uuid::Uuid::new_v4()),
+        user_id: authInfo.user_id.clone(),
+        credential_id: verified.credential_id,
+        public_key: verified.public_key,
+        sign_count: verified.sign_count as i64,
+        name: body.name,
+    })?;
+
+    info!("passkey registered for user: {}", authInfo.user_id);
+    audit(&req, AuditAction::PasskeyRegister)
+        .by(&authInfo)
+        .payload(json!({ "credentialId": credential.id, "name": credential.name }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(credential))
+}
+
+
+// GET /auth/webauthn/credentials
+pub async fn get_webauthn_credentials_handler(
+    req: HttpRequest,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let credentials: Vec<WebauthnCredential> = getWebauthnCredentials(&conn, &authInfo.user_id)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(credentials))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteWebauthnCredentialBody {
+    pub id: String,
+}
+
+// POST /auth/webauthn/credentials/delete
+pub async fn delete_webauthn_credential_handler(
+    req: HttpRequest,
+    json: Json<DeleteWebauthnCredentialBody>,
+    authInfo: AuthInfo,
+) -> Result<HttpResponse, Error> {
+
+    authInfo.forbid_impersonation()?;
+
+    let body = json.into_inner();
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    if !deleteWebauthnCredential(&conn, &authInfo.user_id, &body.id)? {
+        return Err(Error::from(LoginError::BadRequest(
+            errJson!(format!("No passkey with id: {}", body.id)))))
+    }
+
+    audit(&req, AuditAction::PasskeyDelete)
+        .by(&authInfo)
+        .payload(json!({ "credentialId": body.id }))
+        .record();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "id": body.id,
+            "deleted": true,
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebauthnLoginStartBody {
+    /// Leave out to let the user pick a discoverable passkey
+    pub email: Option<String>,
+}
+
+// POST /login/webauthn/start
+pub async fn webauthn_login_start_handler(
+    req: HttpRequest,
+    json: Json<WebauthnLoginStartBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let user: Option<User> = match &body.email {
+        Some(email) => getUser(&conn, Some(email), None).ok(),
+        None => None,
+    };
+
+    let mut allow_ids = match &user {
+        Some(u) => getWebauthnCredentials(&conn, &u.id)?
+            .into_iter()
+            .map(|c| c.credential_id)
+            .collect::<Vec<String>>(),
+        None => vec![],
+    };
+    // Unknown emails, and users without passkeys, get made up credential ids,
+    // so the allow list doesn't reveal who has an account or passkeys
+    if let (Some(email), true) = (&body.email, allow_ids.is_empty()) {
+        allow_ids = fake_credential_ids(email);
+    }
+
+    let challenge = WebauthnChallenge::new(
+        ChallengeKind::Authenticate,
+        user.map(|u| u.id),
+    )?;
+    let challenge_id = store_challenge(&req, &challenge).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application_json")
+        .json(json!({
+            "challengeId": challenge_id,
+            "publicKey": request_options(
+                &RelyingParty::from_env(),
+                &challenge,
+                allow_ids,
+            ),
+        })))
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnLoginFinishBody {
+    pub challenge_id: String,
+    pub credential: AssertionCredential,
+}
+
+// POST /login/webauthn/finish
+pub async fn webauthn_login_finish_handler(
+    req: HttpRequest,
+    id: Identity,
+    json: Json<WebauthnLoginFinishBody>,
+) -> Result<HttpResponse, Error> {
+
+    let body = json.into_inner();
+    let challenge = take_challenge(&req, &body.challenge_id).await?;
+
+    let conn = AppState::databaseActor(&req)
+                .send(GetPool::Postgres)
+                .await??;
+
+    let credential_id = b64url(&from_b64url(&body.credential.raw_id)?);
+    let stored = getWebauthnCredentialById(&conn, &credential_id)?
+        .ok_or(Error::from(WebauthnError::UnknownCredential))?;
+
+    // If the user gave their email, the passkey must be theirs
+    if let Some(user_id) = &challenge.user_id {
+        if *user_id != stored.user_id {
+            return Err(Error::from(WebauthnError::UnknownCredential))
+        }
+    }
+
+    let sign_count = verify_assertion(
+        &RelyingParty::from_env(),
+        &challenge,
+        &body.credential,
+        &stored.public_key,
+        stored.sign_count,
+    )?;
+    updateWebauthnSignCount(&conn, &stored.id, sign_count as i64)?;
+
+    let user: User = getUser(&conn, None, Some(&stored.user_id))
+        .map_err(Error::from)?;
+
+    let user = checkSuspension(&conn, user)?;
+
+    if user.is_deleted {
+        return Err(
+            LoginError::Suspended(ErrJson::new("User is deleted"))
+        ).map_err(Error::from)
+    }
+
+    check_email_verified(&user, VerifiedEmailAction::Login)?;
+
+    // Passkeys require user verification (PIN or biometrics),
+    // so they also satisfy 2FA.
+    let tokens = issue_token_pair(&req, &id, &user).await?;
+
+    audit(&req, AuditAction::Login)
+        .actor(&user.id)
+        .user(&user.id)
+        .payload(json!({ "method": "passkey", "credentialId": stored.id }))
+        .record();
+
+    Ok(login_response(user, tokens))
+}
+
+
+#[actix_rt::test]
+async fn impersonation_tokens_cant_register_passkeys() {
+    use actix_web::{test, http::StatusCode};
+    use crate::models::auth::UserRole;
+
+    let authInfo = AuthInfo {
+        user_id: String::from("u123"),
+        email: String::from("jack@black.com"),
+        user_role: UserRole::USER,
+        session_id: None,
+        impersonator_id: Some(String::from("uadmin")),
+    };
+    let req = test::TestRequest::post()
+        .uri("/auth/webauthn/register/start")
+        .to_http_request();
+
+    // rejected before the database is used
+    let err = webauthn_register_start_handler(req, authInfo).await.unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+}
@@ -246,6 +246,32 @@ pub async fn rpc_read_payout_method(
 }
 
 
+/// Everything the payment service holds about the user,
+/// for data exports (see `data_export`)
+pub async fn rpc_export_user_payment_data(
+    client: &actix_web::client::Client,
+    user_id: &str,
+) -> Result<serde_json::Value, Error> {
+
+    let url = format!("/user/export?user_id={}", user_id);
+
+    let mut response = client.get(Endpoint::Payment(&url).as_url())
+                    .send()
+                    .await?;
+
+    let bytes = response.body().limit(10_485_760).await?;
+
+    if !response.status().is_success() {
+        return Err(Error::from(RpcError::Payment(errJson!(
+            format!("{}: {}", response.status(), String::from_utf8_lossy(&bytes))
+        ))))
+    }
+
+    serde_json::from_slice::<serde_json::Value>(&bytes)
+        .map_err(|e| Error::from(RpcError::Payment(errJson!(e))))
+}
+
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeleteUserShoppingResponse {
     pub success: bool
@@ -344,3 +370,118 @@ pub async fn rpc_send_password_reset_email(
     response.json().await
         .map_err(|e| NotifyActixError::UserCreated(errJson!(e)))
 }
+
+
+pub async fn rpc_send_password_changed_email(
+    client: &actix_web::client::Client,
+    email: &str,
+) -> Result<serde_json::Value, NotifyActixError> {
+
+    let route = "/email/password-changed";
+    debug!("requesting endpoint: {}", route);
+
+    let mut response = client
+                    .post(Endpoint::Notify(&route).as_url())
+                    .send_json(&json!({
+                        "email": email,
+                    }))
+                    .await
+                    .map_err(|e| NotifyActixError::PasswordChangedEmail(errJson!(e)))?;
+
+    response.json().await
+        .map_err(|e| NotifyActixError::PasswordChangedEmail(errJson!(e)))
+}
+
+
+
+pub async fn rpc_send_magic_link_email(
+    client: &actix_web::client::Client,
+    email: &str,
+    login_token: &str,
+    login_code: &str,
+    expires_at: &chrono::NaiveDateTime,
+) -> Result<serde_json::Value, NotifyActixError> {
+
+    let route = "/email/magic-link";
+    debug!("requesting endpoint: {}", route);
+
+    let expires_at_rpc = expires_at
+                            .format("%Y-%m-%dT%H:%M:%S")
+                            .to_string();
+
+    let mut response = client
+                    .post(Endpoint::Notify(&route).as_url())
+                    .send_json(&json!({
+                        "email": email,
+                        "loginToken": login_token,
+                        "loginCode": login_code,
+                        "expiresAt": expires_at_rpc
+                    }))
+                    .await
+                    .map_err(|e| NotifyActixError::MagicLinkEmail(errJson!(e)))?;
+
+    response.json().await
+        .map_err(|e| NotifyActixError::MagicLinkEmail(errJson!(e)))
+}
+
+
+pub async fn rpc_send_verify_email(
+    client: &actix_web::client::Client,
+    email: &str,
+    verify_token: &str,
+    expires_at: &chrono::NaiveDateTime,
+) -> Result<serde_json::Value, NotifyActixError> {
+
+    let route = "/email/verify";
+    debug!("requesting endpoint: {}", route);
+
+    let expires_at_rpc = expires_at
+                            .format("%Y-%m-%dT%H:%M:%S")
+                            .to_string();
+
+    let mut response = client
+                    .post(Endpoint::Notify(&route).as_url())
+                    .send_json(&json!({
+                        "email": email,
+                        "verifyToken": verify_token,
+                        "expiresAt": expires_at_rpc
+                    }))
+                    .await
+                    .map_err(|e| NotifyActixError::VerifyEmail(errJson!(e)))?;
+
+    response.json().await
+        .map_err(|e| NotifyActixError::VerifyEmail(errJson!(e)))
+}
+
+
+pub async fn rpc_send_email_change_emails(
+    client: &actix_web::client::Client,
+    old_email: &str,
+    new_email: &str,
+    confirm_token: &str,
+    revert_token: &str,
+    expires_at: &chrono::NaiveDateTime,
+) -> Result<serde_json::Value, NotifyActixError> {
+
+    let route = "/email/email-change";
+    debug!("requesting endpoint: {}", route);
+
+    let expires_at_rpc = expires_at
+                            .format("%Y-%m-%dT%H:%M:%S")
+                            .to_string();
+
+    let mut response = client
+                    .post(Endpoint::Notify(&route).as_url())
+                    .send_json(&json!({
+                        "oldEmail": old_email,
+                        "newEmail": new_email,
+                        "confirmToken": confirm_token,
+                        "revertToken": revert_token,
+                        "expiresAt": expires_at_rpc
+                    }))
+                    .await
+                    .map_err(|e| NotifyActixError::EmailChangeEmail(errJson!(e)))?;
+
+    response.json().await
+        .map_err(|e| NotifyActixError::EmailChangeEmail(errJson!(e)))
+}
@@ -1,3 +1,95 @@
+table! {
+    account_deletions (id) {
+        id -> Text,
+        user_id -> Text,
+        original_email -> Nullable<Text>,
+        created_at -> Timestamp,
+        erase_after -> Timestamp,
+        restored_at -> Nullable<Timestamp>,
+        restored_by -> Nullable<Text>,
+        erased_at -> Nullable<Timestamp>,
+        original_email_identity -> Nullable<Text>,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Text,
+        actor_id -> Nullable<Text>,
+        user_id -> Nullable<Text>,
+        action -> Text,
+        outcome -> Text,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        payload -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    impersonations (id) {
+        id -> Text,
+        admin_id -> Text,
+        user_id -> Text,
+        reason -> Nullable<Text>,
+        jti -> Text,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    mfa_recovery_codes (id) {
+        id -> Text,
+        user_id -> Text,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    pending_email_changes (id) {
+        id -> Text,
+        user_id -> Text,
+        old_email -> Text,
+        new_email -> Text,
+        confirm_token_hash -> Text,
+        revert_token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        confirmed_at -> Nullable<Timestamp>,
+        reverted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    suspensions (id) {
+        id -> Text,
+        user_id -> Text,
+        reason -> Text,
+        note -> Nullable<Text>,
+        admin_id -> Text,
+        created_at -> Timestamp,
+        until -> Nullable<Timestamp>,
+        lifted_at -> Nullable<Timestamp>,
+        lifted_by -> Nullable<Text>,
+    }
+}
+
+table! {
+    user_totp (user_id) {
+        user_id -> Text,
+        secret -> Text,
+        enabled -> Bool,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamp,
+        enabled_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     users (id) {
         id -> Text,
@@ -11,9 +103,37 @@ table! {
         is_suspended -> Bool,
         is_deleted -> Bool,
         user_role -> Nullable<Text>,
+        email_identity -> Nullable<Text>,
+    }
+}
+
+table! {
+    webauthn_credentials (id) {
+        id -> Text,
+        user_id -> Text,
+        credential_id -> Text,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        name -> Nullable<Text>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(mfa_recovery_codes -> users (user_id));
+joinable!(pending_email_changes -> users (user_id));
+joinable!(suspensions -> users (user_id));
+joinable!(user_totp -> users (user_id));
+joinable!(webauthn_credentials -> users (user_id));
+
 allow_tables_to_appear_in_same_query!(
+    account_deletions,
+    audit_events,
+    impersonations,
+    mfa_recovery_codes,
+    pending_email_changes,
+    suspensions,
+    user_totp,
     users,
+    webauthn_credentials,
 );